@@ -0,0 +1,536 @@
+extern crate proc_macro as original_proc_macro;
+
+use syn::parse::Result as ParseResult;
+use syn::parse::Error as ParseError;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use proc_macro2::{TokenStream, TokenTree};
+use quote::quote;
+
+/// Generates the change and signal enums for a struct, and implements `Changeable` for it.
+///
+/// Each field that has a `#[change(ChangeType)]` attribute gets a variant in the change enum and
+/// the signal enum, named after the field (or `_0`, `_1`, etc. for tuple structs). Fields without
+/// the attribute are not part of the change type. A field can also have an
+/// `#[on_changed(path::to::function)]` attribute, which is called with `&mut self` just before a
+/// change is applied to that field, the same as `on_changed` in `impl_changeable_struct!`.
+///
+/// The enums are called `<Struct>Change` and `<Struct>Signal` by default. They can be renamed with
+/// a `#[change(MyChange[MySignal])]` attribute on the struct.
+#[proc_macro_derive(Changeable, attributes(change, on_changed))]
+pub fn derive_changeable(tokens: original_proc_macro::TokenStream) -> original_proc_macro::TokenStream {
+	derive_changeable2(tokens.into()).into()
+}
+fn derive_changeable2(tokens: TokenStream) -> TokenStream {
+	match syn::parse2(tokens).and_then(|input| ChangeStruct::from_input(&input)) {
+		Ok(change_struct) => change_struct.generate_changeable(),
+		Err(err) => err.to_compile_error(),
+	}
+}
+
+/// Implements `Revertable` for a struct. This uses the same attributes as the `Changeable` derive,
+/// which must also be derived for the struct.
+#[proc_macro_derive(Revertable, attributes(change, on_changed))]
+pub fn derive_revertable(tokens: original_proc_macro::TokenStream) -> original_proc_macro::TokenStream {
+	derive_revertable2(tokens.into()).into()
+}
+fn derive_revertable2(tokens: TokenStream) -> TokenStream {
+	match syn::parse2(tokens).and_then(|input| ChangeStruct::from_input(&input)) {
+		Ok(change_struct) => change_struct.generate_revertable(),
+		Err(err) => err.to_compile_error(),
+	}
+}
+
+/// Implements `Transform` for the change enum of a struct that derives `Changeable`, using the same
+/// attributes. The change type of each field must implement `Transform` as well.
+#[proc_macro_derive(Transform, attributes(change, on_changed))]
+pub fn derive_transform(tokens: original_proc_macro::TokenStream) -> original_proc_macro::TokenStream {
+	derive_transform2(tokens.into()).into()
+}
+fn derive_transform2(tokens: TokenStream) -> TokenStream {
+	match syn::parse2(tokens).and_then(|input| ChangeStruct::from_input(&input)) {
+		Ok(change_struct) => change_struct.generate_transform(),
+		Err(err) => err.to_compile_error(),
+	}
+}
+
+/// Implements `Diff` for a struct that derives `Changeable`, using the same attributes. The type of
+/// each field must implement `Diff` for its change type as well.
+#[proc_macro_derive(Diff, attributes(change, on_changed))]
+pub fn derive_diff(tokens: original_proc_macro::TokenStream) -> original_proc_macro::TokenStream {
+	derive_diff2(tokens.into()).into()
+}
+fn derive_diff2(tokens: TokenStream) -> TokenStream {
+	match syn::parse2(tokens).and_then(|input| ChangeStruct::from_input(&input)) {
+		Ok(change_struct) => change_struct.generate_diff(),
+		Err(err) => err.to_compile_error(),
+	}
+}
+
+/// The `#[change(MyChange[MySignal])]` attribute on a struct.
+struct ChangeNames {
+	change_name: syn::Ident,
+	signal_name: syn::Ident,
+}
+
+impl Parse for ChangeNames {
+	fn parse(input: ParseStream) -> ParseResult<ChangeNames> {
+		let content;
+		syn::parenthesized!(content in input);
+		let change_name: syn::Ident = content.parse()?;
+		let signal_content;
+		syn::bracketed!(signal_content in content);
+		let signal_name: syn::Ident = signal_content.parse()?;
+		Ok(ChangeNames { change_name, signal_name })
+	}
+}
+
+/// The `#[change(ChangeType)]` attribute on a field.
+struct FieldChangeType(syn::Type);
+
+impl Parse for FieldChangeType {
+	fn parse(input: ParseStream) -> ParseResult<FieldChangeType> {
+		let content;
+		syn::parenthesized!(content in input);
+		Ok(FieldChangeType(content.parse()?))
+	}
+}
+
+/// The `#[on_changed(path::to::function)]` attribute on a field.
+struct OnChanged(syn::Path);
+
+impl Parse for OnChanged {
+	fn parse(input: ParseStream) -> ParseResult<OnChanged> {
+		let content;
+		syn::parenthesized!(content in input);
+		Ok(OnChanged(content.parse()?))
+	}
+}
+
+fn is_attr(attr: &syn::Attribute, name: &str) -> bool {
+	attr.path.segments.len() == 1 && attr.path.segments[0].ident == name
+}
+
+/// Returns true if the ident appears anywhere in the given tokens.
+fn tokens_mention(tokens: TokenStream, ident: &syn::Ident) -> bool {
+	tokens.into_iter().any(|tok| {
+		match tok {
+			TokenTree::Ident(ref tok_ident) => tok_ident == ident,
+			TokenTree::Group(group) => tokens_mention(group.stream(), ident),
+			_ => false,
+		}
+	})
+}
+
+struct ChangeField {
+	/// How to access the field from self.
+	member: syn::Member,
+	/// The name of the change and signal variants for this field.
+	variant: syn::Ident,
+	ty: syn::Type,
+	change_type: syn::Type,
+	on_changed: Option<syn::Path>,
+	/// Doc comments, which are copied to the variants.
+	docs: Vec<syn::Attribute>,
+}
+
+struct ChangeStruct {
+	vis: syn::Visibility,
+	ident: syn::Ident,
+	generics: syn::Generics,
+	change_name: syn::Ident,
+	signal_name: syn::Ident,
+	fields: Vec<ChangeField>,
+}
+
+impl ChangeStruct {
+	fn from_input(input: &syn::DeriveInput) -> ParseResult<ChangeStruct> {
+		let data_struct = match input.data {
+			syn::Data::Struct(ref data_struct) => data_struct,
+			_ => return Err(ParseError::new(input.ident.span(), "Changeable can only be derived for structs, use impl_changeable_enum! for enums")),
+		};
+		
+		let mut change_name = syn::Ident::new(&format!("{}Change", input.ident), input.ident.span());
+		let mut signal_name = syn::Ident::new(&format!("{}Signal", input.ident), input.ident.span());
+		for attr in &input.attrs {
+			if is_attr(attr, "change") {
+				let names: ChangeNames = syn::parse2(attr.tts.clone())?;
+				change_name = names.change_name;
+				signal_name = names.signal_name;
+			}
+		}
+		
+		let mut fields = vec![];
+		for (index, field) in data_struct.fields.iter().enumerate() {
+			let mut change_type = None;
+			let mut on_changed = None;
+			let mut docs = vec![];
+			for attr in &field.attrs {
+				if is_attr(attr, "change") {
+					let FieldChangeType(ty) = syn::parse2(attr.tts.clone())?;
+					change_type = Some(ty);
+				} else if is_attr(attr, "on_changed") {
+					let OnChanged(path) = syn::parse2(attr.tts.clone())?;
+					on_changed = Some(path);
+				} else if is_attr(attr, "doc") {
+					docs.push(attr.clone());
+				}
+			}
+			
+			let change_type = match change_type {
+				Some(change_type) => change_type,
+				None if on_changed.is_some() => {
+					return Err(ParseError::new(field.span(), "on_changed requires a #[change(ChangeType)] attribute on the same field"));
+				}
+				None => continue,
+			};
+			
+			let (member, variant) = match field.ident {
+				Some(ref ident) => (syn::Member::Named(ident.clone()), ident.clone()),
+				None => (
+					syn::Member::Unnamed(syn::Index::from(index)),
+					syn::Ident::new(&format!("_{}", index), field.span()),
+				),
+			};
+			
+			fields.push(ChangeField {
+				member,
+				variant,
+				ty: field.ty.clone(),
+				change_type,
+				on_changed,
+				docs,
+			});
+		}
+		
+		Ok(ChangeStruct {
+			vis: input.vis.clone(),
+			ident: input.ident.clone(),
+			generics: input.generics.clone(),
+			change_name,
+			signal_name,
+			fields,
+		})
+	}
+	
+	/// The generics for the change and signal enums. Only the struct's generic parameters that are
+	/// used by the change types are kept, otherwise the enums would have unused parameters.
+	fn enum_generics(&self) -> syn::Generics {
+		let change_types: Vec<TokenStream> = self.fields.iter().map(|field| {
+			let change_type = &field.change_type;
+			quote!(#change_type)
+		}).collect();
+		let is_used = |ident: &syn::Ident| change_types.iter().any(|tokens| tokens_mention(tokens.clone(), ident));
+		
+		let mut unused_idents = vec![];
+		let mut generics = self.generics.clone();
+		generics.params = self.generics.params.iter().filter(|param| {
+			let ident = match **param {
+				syn::GenericParam::Type(ref type_param) => &type_param.ident,
+				syn::GenericParam::Lifetime(ref lifetime_def) => &lifetime_def.lifetime.ident,
+				syn::GenericParam::Const(ref const_param) => &const_param.ident,
+			};
+			if is_used(ident) {
+				true
+			} else {
+				unused_idents.push(ident.clone());
+				false
+			}
+		}).cloned().collect();
+		
+		let where_clause = generics.make_where_clause();
+		where_clause.predicates = where_clause.predicates.iter().filter(|predicate| {
+			let predicate_tokens = quote!(#predicate);
+			!unused_idents.iter().any(|ident| tokens_mention(predicate_tokens.clone(), ident))
+		}).cloned().collect();
+		for field in &self.fields {
+			let change_type = &field.change_type;
+			where_clause.predicates.push(syn::parse_quote!(#change_type: modelone::model::Change));
+		}
+		
+		generics
+	}
+	
+	/// The generics for the impls on the struct, with the given trait required of each field for
+	/// its change type.
+	fn impl_generics(&self, field_trait: TokenStream) -> syn::Generics {
+		let ident = &self.ident;
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		let enum_generics = self.enum_generics();
+		let (_, enum_ty_generics, _) = enum_generics.split_for_impl();
+		let change_name = &self.change_name;
+		
+		let signal_name = &self.signal_name;
+		
+		let mut generics = self.generics.clone();
+		let where_clause = generics.make_where_clause();
+		where_clause.predicates.push(syn::parse_quote!(#ident #ty_generics: Send));
+		// These are what the change enum's Change impl requires. Requiring the change enum to be
+		// Change directly would hide its SignalType from the impl.
+		where_clause.predicates.push(syn::parse_quote!(#change_name #enum_ty_generics: Send + 'static));
+		where_clause.predicates.push(syn::parse_quote!(#signal_name #enum_ty_generics: std::fmt::Debug + Clone + PartialEq));
+		for field in &self.fields {
+			let ty = &field.ty;
+			let change_type = &field.change_type;
+			where_clause.predicates.push(syn::parse_quote!(#change_type: modelone::model::Change));
+			where_clause.predicates.push(syn::parse_quote!(#ty: #field_trait<#change_type>));
+		}
+		generics
+	}
+	
+	/// The generics for the impls on the change enum, which add what its Change impl requires to
+	/// the enum's own generics.
+	fn change_impl_generics(&self) -> syn::Generics {
+		let change_name = &self.change_name;
+		let signal_name = &self.signal_name;
+		let enum_generics = self.enum_generics();
+		let (_, enum_ty_generics, _) = enum_generics.split_for_impl();
+		
+		let mut generics = enum_generics.clone();
+		{
+			let where_clause = generics.make_where_clause();
+			where_clause.predicates.push(syn::parse_quote!(#change_name #enum_ty_generics: Send + 'static));
+			where_clause.predicates.push(syn::parse_quote!(#signal_name #enum_ty_generics: std::fmt::Debug + Clone + PartialEq));
+		}
+		generics
+	}
+	
+	fn generate_changeable(&self) -> TokenStream {
+		let vis = &self.vis;
+		let ident = &self.ident;
+		let change_name = &self.change_name;
+		let signal_name = &self.signal_name;
+		
+		let enum_generics = self.enum_generics();
+		let (enum_impl_generics, enum_ty_generics, enum_where_clause) = enum_generics.split_for_impl();
+		
+		let change_impl_generics = self.change_impl_generics();
+		let change_where_clause = &change_impl_generics.where_clause;
+		
+		let impl_generics = self.impl_generics(quote!(modelone::model::Changeable));
+		let (impl_impl_generics, _, impl_where_clause) = impl_generics.split_for_impl();
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		
+		// Serde can't infer the bounds for the signal types, so they are given explicitly.
+		let serde_bound = |ty_fmt: &dyn Fn(&syn::Type) -> TokenStream, bound: TokenStream| {
+			self.fields.iter().map(|field| {
+				let ty = ty_fmt(&field.change_type);
+				quote!(#ty: #bound,).to_string()
+			}).collect::<String>()
+		};
+		let signal_type = |ty: &syn::Type| quote!(<#ty as modelone::model::Change>::SignalType);
+		let change_type = |ty: &syn::Type| quote!(#ty);
+		let signal_ser_bound = serde_bound(&signal_type, quote!(modelone::serde::Serialize));
+		let signal_de_bound = serde_bound(&signal_type, quote!(modelone::serde::Deserialize<'de>));
+		let change_ser_bound = serde_bound(&change_type, quote!(modelone::serde::Serialize));
+		let change_de_bound = serde_bound(&change_type, quote!(modelone::serde::Deserialize<'de>));
+		
+		// Each of these is one variant or match arm per field.
+		let signal_variants = self.fields.iter().map(|field| {
+			let ChangeField { ref variant, ref change_type, ref docs, .. } = *field;
+			quote! {
+				#(#docs)*
+				#variant(<#change_type as modelone::model::Change>::SignalType),
+			}
+		}).collect::<Vec<_>>();
+		let change_variants = self.fields.iter().map(|field| {
+			let ChangeField { ref variant, ref change_type, ref docs, .. } = *field;
+			quote! {
+				#(#docs)*
+				#variant(#change_type),
+			}
+		}).collect::<Vec<_>>();
+		let coalesce_arms = self.fields.iter().map(|field| {
+			let variant = &field.variant;
+			quote! {
+				(#change_name::#variant(subchange), #change_name::#variant(next_subchange)) => {
+					modelone::model::Change::coalesce(subchange, next_subchange).map_err(#change_name::#variant)
+				},
+			}
+		}).collect::<Vec<_>>();
+		let apply_arms = self.fields.iter().map(|field| {
+			let ChangeField { ref member, ref variant, ref change_type, .. } = *field;
+			let on_changed_call = field.on_changed.as_ref().map(|path| quote!(#path(self);));
+			quote! {
+				#change_name::#variant(subchange) => {
+					#on_changed_call
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(#signal_name::#variant(signal));
+					};
+					modelone::model::Changeable::<#change_type>::changeable_apply(&mut self.#member, subchange, &mut modelone::model::SubWatcher::new(&mut watcher_fn));
+				},
+			}
+		}).collect::<Vec<_>>();
+		let reset_view_signals = self.fields.iter().map(|field| {
+			let ChangeField { ref member, ref variant, ref change_type, .. } = *field;
+			quote! {
+				for subsignal in modelone::model::Changeable::<#change_type>::reset_view_signals(&self.#member) {
+					signals.push(#signal_name::#variant(subsignal));
+				}
+			}
+		}).collect::<Vec<_>>();
+		let validate_arms = self.fields.iter().map(|field| {
+			let ChangeField { ref member, ref variant, ref change_type, .. } = *field;
+			let variant_str = variant.to_string();
+			quote! {
+				#change_name::#variant(ref subchange) => {
+					modelone::model::Changeable::<#change_type>::validate_change(&self.#member, subchange)
+						.map_err(|err| err.within(#variant_str))
+				},
+			}
+		}).collect::<Vec<_>>();
+		
+		quote! {
+			modelone::impl_serde_derive!{
+				bound(#signal_ser_bound, #signal_de_bound)
+				#[allow(non_camel_case_types)]
+				#[derive(Debug, Clone, PartialEq)]
+				#vis enum #signal_name #enum_generics #enum_where_clause {
+					#(#signal_variants)*
+				}
+			}
+			
+			modelone::impl_serde_derive!{
+				bound(#change_ser_bound, #change_de_bound)
+				#[allow(non_camel_case_types)]
+				#[derive(Debug, Clone, PartialEq)]
+				#vis enum #change_name #enum_generics #enum_where_clause {
+					#(#change_variants)*
+				}
+			}
+			
+			impl #enum_impl_generics modelone::model::Change for #change_name #enum_ty_generics #change_where_clause {
+				type SignalType = #signal_name #enum_ty_generics;
+				
+				fn coalesce(&mut self, next: Self) -> Result<(), Self> {
+					#[allow(unreachable_patterns)]
+					match (self, next) {
+						#(#coalesce_arms)*
+						(_, next) => Err(next),
+					}
+				}
+			}
+			
+			impl #impl_impl_generics modelone::model::Changeable<#change_name #enum_ty_generics> for #ident #ty_generics #impl_where_clause {
+				fn changeable_apply(&mut self, change: #change_name #enum_ty_generics, watcher: &mut dyn modelone::model::Watcher<#signal_name #enum_ty_generics>) {
+					match change {
+						#(#apply_arms)*
+					}
+				}
+				
+				fn reset_view_signals(&self) -> Vec<#signal_name #enum_ty_generics> {
+					let mut signals = vec![];
+					#(#reset_view_signals)*
+					signals
+				}
+				
+				fn validate_change(&self, change: &#change_name #enum_ty_generics) -> Result<(), modelone::model::ApplyError> {
+					match *change {
+						#(#validate_arms)*
+					}
+				}
+			}
+		}
+	}
+	
+	fn generate_revertable(&self) -> TokenStream {
+		let ident = &self.ident;
+		let change_name = &self.change_name;
+		let signal_name = &self.signal_name;
+		
+		let enum_generics = self.enum_generics();
+		let (_, enum_ty_generics, _) = enum_generics.split_for_impl();
+		
+		let impl_generics = self.impl_generics(quote!(modelone::model::Revertable));
+		let (impl_impl_generics, _, impl_where_clause) = impl_generics.split_for_impl();
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		
+		let apply_arms = self.fields.iter().map(|field| {
+			let ChangeField { ref member, ref variant, ref change_type, .. } = *field;
+			let on_changed_call = field.on_changed.as_ref().map(|path| quote!(#path(self);));
+			quote! {
+				#change_name::#variant(subchange) => {
+					#on_changed_call
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(#signal_name::#variant(signal));
+					};
+					#change_name::#variant(modelone::model::Revertable::<#change_type>::revertable_apply(&mut self.#member, subchange, &mut modelone::model::SubWatcher::new(&mut watcher_fn)))
+				},
+			}
+		}).collect::<Vec<_>>();
+		
+		quote! {
+			impl #impl_impl_generics modelone::model::Revertable<#change_name #enum_ty_generics> for #ident #ty_generics #impl_where_clause {
+				fn revertable_apply(&mut self, change: #change_name #enum_ty_generics, watcher: &mut dyn modelone::model::Watcher<#signal_name #enum_ty_generics>) -> #change_name #enum_ty_generics {
+					match change {
+						#(#apply_arms)*
+					}
+				}
+			}
+		}
+	}
+	
+	fn generate_diff(&self) -> TokenStream {
+		let ident = &self.ident;
+		let change_name = &self.change_name;
+		
+		let enum_generics = self.enum_generics();
+		let (_, enum_ty_generics, _) = enum_generics.split_for_impl();
+		
+		let impl_generics = self.impl_generics(quote!(modelone::diff::Diff));
+		let (impl_impl_generics, _, impl_where_clause) = impl_generics.split_for_impl();
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		
+		let field_diffs = self.fields.iter().map(|field| {
+			let ChangeField { ref member, ref variant, ref change_type, .. } = *field;
+			quote! {
+				changes.extend(modelone::diff::Diff::<#change_type>::diff(&self.#member, &other.#member).into_iter().map(#change_name::#variant));
+			}
+		}).collect::<Vec<_>>();
+		
+		quote! {
+			impl #impl_impl_generics modelone::diff::Diff<#change_name #enum_ty_generics> for #ident #ty_generics #impl_where_clause {
+				fn diff(&self, other: &Self) -> Vec<#change_name #enum_ty_generics> {
+					let mut changes = vec![];
+					#(#field_diffs)*
+					changes
+				}
+			}
+		}
+	}
+	
+	fn generate_transform(&self) -> TokenStream {
+		let change_name = &self.change_name;
+		
+		let mut generics = self.change_impl_generics();
+		{
+			let where_clause = generics.make_where_clause();
+			for field in &self.fields {
+				let change_type = &field.change_type;
+				where_clause.predicates.push(syn::parse_quote!(#change_type: modelone::transform::Transform));
+			}
+		}
+		let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+		
+		let transform_arms = self.fields.iter().map(|field| {
+			let variant = &field.variant;
+			quote! {
+				(#change_name::#variant(subchange), #change_name::#variant(other_subchange)) => {
+					modelone::transform::Transform::transform(subchange, other_subchange, priority).map(#change_name::#variant)
+				},
+			}
+		}).collect::<Vec<_>>();
+		
+		quote! {
+			impl #impl_generics modelone::transform::Transform for #change_name #ty_generics #where_clause {
+				fn transform(self, other: &Self, priority: modelone::transform::Priority) -> Option<Self> {
+					#[allow(unreachable_patterns)]
+					match (self, other) {
+						#(#transform_arms)*
+						(change, _) => Some(change),
+					}
+				}
+			}
+		}
+	}
+}
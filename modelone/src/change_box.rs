@@ -1,4 +1,6 @@
-use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, apply_pipe_to_mut_ref};
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ApplyError, apply_pipe_to_mut_ref};
+use crate::transform::{Transform, Priority};
+use crate::history::ChangeSize;
 
 use std;
 use std::borrow::BorrowMut;
@@ -6,11 +8,27 @@ use std::borrow::BorrowMut;
 impl<C: Change> Change for Box<C> {
 	type SignalType = Box<C::SignalType>;
 	
+	fn coalesce(&mut self, next: Box<C>) -> Result<(), Box<C>> {
+		self.as_mut().coalesce(*next).map_err(Box::new)
+	}
+	
 	/*fn get_signals(&self) -> Vec<Self::SignalType> {
 		self.as_ref().get_signals().into_iter().map(|signal| Box::new(signal)).collect()
 	}*/
 }
 
+impl<C: Transform> Transform for Box<C> {
+	fn transform(self, other: &Box<C>, priority: Priority) -> Option<Box<C>> {
+		(*self).transform(other, priority).map(Box::new)
+	}
+}
+
+impl<C: ChangeSize> ChangeSize for Box<C> {
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<Box<C>>() + self.as_ref().estimated_size()
+	}
+}
+
 /// Applying Box<C> to T will just apply C to T
 impl<T: Changeable<C> + std::cmp::PartialEq, C: Change> Changeable<Box<C>> for T {
 	fn changeable_apply(&mut self, change: Box<C>, watcher: &mut Watcher<Box<C::SignalType>>) {
@@ -27,6 +45,10 @@ impl<T: Changeable<C> + std::cmp::PartialEq, C: Change> Changeable<Box<C>> for T
 		}
 		signals
 	}
+	
+	fn validate_change(&self, change: &Box<C>) -> Result<(), ApplyError> {
+		Changeable::<C>::validate_change(self, change)
+	}
 }
 
 /// Applying Box<C> to T will just apply C to T
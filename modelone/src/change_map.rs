@@ -0,0 +1,282 @@
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor, ApplyError, ApplyErrorKind};
+use crate::history::ChangeSize;
+
+use std;
+use std::collections::{HashMap, BTreeMap};
+use std::fmt::Debug;
+use std::hash::Hash;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapSignal<K, ST> {
+	/// The value at `key` was inserted or replaced
+	Insert{key: K},
+	/// The value at `key` was removed
+	Remove{key: K},
+	/// The entire map was replaced with a different one
+	ReplaceAll,
+	/// The value at `key` was changed, as described by `signal`
+	At{key: K, signal: ST},
+}
+
+/// A change for map types such as HashMap and BTreeMap, which supports nested changes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapChange<K, V: Changeable<C>, C: Change> {
+	/// Insert `value` at `key`, replacing the existing value if there is one
+	Insert{key: K, value: V},
+	/// Remove the value at `key`, if there is one
+	Remove{key: K},
+	/// Replace the entire map with the given entries
+	ReplaceAll(Vec<(K, V)>),
+	/// Apply the given `change` to the value at `key`
+	At{key: K, change: C},
+}
+
+impl<K, V, C> Change for MapChange<K, V, C> where
+	K: 'static + Send + Debug + Clone + PartialEq,
+	V: 'static + Changeable<C> + Send,
+	C: Change,
+{
+	type SignalType = MapSignal<K, C::SignalType>;
+}
+
+impl<K, V, C> ChangeSize for MapChange<K, V, C> where
+	K: 'static + Send + Debug + Clone + PartialEq,
+	V: 'static + Changeable<C> + Send,
+	C: ChangeSize,
+{
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<MapChange<K, V, C>>() + match *self {
+			MapChange::ReplaceAll(ref entries) => entries.len() * std::mem::size_of::<(K, V)>(),
+			MapChange::At{ref change, ..} => change.estimated_size(),
+			_ => 0,
+		}
+	}
+}
+
+/// Implements Changeable and Revertable with MapChange for one of the std map types, which all
+/// share the same `insert`/`remove`/`get_mut` API but need different bounds on the key.
+macro_rules! impl_map_change{
+	($map_type:ident, $($key_bound:tt)+) => {
+		impl<K, V, C> Changeable<MapChange<K, V, C>> for $map_type<K, V> where
+			K: 'static + Send + Debug + Clone + PartialEq + $($key_bound)+,
+			V: 'static + Changeable<C> + Send,
+			C: Change,
+		{
+			fn changeable_apply(&mut self, change: MapChange<K, V, C>, watcher: &mut Watcher<MapSignal<K, C::SignalType>>) {
+				use self::MapChange::*;
+				match change {
+					Insert{key, value} => {
+						self.insert(key.clone(), value);
+						watcher.send_signal(MapSignal::Insert{key});
+					},
+					Remove{key} => {
+						if self.remove(&key).is_some() {
+							watcher.send_signal(MapSignal::Remove{key});
+						}
+					},
+					ReplaceAll(entries) => {
+						*self = entries.into_iter().collect();
+						watcher.send_signal(MapSignal::ReplaceAll);
+					},
+					At{key, change: subchange} => {
+						let value = self.get_mut(&key).expect("MapChange::At applied to a key that is not in the map");
+						let mut watcher_fn = |signal| {
+							watcher.send_signal(MapSignal::At{key: key.clone(), signal});
+						};
+						value.changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
+					},
+				}
+			}
+			
+			fn reset_view_signals(&self) -> Vec<MapSignal<K, C::SignalType>> {
+				vec![MapSignal::ReplaceAll]
+			}
+			
+			fn validate_change(&self, change: &MapChange<K, V, C>) -> Result<(), ApplyError> {
+				match *change {
+					MapChange::At{ref key, change: ref subchange} => {
+						match self.get(key) {
+							Some(value) => value.validate_change(subchange).map_err(|err| err.within(format!("[{:?}]", key))),
+							None => Err(ApplyErrorKind::MissingKey(format!("{:?}", key)).into()),
+						}
+					}
+					_ => Ok(()),
+				}
+			}
+		}
+		
+		impl<K, V, C> Revertable<MapChange<K, V, C>> for $map_type<K, V> where
+			K: 'static + Send + Debug + Clone + PartialEq + $($key_bound)+,
+			V: 'static + Revertable<C> + Send,
+			C: Change,
+		{
+			fn revertable_apply(&mut self, change: MapChange<K, V, C>, watcher: &mut Watcher<MapSignal<K, C::SignalType>>) -> MapChange<K, V, C> {
+				use self::MapChange::*;
+				match change {
+					Insert{key, value} => {
+						let old_value = self.insert(key.clone(), value);
+						watcher.send_signal(MapSignal::Insert{key: key.clone()});
+						if let Some(value) = old_value {
+							Insert{key, value}
+						} else {
+							Remove{key}
+						}
+					},
+					Remove{key} => {
+						if let Some(value) = self.remove(&key) {
+							watcher.send_signal(MapSignal::Remove{key: key.clone()});
+							Insert{key, value}
+						} else {
+							// Nothing was removed, so removing the key again does nothing as well.
+							Remove{key}
+						}
+					},
+					ReplaceAll(entries) => {
+						let old_map = std::mem::replace(self, entries.into_iter().collect());
+						watcher.send_signal(MapSignal::ReplaceAll);
+						ReplaceAll(old_map.into_iter().collect())
+					},
+					At{key, change: subchange} => {
+						let value = self.get_mut(&key).expect("MapChange::At applied to a key that is not in the map");
+						let revertchange = {
+							let mut watcher_fn = |signal| {
+								watcher.send_signal(MapSignal::At{key: key.clone(), signal});
+							};
+							value.revertable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn))
+						};
+						At{key, change: revertchange}
+					},
+				}
+			}
+		}
+	};
+}
+
+impl_map_change!(HashMap, Eq + Hash);
+impl_map_change!(BTreeMap, Ord);
+
+/// A ChangeConstructor that builds changes for the value at a particular key in a map. It becomes
+/// invalid when the value at that key is replaced or removed.
+pub struct MapChangeConstructor<K, C: Change> {
+	key: K,
+	sub: Box<ChangeConstructor<C>>,
+}
+
+impl<K, C: Change> MapChangeConstructor<K, C> {
+	pub fn new(key: K, sub: Box<ChangeConstructor<C>>) -> MapChangeConstructor<K, C> {
+		MapChangeConstructor { key, sub }
+	}
+}
+
+impl<K, V, C> ChangeConstructor<MapChange<K, V, C>> for MapChangeConstructor<K, C> where
+	K: 'static + Send + Debug + Clone + PartialEq,
+	V: 'static + Changeable<C> + Send,
+	C: Change,
+{
+	fn create(&self, leaf_change: Box<std::any::Any>) -> MapChange<K, V, C> {
+		MapChange::At{
+			key: self.key.clone(),
+			change: self.sub.create(leaf_change),
+		}
+	}
+	
+	fn update(&mut self, change: &MapChange<K, V, C>) -> bool {
+		use self::MapChange::*;
+		match *change {
+			Insert{ref key, ..} if *key == self.key => {
+				// Insert replaces the value that the constructor refers to.
+				false
+			}
+			Remove{ref key} if *key == self.key => {
+				false
+			}
+			ReplaceAll(..) => {
+				false
+			}
+			At{ref key, ref change} if *key == self.key => {
+				self.sub.update(change)
+			}
+			_ => true
+		}
+	}
+	
+	fn affects(&self, change: &MapChange<K, V, C>) -> bool {
+		use self::MapChange::*;
+		match *change {
+			Insert{ref key, ..} | Remove{ref key} => *key == self.key,
+			ReplaceAll(..) => true,
+			At{ref key, ref change} => *key == self.key && self.sub.affects(change),
+		}
+	}
+	
+	fn debug_string(&self) -> String {
+		format!("[{:?}]/{}", self.key, self.sub.debug_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{LeafChangeConstructor, SpyWatcher};
+	use crate::change_string::{StringChange, StringSignal};
+	
+	#[test] fn change_map() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut m: HashMap<u32, String> = HashMap::new();
+		m.insert(1, "one".into());
+		let original = m.clone();
+		
+		let revert1 = m.revertable_apply(MapChange::Insert::<_, _, StringChange>{key: 2, value: "two".into()}, &mut spy);
+		let revert2 = m.revertable_apply(MapChange::At{key: 1, change: StringChange{index: 3, len: 0, new: "!".into()}}, &mut spy);
+		let revert3 = m.revertable_apply(MapChange::Remove::<_, _, StringChange>{key: 2}, &mut spy);
+		assert_eq!(m.len(), 1);
+		assert_eq!(m[&1], "one!");
+		m.revertable_apply(revert3, &mut spy);
+		m.revertable_apply(revert2, &mut spy);
+		m.revertable_apply(revert1, &mut spy);
+		assert_eq!(m, original);
+		
+		assert_eq!(spy.signals, vec![
+			MapSignal::Insert{key: 2},
+			MapSignal::At{key: 1, signal: StringSignal{index: 3, from_len: 0, to_len: 1}},
+			MapSignal::Remove{key: 2},
+			MapSignal::Insert{key: 2},
+			MapSignal::At{key: 1, signal: StringSignal{index: 3, from_len: 1, to_len: 0}},
+			MapSignal::Remove{key: 2},
+		]);
+	}
+	
+	#[test] fn change_btree_map_replace() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut m: BTreeMap<u32, String> = BTreeMap::new();
+		m.insert(1, "one".into());
+		let original = m.clone();
+		
+		let revert = m.revertable_apply(MapChange::Insert::<_, _, StringChange>{key: 1, value: "uno".into()}, &mut spy);
+		assert_eq!(revert, MapChange::Insert{key: 1, value: "one".into()});
+		m.revertable_apply(revert, &mut spy);
+		
+		let revert = m.revertable_apply(MapChange::ReplaceAll::<_, _, StringChange>(vec![(3, "three".into())]), &mut spy);
+		assert_eq!(m.keys().cloned().collect::<Vec<_>>(), vec![3]);
+		m.revertable_apply(revert, &mut spy);
+		assert_eq!(m, original);
+	}
+	
+	#[test] fn map_change_constructor() {
+		let lcc = LeafChangeConstructor::<StringChange>::new();
+		let mut mcc = MapChangeConstructor::new(5u32, Box::new(lcc));
+		
+		let change: MapChange<u32, String, StringChange> = mcc.create(Box::new(StringChange{index: 0, len: 0, new: "a".into()}));
+		assert_eq!(change, MapChange::At{key: 5, change: StringChange{index: 0, len: 0, new: "a".into()}});
+		
+		assert!(ChangeConstructor::<MapChange<u32, String, StringChange>>::update(&mut mcc, &MapChange::Insert{key: 4, value: "".into()}));
+		assert!(ChangeConstructor::<MapChange<u32, String, StringChange>>::update(&mut mcc, &MapChange::Remove{key: 4}));
+		assert!(!ChangeConstructor::<MapChange<u32, String, StringChange>>::update(&mut mcc, &MapChange::Remove{key: 5}));
+	}
+}
@@ -1,6 +1,11 @@
-use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor};
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor, ApplyError, ApplyErrorKind};
+use crate::transform::{Transform, Priority};
+use crate::diff::Diff;
+use crate::history::ChangeSize;
 
 use std;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 /// A change for Option types.
 /*#[derive(Debug, Clone)]
@@ -25,6 +30,7 @@ impl<T: Revertable<C>, C> Revertable<OptionChange<T, C>> for Option<T> {
 	}
 }*/
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum OptionSignal<ST> {
 	/// The Option's entire value changed.
@@ -34,6 +40,7 @@ pub enum OptionSignal<ST> {
 }
 
 /// A change for Option types, which supports nested changes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum OptionChange<T: Changeable<C>, C: Change> {
 	/// Set the Option's entire value.
@@ -44,6 +51,20 @@ pub enum OptionChange<T: Changeable<C>, C: Change> {
 
 impl<T: 'static + Changeable<C> + Send, C: Change> Change for OptionChange<T, C> {
 	type SignalType = OptionSignal<C::SignalType>;
+	
+	fn coalesce(&mut self, next: OptionChange<T, C>) -> Result<(), OptionChange<T, C>> {
+		use self::OptionChange::*;
+		match (self, next) {
+			(this @ Reset(_), next @ Reset(_)) => {
+				*this = next;
+				Ok(())
+			}
+			(Change(subchange), Change(next_subchange)) => {
+				subchange.coalesce(next_subchange).map_err(Change)
+			}
+			(_, next) => Err(next),
+		}
+	}
 }
 
 impl<T: 'static + Changeable<C> + Send, C: Change> Changeable<OptionChange<T, C>> for Option<T> {
@@ -66,6 +87,16 @@ impl<T: 'static + Changeable<C> + Send, C: Change> Changeable<OptionChange<T, C>
 	fn reset_view_signals(&self) -> Vec<OptionSignal<C::SignalType>> {
 		vec![OptionSignal::Reset]
 	}
+	
+	fn validate_change(&self, change: &OptionChange<T, C>) -> Result<(), ApplyError> {
+		match (self, change) {
+			(_, OptionChange::Reset(_)) => Ok(()),
+			(Some(value), OptionChange::Change(subchange)) => {
+				value.validate_change(subchange).map_err(|err| err.within("Some"))
+			}
+			(None, OptionChange::Change(_)) => Err(ApplyErrorKind::NoValue.into()),
+		}
+	}
 }
 
 impl<T: 'static + Revertable<C> + Send, C: Change> Revertable<OptionChange<T, C>> for Option<T> {
@@ -88,6 +119,47 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Revertable<OptionChange<T, C>
 	}
 }
 
+/// Diffs the values when both are Some, otherwise resets the Option.
+impl<T: 'static + Diff<C> + Clone + Send, C: Change> Diff<OptionChange<T, C>> for Option<T> {
+	fn diff(&self, other: &Option<T>) -> Vec<OptionChange<T, C>> {
+		match (self, other) {
+			(Some(value), Some(other_value)) => value.diff(other_value).into_iter().map(OptionChange::Change).collect(),
+			(None, None) => vec![],
+			_ => vec![OptionChange::Reset(other.clone())],
+		}
+	}
+}
+
+impl<T: 'static + Changeable<C> + Send, C: ChangeSize> ChangeSize for OptionChange<T, C> {
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<OptionChange<T, C>>() + match *self {
+			OptionChange::Reset(_) => 0,
+			OptionChange::Change(ref change) => change.estimated_size(),
+		}
+	}
+}
+
+/// Resetting the Option replaces whatever a concurrent nested change did, so Reset always wins
+/// over Change.
+impl<T: 'static + Changeable<C> + Send, C: Transform> Transform for OptionChange<T, C> {
+	fn transform(self, other: &OptionChange<T, C>, priority: Priority) -> Option<OptionChange<T, C>> {
+		use self::OptionChange::*;
+		match (self, other) {
+			(Reset(value), Reset(_)) => {
+				match priority {
+					Priority::Mine => Some(Reset(value)),
+					Priority::Theirs => None,
+				}
+			}
+			(Reset(value), Change(_)) => Some(Reset(value)),
+			(Change(_), Reset(_)) => None,
+			(Change(subchange), Change(other_subchange)) => {
+				subchange.transform(other_subchange, priority).map(Change)
+			}
+		}
+	}
+}
+
 pub struct OptionChangeConstructor<C: Change> {
 	sub: Box<ChangeConstructor<C>>,
 }
@@ -115,6 +187,14 @@ impl<T: 'static + Changeable<C>, C: Change> ChangeConstructor<OptionChange<T, C>
 		}
 	}
 	
+	fn affects(&self, change: &OptionChange<T, C>) -> bool {
+		use self::OptionChange::*;
+		match *change {
+			Reset(..) => true,
+			Change(ref change) => self.sub.affects(change),
+		}
+	}
+	
 	fn debug_string(&self) -> String {
 		format!("Some/{}", self.sub.debug_string())
 	}
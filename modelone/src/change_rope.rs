@@ -0,0 +1,254 @@
+use crate::model::{Changeable, Revertable, Watcher, ApplyError};
+use crate::change_string::{StringChange, StringSignal, StringChangeError};
+
+use std;
+use std::fmt;
+use std::ops::Range;
+use ropey::{Rope, RopeSlice};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+/// A text model backed by a rope, which can be changed with StringChange like a String can, but
+/// without the cost of moving the rest of the text on every change. This makes it suitable for
+/// large documents.
+///
+/// Like StringChange, all indices are UTF-8 byte indices. Lines are split the same way as
+/// [`ropey`](https://docs.rs/ropey) does, which includes CRLF and Unicode line breaks.
+#[derive(Clone, PartialEq, Default)]
+pub struct RopeText {
+	rope: Rope,
+}
+
+impl RopeText {
+	pub fn new() -> RopeText {
+		RopeText { rope: Rope::new() }
+	}
+	
+	/// The underlying rope, for queries that aren't provided here.
+	pub fn rope(&self) -> &Rope {
+		&self.rope
+	}
+	
+	/// The length of the text in bytes.
+	pub fn len(&self) -> usize {
+		self.rope.len_bytes()
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.rope.len_bytes() == 0
+	}
+	
+	/// The number of lines in the text. This is always at least one, because an empty text has one
+	/// empty line, and text that ends with a line break has an empty line after it.
+	pub fn line_count(&self) -> usize {
+		self.rope.len_lines()
+	}
+	
+	/// Returns the line that the byte at `byte` is on.
+	pub fn byte_to_line(&self, byte: usize) -> usize {
+		self.rope.byte_to_line(byte)
+	}
+	
+	/// Returns the byte index that `line` starts at.
+	pub fn line_to_byte(&self, line: usize) -> usize {
+		self.rope.line_to_byte(line)
+	}
+	
+	/// Returns the byte range of `line`, including its line break.
+	pub fn line_range(&self, line: usize) -> Range<usize> {
+		let start = self.rope.line_to_byte(line);
+		start .. start + self.rope.line(line).len_bytes()
+	}
+	
+	/// Returns the text of `line`, including its line break.
+	pub fn line(&self, line: usize) -> RopeSlice<'_> {
+		self.rope.line(line)
+	}
+	
+	/// Returns the line and the char column within that line that the byte at `byte` is at.
+	pub fn line_column(&self, byte: usize) -> (usize, usize) {
+		let line = self.rope.byte_to_line(byte);
+		let column = self.rope.byte_to_char(byte) - self.rope.line_to_char(line);
+		(line, column)
+	}
+	
+	/// Returns the text in the given byte range.
+	pub fn slice(&self, range: Range<usize>) -> RopeSlice<'_> {
+		self.rope.byte_slice(range)
+	}
+	
+	/// Checks that `change` can be applied to this text, which means its range must be within
+	/// the text and start and end on character boundaries.
+	pub fn validate(&self, change: &StringChange) -> Result<(), StringChangeError> {
+		let end = match change.index.checked_add(change.len) {
+			Some(end) if end <= self.rope.len_bytes() => end,
+			_ => return Err(StringChangeError::OutOfBounds{index: change.index, len: change.len, text_len: self.rope.len_bytes()}),
+		};
+		for &index in &[change.index, end] {
+			if self.rope.char_to_byte(self.rope.byte_to_char(index)) != index {
+				return Err(StringChangeError::NotCharBoundary{index});
+			}
+		}
+		Ok(())
+	}
+	
+	/// Replaces the byte range with `new`, returning the text that was replaced if `keep_old` is
+	/// set. The change must already have been validated.
+	fn replace(&mut self, change: &StringChange, keep_old: bool) -> Option<String> {
+		let start = self.rope.byte_to_char(change.index);
+		let end = self.rope.byte_to_char(change.index + change.len);
+		let old = if keep_old { Some(self.rope.slice(start .. end).to_string()) } else { None };
+		self.rope.remove(start .. end);
+		self.rope.insert(start, &change.new);
+		old
+	}
+}
+
+impl fmt::Debug for RopeText {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Debug::fmt(&self.rope, f)
+	}
+}
+
+impl fmt::Display for RopeText {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.rope, f)
+	}
+}
+
+impl<'a> From<&'a str> for RopeText {
+	fn from(text: &'a str) -> RopeText {
+		RopeText { rope: Rope::from_str(text) }
+	}
+}
+
+impl From<String> for RopeText {
+	fn from(text: String) -> RopeText {
+		RopeText { rope: Rope::from(text) }
+	}
+}
+
+impl From<Rope> for RopeText {
+	fn from(rope: Rope) -> RopeText {
+		RopeText { rope }
+	}
+}
+
+/// The text is serialized as a plain string.
+#[cfg(feature = "serde")]
+impl Serialize for RopeText {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(&self.rope)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for RopeText {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RopeText, D::Error> {
+		String::deserialize(deserializer).map(RopeText::from)
+	}
+}
+
+impl Changeable<StringChange> for RopeText {
+	fn changeable_apply(&mut self, change: StringChange, watcher: &mut Watcher<StringSignal>) {
+		if let Err(err) = self.validate(&change) {
+			panic!("{}", err);
+		}
+		
+		self.replace(&change, false);
+		
+		watcher.send_signal(StringSignal{index: change.index, from_len: change.len, to_len: change.new.len()});
+	}
+	
+	fn reset_view_signals(&self) -> Vec<StringSignal> {
+		vec![StringSignal{index: 0, from_len: usize::MAX, to_len: self.len()}]
+	}
+	
+	fn validate_change(&self, change: &StringChange) -> Result<(), ApplyError> {
+		self.validate(change).map_err(ApplyError::from)
+	}
+}
+
+impl Revertable<StringChange> for RopeText {
+	fn revertable_apply(&mut self, mut change: StringChange, watcher: &mut Watcher<StringSignal>) -> StringChange {
+		if let Err(err) = self.validate(&change) {
+			panic!("{}", err);
+		}
+		
+		let old = self.replace(&change, true).unwrap();
+		change.len = change.new.len();
+		change.new = old;
+		
+		watcher.send_signal(StringSignal{index: change.index, from_len: change.new.len(), to_len: change.len});
+		
+		change
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::SpyWatcher;
+	use crate::history::{History, HistoryChange};
+	
+	#[test] fn rope_change() {
+		let mut spy = SpyWatcher::new();
+		let mut s = "Hello World".to_string();
+		let mut r = RopeText::from("Hello World");
+		
+		let changes = vec![
+			StringChange{index: 6, len: 2, new: "Awesome".into()},
+			StringChange{index: 0, len: 0, new: "é\n".into()},
+			StringChange{index: 3, len: 5, new: "".into()},
+		];
+		let mut reverts = vec![];
+		for change in changes {
+			s.changeable_apply(change.clone(), &mut SpyWatcher::new());
+			reverts.push(r.revertable_apply(change, &mut spy));
+			assert_eq!(r.to_string(), s);
+		}
+		for revert in reverts.into_iter().rev() {
+			r.revertable_apply(revert, &mut spy);
+		}
+		assert_eq!(r.to_string(), "Hello World");
+		
+		assert_eq!(spy.signals, vec![
+			StringSignal{index: 6, from_len: 2, to_len: 7},
+			StringSignal{index: 0, from_len: 0, to_len: 3},
+			StringSignal{index: 3, from_len: 5, to_len: 0},
+			StringSignal{index: 3, from_len: 0, to_len: 5},
+			StringSignal{index: 0, from_len: 3, to_len: 0},
+			StringSignal{index: 6, from_len: 7, to_len: 2},
+		]);
+		
+		assert_eq!(r.try_changeable_apply(StringChange{index: 0, len: 20, new: "".into()}, &mut spy), Err(StringChangeError::OutOfBounds{index: 0, len: 20, text_len: 11}.into()));
+		let mut r = RopeText::from("é");
+		assert_eq!(r.try_changeable_apply(StringChange{index: 1, len: 0, new: "".into()}, &mut spy), Err(StringChangeError::NotCharBoundary{index: 1}.into()));
+		assert_eq!(r.to_string(), "é");
+	}
+	
+	#[test] #[should_panic(expected = "StringChange range 0..5 is out of bounds for text of length 2")]
+	fn rope_change_invalid_panics() {
+		let mut r = RopeText::from("é");
+		r.revertable_apply(StringChange{index: 0, len: 5, new: "".into()}, &mut SpyWatcher::new());
+	}
+	
+	#[test] fn rope_lines() {
+		let r = RopeText::from("one\ntwö\r\nthree");
+		assert_eq!(r.line_count(), 3);
+		assert_eq!(r.byte_to_line(5), 1);
+		assert_eq!(r.line_to_byte(2), 10);
+		assert_eq!(r.line_range(1), 4 .. 10);
+		assert_eq!(r.line(1).to_string(), "twö\r\n");
+		assert_eq!(r.line_column(8), (1, 3));
+		assert_eq!(r.slice(4 .. 8).to_string(), "twö");
+	}
+	
+	#[test] fn rope_history() {
+		let mut history: History<RopeText, StringChange> = History::new(RopeText::from("abc"));
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 3, len: 0, new: "def".into()}), &mut SpyWatcher::new());
+		assert_eq!(history.model.to_string(), "abcdef");
+		history.changeable_apply(HistoryChange::Undo, &mut SpyWatcher::new());
+		assert_eq!(history.model.to_string(), "abc");
+	}
+}
@@ -0,0 +1,131 @@
+use crate::model::{Change, Changeable, Revertable, Watcher};
+use crate::history::ChangeSize;
+
+use std;
+use std::collections::{HashSet, BTreeSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetSignal<T> {
+	/// `item` was added to the set
+	Insert(T),
+	/// `item` was removed from the set
+	Remove(T),
+	/// The entire set was replaced with a different one
+	ReplaceAll,
+}
+
+/// A change for set types such as HashSet and BTreeSet. Set items can't be changed in place, so
+/// there is no nested change.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetChange<T> {
+	/// Add `item` to the set, if it isn't already in it
+	Insert(T),
+	/// Remove `item` from the set, if it is in it
+	Remove(T),
+	/// Replace the entire set with the given items
+	ReplaceAll(Vec<T>),
+}
+
+impl<T: 'static + Send + Debug + Clone + PartialEq> Change for SetChange<T> {
+	type SignalType = SetSignal<T>;
+}
+
+impl<T: 'static + Send + Debug + Clone + PartialEq> ChangeSize for SetChange<T> {
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<SetChange<T>>() + match *self {
+			SetChange::ReplaceAll(ref items) => items.len() * std::mem::size_of::<T>(),
+			_ => 0,
+		}
+	}
+}
+
+/// Implements Changeable and Revertable with SetChange for one of the std set types, which all
+/// share the same `insert`/`remove` API but need different bounds on the item.
+macro_rules! impl_set_change{
+	($set_type:ident, $($item_bound:tt)+) => {
+		impl<T> Changeable<SetChange<T>> for $set_type<T> where
+			T: 'static + Send + Debug + Clone + PartialEq + $($item_bound)+,
+		{
+			fn changeable_apply(&mut self, change: SetChange<T>, watcher: &mut Watcher<SetSignal<T>>) {
+				self.revertable_apply(change, watcher);
+			}
+			
+			fn reset_view_signals(&self) -> Vec<SetSignal<T>> {
+				vec![SetSignal::ReplaceAll]
+			}
+		}
+		
+		impl<T> Revertable<SetChange<T>> for $set_type<T> where
+			T: 'static + Send + Debug + Clone + PartialEq + $($item_bound)+,
+		{
+			fn revertable_apply(&mut self, change: SetChange<T>, watcher: &mut Watcher<SetSignal<T>>) -> SetChange<T> {
+				use self::SetChange::*;
+				match change {
+					Insert(item) => {
+						if self.insert(item.clone()) {
+							watcher.send_signal(SetSignal::Insert(item.clone()));
+							Remove(item)
+						} else {
+							// The item was already in the set, so inserting it again does nothing.
+							Insert(item)
+						}
+					},
+					Remove(item) => {
+						if self.remove(&item) {
+							watcher.send_signal(SetSignal::Remove(item.clone()));
+							Insert(item)
+						} else {
+							Remove(item)
+						}
+					},
+					ReplaceAll(items) => {
+						let old_set = std::mem::replace(self, items.into_iter().collect());
+						watcher.send_signal(SetSignal::ReplaceAll);
+						ReplaceAll(old_set.into_iter().collect())
+					},
+				}
+			}
+		}
+	};
+}
+
+impl_set_change!(HashSet, Eq + Hash);
+impl_set_change!(BTreeSet, Ord);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::SpyWatcher;
+	
+	#[test] fn change_set() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut s: BTreeSet<i32> = vec![1, 2, 3].into_iter().collect();
+		let revert1 = s.revertable_apply(SetChange::Insert(4), &mut spy);
+		let revert2 = s.revertable_apply(SetChange::Insert(1), &mut spy);
+		let revert3 = s.revertable_apply(SetChange::Remove(2), &mut spy);
+		assert_eq!(s.iter().cloned().collect::<Vec<_>>(), vec![1, 3, 4]);
+		s.revertable_apply(revert3, &mut spy);
+		s.revertable_apply(revert2, &mut spy);
+		s.revertable_apply(revert1, &mut spy);
+		assert_eq!(s.iter().cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+		
+		let mut h: HashSet<i32> = HashSet::new();
+		h.changeable_apply(SetChange::ReplaceAll(vec![5, 6]), &mut spy);
+		assert!(h.contains(&5) && h.contains(&6));
+		
+		assert_eq!(spy.signals, vec![
+			SetSignal::Insert(4),
+			SetSignal::Remove(2),
+			SetSignal::Insert(2),
+			SetSignal::Remove(4),
+			SetSignal::ReplaceAll,
+		]);
+	}
+}
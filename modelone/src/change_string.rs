@@ -1,5 +1,15 @@
-use crate::model::{Change, Changeable, Revertable, Watcher};
+use crate::model::{Change, Changeable, Revertable, Watcher, ApplyError};
+use crate::transform::{Transform, Priority};
+use crate::diff::{Diff, diff_ranges};
+use crate::history::ChangeSize;
 
+use std;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct StringSignal {
 	pub index: usize,
@@ -7,8 +17,36 @@ pub struct StringSignal {
 	pub to_len: usize,
 }
 
+impl StringSignal {
+	/// The byte range of the text that was replaced, in the text from before the change.
+	pub fn old_range(&self) -> Range<usize> {
+		self.index .. self.index.saturating_add(self.from_len)
+	}
+	
+	/// The byte range of the replacement text, in the text from after the change.
+	pub fn new_range(&self) -> Range<usize> {
+		self.index .. self.index + self.to_len
+	}
+	
+	/// Converts this signal from bytes to the given unit. `old_text` and `new_text` are the text
+	/// from before and after the change, which is needed to measure the replaced and replacement
+	/// text. A `from_len` of `usize::max_value()`, which means the whole text was replaced, is kept
+	/// as it is. Returns None if the texts don't match the signal.
+	pub fn to_unit(&self, old_text: &str, new_text: &str, unit: TextUnit) -> Option<StringSignal> {
+		let index = byte_to_unit(new_text, self.index, unit)?;
+		let from_len = if self.from_len == usize::MAX {
+			self.from_len
+		} else {
+			byte_to_unit(old_text, self.index + self.from_len, unit)? - byte_to_unit(old_text, self.index, unit)?
+		};
+		let to_len = byte_to_unit(new_text, self.index + self.to_len, unit)? - index;
+		Some(StringSignal{index, from_len, to_len})
+	}
+}
+
 /// A change for String types.
 /// Replace the text between [`index`, `index` + `count`) with `new`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct StringChange {
 	pub index: usize,
@@ -16,31 +54,225 @@ pub struct StringChange {
 	pub new: String,
 }
 
+impl StringChange {
+	/// Makes a change that replaces `len` units of text starting at `index` with `new`, where the
+	/// units are given by `unit`, by converting the range to bytes within `text`.
+	pub fn from_unit_range(text: &str, index: usize, len: usize, new: String, unit: TextUnit) -> Result<StringChange, StringChangeError> {
+		let to_byte = |unit_index| {
+			unit_to_byte(text, unit_index, unit).ok_or_else(|| {
+				if unit == TextUnit::Byte && unit_index <= text.len() {
+					StringChangeError::NotCharBoundary{index: unit_index}
+				} else {
+					StringChangeError::OutOfBounds{index, len, text_len: text.len()}
+				}
+			})
+		};
+		let start = to_byte(index)?;
+		let end = index.checked_add(len).ok_or(StringChangeError::OutOfBounds{index, len, text_len: text.len()})?;
+		let end = to_byte(end)?;
+		Ok(StringChange{index: start, len: end - start, new})
+	}
+	
+	/// Checks that this change can be applied to `text`, which means its range must be within the
+	/// text and start and end on character boundaries.
+	pub fn validate(&self, text: &str) -> Result<(), StringChangeError> {
+		let end = self.index.checked_add(self.len);
+		match end {
+			Some(end) if end <= text.len() => {
+				if !text.is_char_boundary(self.index) {
+					Err(StringChangeError::NotCharBoundary{index: self.index})
+				} else if !text.is_char_boundary(end) {
+					Err(StringChangeError::NotCharBoundary{index: end})
+				} else {
+					Ok(())
+				}
+			}
+			_ => Err(StringChangeError::OutOfBounds{index: self.index, len: self.len, text_len: text.len()}),
+		}
+	}
+}
+
+/// The error returned when a StringChange can't be applied to a text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringChangeError {
+	/// The range starting at `index` with length `len` goes past the end of the text, which is
+	/// `text_len` bytes long.
+	OutOfBounds{index: usize, len: usize, text_len: usize},
+	/// The byte `index` is inside a multi-byte character.
+	NotCharBoundary{index: usize},
+}
+
+impl std::fmt::Display for StringChangeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			StringChangeError::OutOfBounds{index, len, text_len} => {
+				write!(f, "StringChange range {}..{} is out of bounds for text of length {}", index, index.saturating_add(len), text_len)
+			}
+			StringChangeError::NotCharBoundary{index} => {
+				write!(f, "StringChange index {} is not on a character boundary", index)
+			}
+		}
+	}
+}
+
+impl std::error::Error for StringChangeError {}
+
+/// The unit that an index or length into a text is measured in.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextUnit {
+	/// UTF-8 bytes, which is what StringChange and StringSignal use
+	Byte,
+	/// Unicode scalar values, as given by `str::chars`
+	Char,
+	/// UTF-16 code units, as used by many text layout and platform APIs
+	Utf16,
+	/// Extended grapheme clusters, which is what a user sees as a single character
+	Grapheme,
+}
+
+/// Converts `index`, measured in `unit`, to a byte index into `text`. Returns None if the index is
+/// past the end of the text, or if it doesn't land on a character boundary (such as a byte index
+/// in the middle of a character, or a UTF-16 index in the middle of a surrogate pair).
+pub fn unit_to_byte(text: &str, index: usize, unit: TextUnit) -> Option<usize> {
+	let end = std::iter::once(text.len());
+	match unit {
+		TextUnit::Byte => {
+			if text.is_char_boundary(index) { Some(index) } else { None }
+		}
+		TextUnit::Char => {
+			text.char_indices().map(|(byte, _)| byte).chain(end).nth(index)
+		}
+		TextUnit::Utf16 => {
+			let mut utf16_index = 0;
+			for (byte, c) in text.char_indices() {
+				if utf16_index >= index {
+					return if utf16_index == index { Some(byte) } else { None };
+				}
+				utf16_index += c.len_utf16();
+			}
+			if utf16_index == index { Some(text.len()) } else { None }
+		}
+		TextUnit::Grapheme => {
+			text.grapheme_indices(true).map(|(byte, _)| byte).chain(end).nth(index)
+		}
+	}
+}
+
+/// Converts the byte index `byte` into `text` to an index measured in `unit`. Returns None if the
+/// index is past the end of the text, or isn't on a boundary of the unit.
+pub fn byte_to_unit(text: &str, byte: usize, unit: TextUnit) -> Option<usize> {
+	if !text.is_char_boundary(byte) {
+		return None;
+	}
+	match unit {
+		TextUnit::Byte => Some(byte),
+		TextUnit::Char => Some(text[.. byte].chars().count()),
+		TextUnit::Utf16 => Some(text[.. byte].encode_utf16().count()),
+		TextUnit::Grapheme => {
+			text.grapheme_indices(true).map(|(byte, _)| byte).chain(std::iter::once(text.len())).position(|start| start == byte)
+		}
+	}
+}
+
+/// Converts `index` from one unit to another within `text`.
+pub fn convert_index(text: &str, index: usize, from: TextUnit, to: TextUnit) -> Option<usize> {
+	unit_to_byte(text, index, from).and_then(|byte| byte_to_unit(text, byte, to))
+}
+
 impl Change for StringChange {
 	type SignalType = StringSignal;
 	
+	/// Merges `next` into this change if the text it replaces touches or overlaps the text that
+	/// this change inserted, such as when typing or backspacing one character at a time.
+	fn coalesce(&mut self, next: StringChange) -> Result<(), StringChange> {
+		let inserted_end = self.index + self.new.len();
+		let next_end = next.index + next.len;
+		if next.index > inserted_end || next_end < self.index {
+			return Err(next);
+		}
+		
+		// The parts of this change's inserted text that `next` keeps.
+		let head_len = next.index.saturating_sub(self.index);
+		let tail_start = if next_end < inserted_end { next_end - self.index } else { self.new.len() };
+		if !self.new.is_char_boundary(head_len) || !self.new.is_char_boundary(tail_start) {
+			return Err(next);
+		}
+		
+		// If `next` extends past the inserted text, it also removes text after this change's range.
+		let len = if next_end > inserted_end {
+			next_end - inserted_end + self.index + self.len
+		} else {
+			self.index + self.len
+		};
+		let index = self.index.min(next.index);
+		
+		let mut new = String::with_capacity(head_len + next.new.len() + self.new.len() - tail_start);
+		new.push_str(&self.new[.. head_len]);
+		new.push_str(&next.new);
+		new.push_str(&self.new[tail_start ..]);
+		
+		*self = StringChange{index, len: len - index, new};
+		Ok(())
+	}
+	
 	/*fn get_signals(&self) -> Vec<Self::SignalType> {
 		vec![StringSignal{index: self.index, from_len: self.len, to_len: self.new.len()}]
 	}*/
 }
 
-trait StringReplaceRange {
-	fn replace_range(&mut self, index: usize, len: usize, new: String) -> String;
+impl ChangeSize for StringChange {
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<StringChange>() + self.new.len()
+	}
 }
 
-impl StringReplaceRange for String {
-	/// Replaces the text between `index` and `index + len` with `new`, and
-	/// returns the string that it replaced.
-	fn replace_range(&mut self, index: usize, len: usize, new: String) -> String {
-		let old_str: String = self.drain(index .. index + len).collect();
-		self.insert_str(index, &new);
+/// Changes that replace separate ranges just shift each other along. When the ranges overlap,
+/// the union of both ranges is replaced by both changes' new text, with the text of the change
+/// that has priority going first, so no typed text is lost.
+impl Transform for StringChange {
+	fn transform(self, other: &StringChange, priority: Priority) -> Option<StringChange> {
+		let end = self.index + self.len;
+		let other_end = other.index + other.len;
+		let both_insert_here = self.len == 0 && other.len == 0 && self.index == other.index;
 		
-		old_str
+		if !both_insert_here && end <= other.index {
+			return Some(self);
+		}
+		if !both_insert_here && self.index >= other_end {
+			return Some(StringChange{index: self.index + other.new.len() - other.len, ..self});
+		}
+		
+		// After `other` is applied, the union of the two ranges holds the original text before
+		// other's range, other's new text, then the original text after other's range.
+		let index = self.index.min(other.index);
+		let before_len = other.index - index;
+		let after_len = end.max(other_end) - other_end;
+		let union_len = before_len + other.new.len() + after_len;
+		
+		Some(match priority {
+			Priority::Mine if after_len == 0 => {
+				StringChange{index, len: before_len, new: self.new}
+			}
+			Priority::Mine => {
+				StringChange{index, len: union_len, new: self.new + &other.new}
+			}
+			Priority::Theirs if before_len == 0 => {
+				StringChange{index: index + other.new.len(), len: after_len, new: self.new}
+			}
+			Priority::Theirs => {
+				StringChange{index, len: union_len, new: other.new.clone() + &self.new}
+			}
+		})
 	}
 }
 
 impl Revertable<StringChange> for String {
 	fn revertable_apply(&mut self, mut change: StringChange, watcher: &mut Watcher<StringSignal>) -> StringChange {
+		if let Err(err) = change.validate(self) {
+			panic!("{}", err);
+		}
+		
 		let old = self.drain(change.index .. change.index + change.len).collect();
 		change.len = change.new.len();
 		self.insert_str(change.index, &change.new);
@@ -54,6 +286,10 @@ impl Revertable<StringChange> for String {
 
 impl Changeable<StringChange> for String {
 	fn changeable_apply(&mut self, change: StringChange, watcher: &mut Watcher<StringSignal>) {
+		if let Err(err) = change.validate(self) {
+			panic!("{}", err);
+		}
+		
 		self.drain(change.index .. change.index + change.len);
 		self.insert_str(change.index, &change.new);
 		
@@ -63,17 +299,47 @@ impl Changeable<StringChange> for String {
 	fn reset_view_signals(&self) -> Vec<StringSignal> {
 		vec![StringSignal{index: 0, from_len: usize::max_value(), to_len: self.len()}]
 	}
+	
+	fn validate_change(&self, change: &StringChange) -> Result<(), ApplyError> {
+		change.validate(self).map_err(ApplyError::from)
+	}
+}
+
+/// Compares the text character by character, so the changes only cover the characters that differ.
+impl Diff<StringChange> for String {
+	fn diff(&self, other: &String) -> Vec<StringChange> {
+		let old_chars: Vec<char> = self.chars().collect();
+		let new_chars: Vec<char> = other.chars().collect();
+		let old_offsets = char_offsets(self);
+		let new_offsets = char_offsets(other);
+		
+		// Each change is applied after the ones before it, which have already made the text up
+		// to its start the same as `other`
+		diff_ranges(&old_chars, &new_chars).into_iter().map(|(old_range, new_range)| {
+			let new_start = new_offsets[new_range.start];
+			StringChange {
+				index: new_start,
+				len: old_offsets[old_range.end] - old_offsets[old_range.start],
+				new: other[new_start..new_offsets[new_range.end]].to_string(),
+			}
+		}).collect()
+	}
+}
+
+/// The byte offset of each character in `text`, followed by its length.
+fn char_offsets(text: &str) -> Vec<usize> {
+	text.char_indices().map(|(offset, _)| offset).chain(std::iter::once(text.len())).collect()
 }
 
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use model::SpyWatcher;
+	use crate::model::{NoWatcher, SpyWatcher};
 	
 	#[test] fn string_change() {
 		let mut spy = SpyWatcher::new();
-	
+		
 		let mut s = "Hello World".to_string();
 		
 		{
@@ -119,4 +385,169 @@ mod tests {
 			StringSignal { index: 0, from_len: 0, to_len: 0 },
 		]);
 	}
+	
+	fn coalesced(mut first: StringChange, next: StringChange) -> Option<StringChange> {
+		first.coalesce(next).ok().map(|_| first)
+	}
+	
+	#[test] fn string_coalesce() {
+		// Typing one character after another.
+		assert_eq!(
+			coalesced(StringChange{index: 2, len: 0, new: "a".into()}, StringChange{index: 3, len: 0, new: "b".into()}),
+			Some(StringChange{index: 2, len: 0, new: "ab".into()})
+		);
+		// Backspacing over some of the typed text, and then past it.
+		assert_eq!(
+			coalesced(StringChange{index: 2, len: 0, new: "ab".into()}, StringChange{index: 3, len: 1, new: "".into()}),
+			Some(StringChange{index: 2, len: 0, new: "a".into()})
+		);
+		assert_eq!(
+			coalesced(StringChange{index: 2, len: 1, new: "a".into()}, StringChange{index: 1, len: 2, new: "".into()}),
+			Some(StringChange{index: 1, len: 2, new: "".into()})
+		);
+		// Deleting forwards.
+		assert_eq!(
+			coalesced(StringChange{index: 2, len: 1, new: "".into()}, StringChange{index: 2, len: 1, new: "".into()}),
+			Some(StringChange{index: 2, len: 2, new: "".into()})
+		);
+		// Changes that don't touch can't be merged.
+		assert_eq!(
+			coalesced(StringChange{index: 2, len: 0, new: "a".into()}, StringChange{index: 4, len: 0, new: "b".into()}),
+			None
+		);
+		// Nor can changes that would split a character.
+		assert_eq!(
+			coalesced(StringChange{index: 0, len: 0, new: "é".into()}, StringChange{index: 1, len: 0, new: "b".into()}),
+			None
+		);
+	}
+	
+	#[test] fn string_coalesce_matches_apply() {
+		let original = "0123456789";
+		let changes = (0 .. 8).flat_map(|index| (0 .. 3).flat_map(move |len| {
+			vec!["", "x", "xyz"].into_iter().map(move |new| StringChange{index, len, new: new.into()})
+		})).collect::<Vec<_>>();
+		
+		for first in &changes {
+			for next in &changes {
+				let mut expected = original.to_string();
+				expected.changeable_apply(first.clone(), &mut NoWatcher);
+				if next.index + next.len > expected.len() {
+					continue;
+				}
+				expected.changeable_apply(next.clone(), &mut NoWatcher);
+				
+				if let Some(merged) = coalesced(first.clone(), next.clone()) {
+					let mut actual = original.to_string();
+					actual.changeable_apply(merged.clone(), &mut NoWatcher);
+					assert_eq!(actual, expected, "{:?} + {:?} = {:?}", first, next, merged);
+				}
+			}
+		}
+	}
+	
+	#[test] fn string_transform_converges() {
+		let original = "0123456789";
+		let changes = (0 .. 8).flat_map(|index| (0 .. 3).flat_map(move |len| {
+			vec!["", "x", "yz"].into_iter().map(move |new| StringChange{index, len, new: new.into()})
+		})).collect::<Vec<_>>();
+		
+		for a in &changes {
+			for b in &changes {
+				let mut ab = original.to_string();
+				ab.changeable_apply(a.clone(), &mut NoWatcher);
+				ab.changeable_apply(b.clone().transform(a, Priority::Theirs).unwrap(), &mut NoWatcher);
+				
+				let mut ba = original.to_string();
+				ba.changeable_apply(b.clone(), &mut NoWatcher);
+				ba.changeable_apply(a.clone().transform(b, Priority::Mine).unwrap(), &mut NoWatcher);
+				
+				assert_eq!(ab, ba, "{:?} and {:?}", a, b);
+			}
+		}
+	}
+	
+	#[test] fn string_diff() {
+		let cases = [("", ""), ("", "abc"), ("Hello World", "Hello World"), ("Hello World", "Help Words"), ("é of ü", "ü of é"), ("abc", "")];
+		for &(old, new) in &cases {
+			let mut text = old.to_string();
+			for change in Diff::<StringChange>::diff(&text.clone(), &new.to_string()) {
+				text.changeable_apply(change, &mut NoWatcher);
+			}
+			assert_eq!(text, new);
+		}
+		
+		assert_eq!(Diff::<StringChange>::diff(&"Hello World".to_string(), &"Help Words".to_string()), vec![
+			StringChange{index: 3, len: 2, new: "p".into()},
+			StringChange{index: 8, len: 1, new: "".into()},
+			StringChange{index: 9, len: 0, new: "s".into()},
+		]);
+	}
+	
+	#[test] fn string_transform() {
+		// Concurrent inserts at the same position are ordered by priority.
+		let a = StringChange{index: 5, len: 0, new: "a".into()};
+		let b = StringChange{index: 5, len: 0, new: "b".into()};
+		assert_eq!(a.clone().transform(&b, Priority::Mine), Some(StringChange{index: 5, len: 0, new: "a".into()}));
+		assert_eq!(a.clone().transform(&b, Priority::Theirs), Some(StringChange{index: 6, len: 0, new: "a".into()}));
+		
+		// A change after a replaced range shifts by the difference in length.
+		let replace = StringChange{index: 1, len: 3, new: "Z".into()};
+		assert_eq!(a.transform(&replace, Priority::Mine), Some(StringChange{index: 3, len: 0, new: "a".into()}));
+	}
+	
+	#[test] fn string_change_validation() {
+		let mut spy = SpyWatcher::new();
+		let mut s = "aé😀".to_string();
+		
+		assert_eq!(s.try_changeable_apply(StringChange{index: 2, len: 0, new: "x".into()}, &mut spy), Err(StringChangeError::NotCharBoundary{index: 2}.into()));
+		assert_eq!(s.try_changeable_apply(StringChange{index: 3, len: 2, new: "x".into()}, &mut spy), Err(StringChangeError::NotCharBoundary{index: 5}.into()));
+		assert_eq!(s.try_changeable_apply(StringChange{index: 7, len: 1, new: "x".into()}, &mut spy), Err(StringChangeError::OutOfBounds{index: 7, len: 1, text_len: 7}.into()));
+		assert_eq!(s, "aé😀");
+		
+		let revert = s.try_revertable_apply(StringChange{index: 1, len: 2, new: "e".into()}, &mut spy).unwrap();
+		assert_eq!(s, "ae😀");
+		s.try_changeable_apply(revert, &mut spy).unwrap();
+		assert_eq!(s, "aé😀");
+	}
+	
+	#[test] #[should_panic(expected = "StringChange index 2 is not on a character boundary")]
+	fn string_change_invalid_panics() {
+		let mut s = "aé😀".to_string();
+		s.changeable_apply(StringChange{index: 2, len: 0, new: "x".into()}, &mut NoWatcher);
+	}
+	
+	#[test] fn text_units() {
+		// "e" followed by a combining acute accent is one grapheme made of two chars.
+		let text = "ae\u{301}😀b";
+		assert_eq!(unit_to_byte(text, 2, TextUnit::Char), Some(2));
+		assert_eq!(unit_to_byte(text, 2, TextUnit::Grapheme), Some(4));
+		assert_eq!(unit_to_byte(text, 3, TextUnit::Utf16), Some(4));
+		assert_eq!(unit_to_byte(text, 4, TextUnit::Utf16), None);
+		assert_eq!(unit_to_byte(text, 5, TextUnit::Utf16), Some(8));
+		assert_eq!(unit_to_byte(text, 5, TextUnit::Grapheme), None);
+		assert_eq!(unit_to_byte(text, 3, TextUnit::Byte), None);
+		
+		assert_eq!(byte_to_unit(text, 8, TextUnit::Char), Some(4));
+		assert_eq!(byte_to_unit(text, 8, TextUnit::Utf16), Some(5));
+		assert_eq!(byte_to_unit(text, 8, TextUnit::Grapheme), Some(3));
+		assert_eq!(byte_to_unit(text, 2, TextUnit::Grapheme), None);
+		assert_eq!(convert_index(text, 4, TextUnit::Grapheme, TextUnit::Utf16), Some(6));
+		
+		assert_eq!(
+			StringChange::from_unit_range(text, 1, 1, "o".into(), TextUnit::Grapheme),
+			Ok(StringChange{index: 1, len: 3, new: "o".into()})
+		);
+		assert_eq!(
+			StringChange::from_unit_range(text, 4, 2, "".into(), TextUnit::Grapheme),
+			Err(StringChangeError::OutOfBounds{index: 4, len: 2, text_len: 9})
+		);
+		assert_eq!(
+			StringChange::from_unit_range(text, 1, usize::MAX, "".into(), TextUnit::Byte),
+			Err(StringChangeError::OutOfBounds{index: 1, len: usize::MAX, text_len: 9})
+		);
+		
+		let signal = StringSignal{index: 1, from_len: 3, to_len: 4};
+		assert_eq!(signal.to_unit(text, "a😀😀b", TextUnit::Utf16), Some(StringSignal{index: 1, from_len: 2, to_len: 2}));
+	}
 }
@@ -1,22 +1,48 @@
 use crate::model::{Change, Changeable, Revertable, Watcher};
+use crate::transform::{Transform, Priority};
+use crate::diff::Diff;
+use crate::history::ChangeSize;
 
 use std;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct ValueSignal;
 
 /// The most basic of changes, which just swaps the value with another
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct ValueChange<T: std::cmp::PartialEq>(pub T);
 
 impl<T: 'static + std::cmp::PartialEq + Send> Change for ValueChange<T> {
 	type SignalType = ValueSignal;
 	
+	/// Setting the value twice is the same as only setting it to the second value.
+	fn coalesce(&mut self, next: ValueChange<T>) -> Result<(), ValueChange<T>> {
+		*self = next;
+		Ok(())
+	}
+	
 	/*fn get_signals(&self) -> Vec<Self::SignalType> {
 		vec![ValueSignal]
 	}*/
 }
 
+/// Concurrently setting the same value twice is a conflict, so only the value with priority is
+/// kept.
+impl<T: 'static + std::cmp::PartialEq + Send> Transform for ValueChange<T> {
+	fn transform(self, _other: &ValueChange<T>, priority: Priority) -> Option<ValueChange<T>> {
+		match priority {
+			Priority::Mine => Some(self),
+			Priority::Theirs => None,
+		}
+	}
+}
+
+impl<T: 'static + std::cmp::PartialEq + Send> ChangeSize for ValueChange<T> {}
+
 /// The most basic of changes, which just swaps the value with another of the
 /// same type. This means a type can be used as the change type for itself.
 impl<T: 'static + std::cmp::PartialEq + Send> Revertable<ValueChange<T>> for T {
@@ -42,20 +68,30 @@ impl<T: 'static + std::cmp::PartialEq + Send> Changeable<ValueChange<T>> for T {
 	}
 }
 
+impl<T: 'static + std::cmp::PartialEq + Clone + Send> Diff<ValueChange<T>> for T {
+	fn diff(&self, other: &T) -> Vec<ValueChange<T>> {
+		if self != other {
+			vec![ValueChange(other.clone())]
+		} else {
+			vec![]
+		}
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use model::{SubChangeConstructor, LeafChangeConstructor, SpyWatcher};
+	use crate::model::SpyWatcher;
 	
 	#[test] fn value_change() {
 		let mut spy = SpyWatcher::new();
-	
+		
 		let mut v = 6;
-		let revert = v.revertable_apply(ValueChange::Set(8), &mut spy);
+		let revert = v.revertable_apply(ValueChange(8), &mut spy);
 		assert_eq!(v, 8);
 		v.revertable_apply(revert, &mut spy);
 		assert_eq!(v, 6);
-		v.changeable_apply(ValueChange::Set(8), &mut spy);
+		v.changeable_apply(ValueChange(8), &mut spy);
 		assert_eq!(v, 8);
 		
 		assert_eq!(spy.signals, vec![ValueSignal, ValueSignal, ValueSignal]);
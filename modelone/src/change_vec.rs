@@ -1,8 +1,15 @@
-use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor};
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor, ApplyError, ApplyErrorKind};
 use crate::change_value::{ValueChange, ValueSignal};
+use crate::transform::{Transform, Priority};
+use crate::diff::{Diff, diff_ranges};
+use crate::history::ChangeSize;
 
 use std;
+use std::ops::Range;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum VecSignal<ST> {
 	/// Set the value at `index` to `item`
@@ -14,7 +21,15 @@ pub enum VecSignal<ST> {
 	/// Replace the entire vector with a different one
 	ReplaceAll,
 	/// Apply the given `change` to the item at `index`
-	At{index: usize, signal: ST}
+	At{index: usize, signal: ST},
+	/// Move the item at `from` so that it ends up at `to`
+	Move{from: usize, to: usize},
+	/// Swap the items at `a` and `b`
+	Swap{a: usize, b: usize},
+	/// Insert `len` items starting at `index`
+	InsertRange{index: usize, len: usize},
+	/// Remove the items in `range`
+	RemoveRange{range: Range<usize>},
 }
 
 impl<ST> VecSignal<ST> {
@@ -37,6 +52,26 @@ impl<ST> VecSignal<ST> {
 			VecSignal::At{index, ..} => {
 				visitor(index);
 			}
+			VecSignal::Move{from, to} => {
+				// Every item between from and to shifts along by one.
+				for index in from.min(to)..=from.max(to) {
+					visitor(index);
+				}
+			}
+			VecSignal::Swap{a, b} => {
+				visitor(a);
+				visitor(b);
+			}
+			VecSignal::InsertRange{index, len} => {
+				for index in index..index + len {
+					visitor(index);
+				}
+			}
+			VecSignal::RemoveRange{ref range} => {
+				for index in range.clone() {
+					visitor(index);
+				}
+			}
 		}
 	}
 }
@@ -45,6 +80,7 @@ impl<ST> VecSignal<ST> {
 // TODO: This change could have a method which returns an iterator over all indices
 // that have changed so listeners don't have to implement all of the possible
 // changes
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum VecChange<T: Changeable<C>, C: Change> {
 	/// Set the value at `index` to `item`
@@ -56,12 +92,38 @@ pub enum VecChange<T: Changeable<C>, C: Change> {
 	/// Replace the entire vector with a different one
 	ReplaceAll(Vec<T>),
 	/// Apply the given `change` to the item at `index`
-	At{index: usize, change: C}
+	At{index: usize, change: C},
+	/// Move the item at `from` so that it ends up at `to`
+	Move{from: usize, to: usize},
+	/// Swap the items at `a` and `b`
+	Swap{a: usize, b: usize},
+	/// Insert all of `items` starting at `index`
+	InsertRange{index: usize, items: Vec<T>},
+	/// Remove the items in `range`
+	RemoveRange{range: Range<usize>},
 }
 
 impl<T: 'static + Changeable<C> + Send, C: Change> Change for VecChange<T, C> {
 	type SignalType = VecSignal<C::SignalType>;
 	
+	fn coalesce(&mut self, next: VecChange<T, C>) -> Result<(), VecChange<T, C>> {
+		use self::VecChange::*;
+		match (self, next) {
+			(At{index, change}, At{index: next_index, change: next_change}) if *index == next_index => {
+				change.coalesce(next_change).map_err(|next_change| At{index: next_index, change: next_change})
+			}
+			(Set{index, item}, Set{index: next_index, item: next_item}) if *index == next_index => {
+				*item = next_item;
+				Ok(())
+			}
+			(this @ ReplaceAll(_), next @ ReplaceAll(_)) => {
+				*this = next;
+				Ok(())
+			}
+			(_, next) => Err(next),
+		}
+	}
+	
 	/*fn get_signals(&self) -> Vec<Self::SignalType> {
 		use VecChange::*;
 		match *self {
@@ -74,7 +136,9 @@ impl<T: 'static + Changeable<C> + Send, C: Change> Change for VecChange<T, C> {
 	}*/
 }
 
-impl<T: Revertable<C>, C: Change> VecChange<T, C> {
+impl<T: Changeable<C>, C: Change> VecChange<T, C> {
+	/// Returns where the item at `index_reference` will be after this change is applied, or None if
+	/// this change removes it.
 	pub fn updated_reference(&self, index_reference: Option<usize>) -> Option<usize> {
 		if let Some(ref_index) = index_reference {
 			use self::VecChange::*;
@@ -95,6 +159,42 @@ impl<T: Revertable<C>, C: Change> VecChange<T, C> {
 						Some(ref_index)
 					}
 				},
+				Move{from, to} => {
+					if from == ref_index {
+						Some(to)
+					} else if from < ref_index && ref_index <= to {
+						Some(ref_index - 1)
+					} else if to <= ref_index && ref_index < from {
+						Some(ref_index + 1)
+					} else {
+						Some(ref_index)
+					}
+				},
+				Swap{a, b} => {
+					if a == ref_index {
+						Some(b)
+					} else if b == ref_index {
+						Some(a)
+					} else {
+						Some(ref_index)
+					}
+				},
+				InsertRange{index, ref items} => {
+					if index <= ref_index {
+						Some(ref_index + items.len())
+					} else {
+						Some(ref_index)
+					}
+				},
+				RemoveRange{ref range} => {
+					if range.contains(&ref_index) {
+						None
+					} else if range.end <= ref_index {
+						Some(ref_index - range.len())
+					} else {
+						Some(ref_index)
+					}
+				},
 				_ => Some(ref_index)
 			}
 		} else {
@@ -103,6 +203,125 @@ impl<T: Revertable<C>, C: Change> VecChange<T, C> {
 	}
 }
 
+impl<T: Changeable<C>, C: Change> VecChange<T, C> {
+	/// Returns where the gap before the item at `position` will be after this change is applied.
+	/// `before_inserts` decides whether the gap goes before or after items that this change inserts
+	/// at that exact position. A position inside a removed range ends up where the range was.
+	/// Returns None for ReplaceAll, since nothing about the vector is known after it.
+	fn updated_position(&self, position: usize, before_inserts: bool) -> Option<usize> {
+		use self::VecChange::*;
+		let inserted_before = |index: usize| index < position || (index == position && !before_inserts);
+		match *self {
+			Insert{index, ..} if inserted_before(index) => Some(position + 1),
+			InsertRange{index, ref items} if inserted_before(index) => Some(position + items.len()),
+			Remove{index} if index < position => Some(position - 1),
+			RemoveRange{ref range} if range.end <= position => Some(position - range.len()),
+			RemoveRange{ref range} if range.start < position => Some(range.start),
+			Move{from, to} if from < position && to >= position => Some(position - 1),
+			Move{from, to} if from >= position && to < position => Some(position + 1),
+			ReplaceAll(..) => None,
+			_ => Some(position),
+		}
+	}
+	
+	/// Returns true if this change moves an item into or out of `range`.
+	fn moves_across(&self, range: &Range<usize>) -> bool {
+		use self::VecChange::*;
+		match *self {
+			Move{from, to} => range.contains(&from) != range.contains(&to),
+			Swap{a, b} => range.contains(&a) != range.contains(&b),
+			_ => false,
+		}
+	}
+}
+
+impl<T: 'static + Changeable<C> + Send, C: ChangeSize> ChangeSize for VecChange<T, C> {
+	fn estimated_size(&self) -> usize {
+		use self::VecChange::*;
+		std::mem::size_of::<VecChange<T, C>>() + match *self {
+			ReplaceAll(ref items) | InsertRange{ref items, ..} => items.len() * std::mem::size_of::<T>(),
+			At{ref change, ..} => change.estimated_size(),
+			_ => 0,
+		}
+	}
+}
+
+/// Changes to separate items just have their indices updated, and changes to an item that was
+/// concurrently removed or replaced are dropped. ReplaceAll wins over everything else. Items
+/// inserted inside a concurrently removed range are removed along with it.
+///
+/// A range removal that a concurrent Move or Swap moved an item into or out of can't be expressed
+/// as a single change, so it is dropped. Moves and swaps follow the items they refer to, but
+/// concurrent reorderings are not guaranteed to end up in the same order.
+impl<T: 'static + Changeable<C> + Send, C: Transform> Transform for VecChange<T, C> {
+	fn transform(self, other: &VecChange<T, C>, priority: Priority) -> Option<VecChange<T, C>> {
+		use self::VecChange::*;
+		match (self, other) {
+			(ReplaceAll(items), &ReplaceAll(_)) => {
+				match priority {
+					Priority::Mine => Some(ReplaceAll(items)),
+					Priority::Theirs => None,
+				}
+			}
+			(ReplaceAll(items), _) => Some(ReplaceAll(items)),
+			(_, &ReplaceAll(_)) => None,
+			(Set{index, item}, &Set{index: other_index, ..}) if index == other_index => {
+				match priority {
+					Priority::Mine => Some(Set{index, item}),
+					Priority::Theirs => None,
+				}
+			}
+			(At{index, ..}, &Set{index: other_index, ..}) if index == other_index => None,
+			(At{index, change}, &At{index: other_index, change: ref other_change}) if index == other_index => {
+				change.transform(other_change, priority).map(|change| At{index, change})
+			}
+			(At{index, change}, _) => {
+				other.updated_reference(Some(index)).map(|index| At{index, change})
+			}
+			(Set{index, item}, _) => {
+				other.updated_reference(Some(index)).map(|index| Set{index, item})
+			}
+			(Remove{index}, _) => {
+				other.updated_reference(Some(index)).map(|index| Remove{index})
+			}
+			(Swap{a, b}, _) => {
+				Some(Swap{a: other.updated_reference(Some(a))?, b: other.updated_reference(Some(b))?})
+			}
+			(Move{from, to}, _) => {
+				Some(Move{from: other.updated_reference(Some(from))?, to: other.updated_reference(Some(to))?})
+			}
+			(Insert{index, item}, _) => {
+				if let RemoveRange{ref range} = *other {
+					if range.start < index && index < range.end {
+						return None;
+					}
+				}
+				other.updated_position(index, priority == Priority::Mine).map(|index| Insert{index, item})
+			}
+			(InsertRange{index, items}, _) => {
+				if let RemoveRange{ref range} = *other {
+					if range.start < index && index < range.end {
+						return None;
+					}
+				}
+				other.updated_position(index, priority == Priority::Mine).map(|index| InsertRange{index, items})
+			}
+			(RemoveRange{range}, _) => {
+				if other.moves_across(&range) {
+					return None;
+				}
+				let start = other.updated_position(range.start, false)?;
+				let end = other.updated_position(range.end, true)?;
+				if start < end {
+					Some(RemoveRange{range: start .. end})
+				} else {
+					None
+				}
+			}
+		}
+	}
+}
+
 impl<T: 'static + Changeable<C> + Send, C: Change> Changeable<VecChange<T, C>> for Vec<T> {
 	fn changeable_apply(&mut self, change: VecChange<T, C>, watcher: &mut Watcher<VecSignal<C::SignalType>>) {
 		use self::VecChange::*;
@@ -137,12 +356,85 @@ impl<T: 'static + Changeable<C> + Send, C: Change> Changeable<VecChange<T, C>> f
 				};
 				self[index].changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
 			},
+			Move{from, to} => {
+				move_item(self, from, to);
+				watcher.send_signal(VecSignal::Move{from, to});
+			},
+			Swap{a, b} => {
+				self.swap(a, b);
+				watcher.send_signal(VecSignal::Swap{a, b});
+			},
+			InsertRange{index, items} => {
+				let len = items.len();
+				self.splice(index..index, items);
+				watcher.send_signal(VecSignal::InsertRange{index, len});
+			},
+			RemoveRange{range} => {
+				self.drain(range.clone());
+				watcher.send_signal(VecSignal::RemoveRange{range});
+			},
 		}
 	}
 	
 	fn reset_view_signals(&self) -> Vec<VecSignal<C::SignalType>> {
 		vec![VecSignal::ReplaceAll]
 	}
+	
+	fn validate_change(&self, change: &VecChange<T, C>) -> Result<(), ApplyError> {
+		use self::VecChange::*;
+		let check_index = |index: usize, len: usize| {
+			if index < len {
+				Ok(())
+			} else {
+				Err(ApplyError::new(ApplyErrorKind::IndexOutOfBounds{index, len}))
+			}
+		};
+		match *change {
+			Set{index, ..} | Remove{index} => check_index(index, self.len()),
+			// Items can be inserted at the end, so the index can be the length itself.
+			Insert{index, ..} | InsertRange{index, ..} => check_index(index, self.len() + 1),
+			ReplaceAll(..) => Ok(()),
+			At{index, ref change} => {
+				check_index(index, self.len())?;
+				self[index].validate_change(change).map_err(|err| err.within(format!("[{}]", index)))
+			}
+			Move{from: a, to: b} | Swap{a, b} => {
+				check_index(a, self.len())?;
+				check_index(b, self.len())
+			}
+			RemoveRange{ref range} => {
+				check_index(range.start.max(range.end), self.len() + 1)?;
+				check_index(range.start, range.end + 1)
+			}
+		}
+	}
+}
+
+/// Items that are in both vectors in the same order are kept, based on their longest common
+/// subsequence. Where items were replaced, each old item is diffed with the new item in its place
+/// using At, and the rest are removed or inserted.
+impl<T: 'static + Diff<C> + Clone + PartialEq + Send, C: Change> Diff<VecChange<T, C>> for Vec<T> {
+	fn diff(&self, other: &Vec<T>) -> Vec<VecChange<T, C>> {
+		let mut changes = vec![];
+		for (old_range, new_range) in diff_ranges(self, other) {
+			// The items before new_range.start already match `other` once the earlier changes have
+			// been applied
+			let replaced = std::cmp::min(old_range.len(), new_range.len());
+			for offset in 0..replaced {
+				let index = new_range.start + offset;
+				let item_changes = self[old_range.start + offset].diff(&other[index]);
+				changes.extend(item_changes.into_iter().map(|change| VecChange::At{index, change}));
+			}
+			for _ in replaced..old_range.len() {
+				changes.push(VecChange::Remove{index: new_range.start + replaced});
+			}
+			let inserted = new_range.start + replaced..new_range.end;
+			for (index, item) in inserted.clone().zip(&other[inserted]) {
+				changes.push(VecChange::Insert{index, item: item.clone()});
+			}
+		}
+		changes
+	}
 }
 
 impl<T: 'static + Revertable<C> + Send, C: Change> Revertable<VecChange<T, C>> for Vec<T> {
@@ -156,11 +448,12 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Revertable<VecChange<T, C>> f
 			},
 			Insert{index, item} => {
 				self.insert(index, item);
+				watcher.send_signal(VecSignal::Insert{index});
 				Remove{index}
 			},
 			Remove{index} => {
 				let item = self.remove(index);
-				watcher.send_signal(VecSignal::Insert{index});
+				watcher.send_signal(VecSignal::Remove{index});
 				Insert{index, item}
 			},
 			ReplaceAll(mut all) => {
@@ -175,10 +468,40 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Revertable<VecChange<T, C>> f
 				let revertchange = self[index].revertable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
 				At { index, change: revertchange }
 			},
+			Move{from, to} => {
+				move_item(self, from, to);
+				watcher.send_signal(VecSignal::Move{from, to});
+				Move{from: to, to: from}
+			},
+			Swap{a, b} => {
+				self.swap(a, b);
+				watcher.send_signal(VecSignal::Swap{a, b});
+				Swap{a, b}
+			},
+			InsertRange{index, items} => {
+				let len = items.len();
+				self.splice(index..index, items);
+				watcher.send_signal(VecSignal::InsertRange{index, len});
+				RemoveRange{range: index..index + len}
+			},
+			RemoveRange{range} => {
+				let items = self.drain(range.clone()).collect();
+				watcher.send_signal(VecSignal::RemoveRange{range: range.clone()});
+				InsertRange{index: range.start, items}
+			},
 		}
 	}
 }
 
+/// Moves the item at `from` so that it ends up at `to`, shifting the items in between.
+fn move_item<T>(items: &mut [T], from: usize, to: usize) {
+	if from < to {
+		items[from..=to].rotate_left(1);
+	} else if to < from {
+		items[to..=from].rotate_right(1);
+	}
+}
+
 pub struct VecChangeConstructor<C: Change> {
 	index: usize,
 	sub: Box<ChangeConstructor<C>>,
@@ -209,10 +532,6 @@ impl<T: 'static + Changeable<C>, C: Change> ChangeConstructor<VecChange<T, C>> f
 				self.index += 1;
 				true
 			}
-			/*VecChange::Move(from, to) if from == self.index => {
-				self.index = to;
-				true
-			}*/
 			Remove{index} => {
 				if self.index == index {
 					false
@@ -223,6 +542,14 @@ impl<T: 'static + Changeable<C>, C: Change> ChangeConstructor<VecChange<T, C>> f
 					true
 				}
 			}
+			Move{..} | Swap{..} | InsertRange{..} | RemoveRange{..} => {
+				if let Some(index) = change.updated_reference(Some(self.index)) {
+					self.index = index;
+					true
+				} else {
+					false
+				}
+			}
 			ReplaceAll{..} => {
 				false
 			}
@@ -233,6 +560,17 @@ impl<T: 'static + Changeable<C>, C: Change> ChangeConstructor<VecChange<T, C>> f
 		}
 	}
 	
+	fn affects(&self, change: &VecChange<T, C>) -> bool {
+		use self::VecChange::*;
+		match *change {
+			Set{index, ..} | Remove{index} => index == self.index,
+			RemoveRange{..} => change.updated_reference(Some(self.index)).is_none(),
+			ReplaceAll{..} => true,
+			At{index, ref change} => index == self.index && self.sub.affects(change),
+			Insert{..} | Move{..} | Swap{..} | InsertRange{..} => false,
+		}
+	}
+	
 	fn debug_string(&self) -> String {
 		format!("[{}]/{}", self.index, self.sub.debug_string())
 	}
@@ -244,7 +582,7 @@ pub type ValueVecSignal = VecSignal<ValueSignal>;
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use model::{SubChangeConstructor, LeafChangeConstructor, SpyWatcher};
+	use crate::model::{LeafChangeConstructor, SpyWatcher, NoWatcher};
 	
 	#[test] fn change_vec() {
 		let mut spy = SpyWatcher::new();
@@ -270,8 +608,143 @@ mod tests {
 		]);
 	}
 	
+	#[test] fn change_vec_insert_remove() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut v: Vec<i32> = vec![0, 1, 2];
+		let revert1 = v.revertable_apply(ValueVecChange::Insert{index: 1, item: 7}, &mut spy);
+		assert_eq!(v, vec![0, 7, 1, 2]);
+		let revert2 = v.revertable_apply(ValueVecChange::Remove{index: 3}, &mut spy);
+		assert_eq!(v, vec![0, 7, 1]);
+		v.revertable_apply(revert2, &mut spy);
+		v.revertable_apply(revert1, &mut spy);
+		assert_eq!(v, vec![0, 1, 2]);
+		
+		assert_eq!(spy.signals, vec![
+			VecSignal::Insert{index: 1},
+			VecSignal::Remove{index: 3},
+			VecSignal::Insert{index: 3},
+			VecSignal::Remove{index: 1},
+		]);
+	}
+	
+	#[test] fn change_vec_reorder() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut v: Vec<i32> = vec![0, 1, 2, 3, 4, 5];
+		let revert1 = v.revertable_apply(ValueVecChange::Move{from: 1, to: 4}, &mut spy);
+		assert_eq!(v, vec![0, 2, 3, 4, 1, 5]);
+		let revert2 = v.revertable_apply(ValueVecChange::Swap{a: 0, b: 5}, &mut spy);
+		assert_eq!(v, vec![5, 2, 3, 4, 1, 0]);
+		let revert3 = v.revertable_apply(ValueVecChange::InsertRange{index: 2, items: vec![7, 8]}, &mut spy);
+		assert_eq!(v, vec![5, 2, 7, 8, 3, 4, 1, 0]);
+		let revert4 = v.revertable_apply(ValueVecChange::RemoveRange{range: 1..4}, &mut spy);
+		assert_eq!(v, vec![5, 3, 4, 1, 0]);
+		v.revertable_apply(revert4, &mut spy);
+		v.revertable_apply(revert3, &mut spy);
+		v.revertable_apply(revert2, &mut spy);
+		v.revertable_apply(revert1, &mut spy);
+		assert_eq!(v, vec![0, 1, 2, 3, 4, 5]);
+		
+		assert_eq!(spy.signals, vec![
+			VecSignal::Move{from: 1, to: 4},
+			VecSignal::Swap{a: 0, b: 5},
+			VecSignal::InsertRange{index: 2, len: 2},
+			VecSignal::RemoveRange{range: 1..4},
+			VecSignal::InsertRange{index: 1, len: 3},
+			VecSignal::RemoveRange{range: 2..4},
+			VecSignal::Swap{a: 0, b: 5},
+			VecSignal::Move{from: 4, to: 1},
+		]);
+	}
+	
+	#[test] fn vec_updated_reference() {
+		assert_eq!(ValueVecChange::<i32>::Move{from: 1, to: 4}.updated_reference(Some(1)), Some(4));
+		assert_eq!(ValueVecChange::<i32>::Move{from: 1, to: 4}.updated_reference(Some(3)), Some(2));
+		assert_eq!(ValueVecChange::<i32>::Move{from: 4, to: 1}.updated_reference(Some(3)), Some(4));
+		assert_eq!(ValueVecChange::<i32>::Swap{a: 2, b: 5}.updated_reference(Some(5)), Some(2));
+		assert_eq!(ValueVecChange::<i32>::InsertRange{index: 2, items: vec![0, 0]}.updated_reference(Some(2)), Some(4));
+		assert_eq!(ValueVecChange::<i32>::RemoveRange{range: 1..3}.updated_reference(Some(2)), None);
+		assert_eq!(ValueVecChange::<i32>::RemoveRange{range: 1..3}.updated_reference(Some(5)), Some(3));
+	}
+	
 	#[test] fn vec_change_constructor() {
 		let lcc = LeafChangeConstructor::<ValueChange<u32>>::new();
-		let vcc = VecChangeConstructor::<ValueChange<u32>>::new(12, Box::new(lcc));
+		let mut vcc = VecChangeConstructor::<ValueChange<u32>>::new(12, Box::new(lcc));
+		
+		assert!(ChangeConstructor::<ValueVecChange<u32>>::update(&mut vcc, &VecChange::Move{from: 12, to: 3}));
+		assert_eq!(ChangeConstructor::<ValueVecChange<u32>>::debug_string(&vcc), "[3]/?");
+		assert!(ChangeConstructor::<ValueVecChange<u32>>::update(&mut vcc, &VecChange::InsertRange{index: 0, items: vec![1, 2]}));
+		assert_eq!(ChangeConstructor::<ValueVecChange<u32>>::debug_string(&vcc), "[5]/?");
+		assert!(!ChangeConstructor::<ValueVecChange<u32>>::update(&mut vcc, &VecChange::RemoveRange{range: 4..6}));
+	}
+	
+	#[test] fn vec_transform_converges() {
+		let original: Vec<i32> = vec![0, 1, 2, 3, 4];
+		let mut changes: Vec<ValueVecChange<i32>> = vec![ValueVecChange::ReplaceAll(vec![10])];
+		for index in 0 .. 5 {
+			changes.push(VecChange::Set{index, item: 20 + index as i32});
+			changes.push(VecChange::At{index, change: ValueChange(30 + index as i32)});
+			changes.push(VecChange::Remove{index});
+		}
+		for index in 0 ..= 5 {
+			changes.push(VecChange::Insert{index, item: 40 + index as i32});
+			changes.push(VecChange::InsertRange{index, items: vec![50, 51]});
+			for end in index + 1 ..= 5 {
+				changes.push(VecChange::RemoveRange{range: index .. end});
+			}
+		}
+		
+		let apply = |v: &mut Vec<i32>, change: Option<ValueVecChange<i32>>| {
+			if let Some(change) = change {
+				v.changeable_apply(change, &mut NoWatcher);
+			}
+		};
+		for a in &changes {
+			for b in &changes {
+				let mut ab = original.clone();
+				apply(&mut ab, Some(a.clone()));
+				apply(&mut ab, b.clone().transform(a, Priority::Theirs));
+				
+				let mut ba = original.clone();
+				apply(&mut ba, Some(b.clone()));
+				apply(&mut ba, a.clone().transform(b, Priority::Mine));
+				
+				assert_eq!(ab, ba, "{:?} and {:?}", a, b);
+			}
+		}
+	}
+	
+	#[test] fn vec_diff() {
+		use crate::change_string::{StringChange, StringSignal};
+		
+		let mut v: Vec<String> = vec!["a".into(), "b".into(), "c".into(), "d".into()];
+		let edited: Vec<String> = vec!["b".into(), "cat".into(), "x".into(), "y".into(), "d".into()];
+		let changes = Diff::<VecChange<String, StringChange>>::diff(&v, &edited);
+		assert_eq!(changes, vec![
+			VecChange::Remove{index: 0},
+			VecChange::At{index: 1, change: StringChange{index: 1, len: 0, new: "at".into()}},
+			VecChange::Insert{index: 2, item: "x".into()},
+			VecChange::Insert{index: 3, item: "y".into()},
+		]);
+		
+		let mut spy = SpyWatcher::new();
+		for change in changes {
+			v.changeable_apply(change, &mut spy);
+		}
+		assert_eq!(v, edited);
+		assert_eq!(spy.signals[1], VecSignal::At{index: 1, signal: StringSignal{index: 1, from_len: 0, to_len: 2}});
+		assert!(Diff::<VecChange<String, StringChange>>::diff(&v, &edited).is_empty());
+	}
+	
+	#[test] fn vec_transform_reorder() {
+		let remove = ValueVecChange::<i32>::Remove{index: 1};
+		assert_eq!(ValueVecChange::<i32>::Move{from: 3, to: 0}.transform(&remove, Priority::Mine), Some(VecChange::Move{from: 2, to: 0}));
+		assert_eq!(ValueVecChange::<i32>::Swap{a: 1, b: 2}.transform(&remove, Priority::Mine), None);
+		
+		let swap = ValueVecChange::<i32>::Swap{a: 0, b: 4};
+		assert_eq!(ValueVecChange::<i32>::At{index: 4, change: ValueChange(1)}.transform(&swap, Priority::Mine), Some(VecChange::At{index: 0, change: ValueChange(1)}));
+		assert_eq!(ValueVecChange::<i32>::RemoveRange{range: 1 .. 3}.transform(&swap, Priority::Mine), Some(VecChange::RemoveRange{range: 1 .. 3}));
+		assert_eq!(ValueVecChange::<i32>::RemoveRange{range: 0 .. 3}.transform(&swap, Priority::Mine), None);
 	}
 }
@@ -0,0 +1,191 @@
+use crate::model::{Change, Changeable};
+
+use std::cmp;
+use std::ops::Range;
+
+/// Computes the changes that turn one value into another, so that a new value from outside (such
+/// as a file that was edited elsewhere) can be applied to a model with signals for only the parts
+/// that differ, rather than replacing the whole model.
+pub trait Diff<C: Change>: Changeable<C> {
+	/// Returns the changes that turn `self` into `other` when they are applied in order. Returns
+	/// no changes if the values are already the same.
+	fn diff(&self, other: &Self) -> Vec<C>;
+}
+
+/// The differing middle of two slices is compared using a table of this many cells at most, which
+/// takes time and memory proportional to the product of their lengths. Past that, the whole
+/// middle is treated as changed.
+const MAX_LCS_CELLS: usize = 1 << 22;
+
+/// Finds the parts of `old` that need replacing to turn it into `new`, based on their longest
+/// common subsequence. Returns the ranges of `old` and the ranges of `new` that replace them, in
+/// order. Everything outside the ranges is the same in both.
+pub(crate) fn diff_ranges<T: PartialEq>(old: &[T], new: &[T]) -> Vec<(Range<usize>, Range<usize>)> {
+	let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+	let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+	let old_mid = &old[prefix..old.len() - suffix];
+	let new_mid = &new[prefix..new.len() - suffix];
+	
+	if old_mid.is_empty() && new_mid.is_empty() {
+		return vec![];
+	}
+	if old_mid.len().saturating_mul(new_mid.len()) > MAX_LCS_CELLS {
+		return vec![(prefix..old.len() - suffix, prefix..new.len() - suffix)];
+	}
+	
+	// lengths[i * width + j] is the length of the longest common subsequence of old_mid[i..] and
+	// new_mid[j..]
+	let width = new_mid.len() + 1;
+	let mut lengths = vec![0u32; (old_mid.len() + 1) * width];
+	for i in (0..old_mid.len()).rev() {
+		for j in (0..new_mid.len()).rev() {
+			lengths[i * width + j] = if old_mid[i] == new_mid[j] {
+				lengths[(i + 1) * width + j + 1] + 1
+			} else {
+				cmp::max(lengths[(i + 1) * width + j], lengths[i * width + j + 1])
+			};
+		}
+	}
+	
+	let mut ranges = vec![];
+	let (mut i, mut j) = (0, 0);
+	let mut start = None;
+	while i < old_mid.len() || j < new_mid.len() {
+		if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+			if let Some((old_start, new_start)) = start.take() {
+				ranges.push((prefix + old_start..prefix + i, prefix + new_start..prefix + j));
+			}
+			i += 1;
+			j += 1;
+			continue;
+		}
+		
+		if start.is_none() {
+			start = Some((i, j));
+		}
+		if j == new_mid.len() || (i < old_mid.len() && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1]) {
+			i += 1;
+		} else {
+			j += 1;
+		}
+	}
+	if let Some((old_start, new_start)) = start {
+		ranges.push((prefix + old_start..prefix + i, prefix + new_start..prefix + j));
+	}
+	ranges
+}
+
+/// Implements Diff for the model of `impl_changeable_struct!`, when it's given `with Diff`, by
+/// diffing each field in turn. The fields' types must implement Diff for their change types.
+#[doc(hidden)]
+#[macro_export] macro_rules! impl_diff_struct{
+	($change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)+
+	) => {
+		impl $crate::diff::Diff<$change_name> for $model_name {
+			fn diff(&self, other: &$model_name) -> Vec<$change_name> {
+				let mut changes = vec![];
+				$(
+					changes.extend($crate::diff::Diff::<$change_type>::diff(&self.$field_name, &other.$field_name).into_iter().map($change_name::$field_name));
+				)*
+				changes
+			}
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::SpyWatcher;
+	use crate::change_value::{ValueChange, ValueSignal};
+	use crate::change_option::{OptionChange, OptionSignal};
+	use crate::change_string::{StringChange, StringSignal};
+	use crate::change_vec::VecChange;
+	use crate::Changeable;
+	use crate::Diff;
+	
+	#[derive(Debug, Clone, PartialEq)]
+	struct Document {
+		title: String,
+		tags: Vec<String>,
+		author: Option<String>,
+	}
+	
+	impl_revertable_struct!{DocumentChange[DocumentSignal] for Document with Diff, Transform:
+		title: StringChange,
+		tags: VecChange<String, StringChange>,
+		author: OptionChange<String, StringChange>,
+	}
+	
+	#[derive(Debug, Clone, PartialEq, Changeable, Diff)]
+	struct Pair<T: 'static + PartialEq + Clone + Send> {
+		#[change(ValueChange<T>)]
+		first: T,
+		#[change(ValueChange<T>)]
+		second: T,
+	}
+	
+	#[test] fn ranges_between_slices() {
+		assert_eq!(diff_ranges(b"abc", b"abc"), vec![]);
+		assert_eq!(diff_ranges(b"abcdef", b"axcdyyf"), vec![(1..2, 1..2), (4..5, 4..6)]);
+		assert_eq!(diff_ranges(b"abc", b""), vec![(0..3, 0..0)]);
+		assert_eq!(diff_ranges(b"", b"ab"), vec![(0..0, 0..2)]);
+	}
+	
+	#[test] fn value_and_option_diff() {
+		assert_eq!(Diff::<ValueChange<u32>>::diff(&1, &1), vec![]);
+		assert_eq!(Diff::<ValueChange<u32>>::diff(&1, &2), vec![ValueChange(2)]);
+		
+		let none: Option<String> = None;
+		let hello = Some("Hello".to_string());
+		assert_eq!(Diff::<OptionChange<String, StringChange>>::diff(&none, &none), vec![]);
+		assert_eq!(Diff::<OptionChange<String, StringChange>>::diff(&hello, &none), vec![OptionChange::Reset(None)]);
+		assert_eq!(
+			Diff::<OptionChange<String, StringChange>>::diff(&hello, &Some("Help".into())),
+			vec![OptionChange::Change(StringChange{index: 3, len: 2, new: "p".into()})]
+		);
+	}
+	
+	#[test] fn diff_struct() {
+		let mut document = Document {
+			title: "Notes".into(),
+			tags: vec!["a".into(), "b".into()],
+			author: Some("Jo".into()),
+		};
+		let edited = Document {
+			title: "My notes".into(),
+			tags: vec!["a".into(), "b".into()],
+			author: None,
+		};
+		
+		let changes = Diff::<DocumentChange>::diff(&document, &edited);
+		assert_eq!(changes, vec![
+			DocumentChange::title(StringChange{index: 0, len: 1, new: "My n".into()}),
+			DocumentChange::author(OptionChange::Reset(None)),
+		]);
+		
+		let mut spy = SpyWatcher::new();
+		for change in changes {
+			document.changeable_apply(change, &mut spy);
+		}
+		assert_eq!(document, edited);
+		assert_eq!(spy.signals, vec![
+			DocumentSignal::title(StringSignal{index: 0, from_len: 1, to_len: 4}),
+			DocumentSignal::author(OptionSignal::Reset),
+		]);
+	}
+	
+	#[test] fn derived_diff() {
+		let pair = Pair { first: 1, second: 2 };
+		assert_eq!(Diff::<PairChange<i32>>::diff(&pair, &Pair { first: 1, second: 3 }), vec![PairChange::second(ValueChange(3))]);
+		
+		let mut spy = SpyWatcher::new();
+		let mut pair = pair;
+		for change in Diff::<PairChange<i32>>::diff(&pair, &Pair { first: 4, second: 5 }) {
+			pair.changeable_apply(change, &mut spy);
+		}
+		assert_eq!(pair, Pair { first: 4, second: 5 });
+		assert_eq!(spy.signals, vec![PairSignal::first(ValueSignal), PairSignal::second(ValueSignal)]);
+	}
+}
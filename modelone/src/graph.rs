@@ -1,10 +1,4 @@
 /*
-Nodes could be serialized by converting id and gen to a string like "5+9", so they can be stored as
-a JSON map of string -> object in the graph, and they deserialize directly to the node reference's
-data.
-
---
-
 To do a reference system where all references update, the UI can be initalised with the graph, and
 any references can create an ApplyHandle and give it to some kind of graph handler. When a
 GraphSignal is emitted, the UI can look up all ApplyHandles associated with the signalling node, and
@@ -12,213 +6,767 @@ call them. This means the graph itself doesn't need to deal with the reference u
 the UI handles all "cached" data, and all connections between nodes that need to be updated.
 */
 
-struct NodeRef<T> {
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ChangeConstructor, ApplyError, ApplyErrorKind};
+use crate::history::ChangeSize;
+
+use std;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::de::Error as DeError;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+
+/// A reference to a node in a [`Graph`](struct.Graph.html). The generation tells apart the nodes
+/// that have used the same slot, so a reference to a node that has been removed stays invalid
+/// even after its slot is reused.
+pub struct NodeRef<T> {
 	id: usize,
 	gen: usize,
+	_node: PhantomData<fn() -> T>,
 }
 
 impl<T> NodeRef<T> {
-	fn get(&self, graph: &Graph) -> &T {
-		graph.get(self);
+	pub fn new(id: usize, gen: usize) -> NodeRef<T> {
+		NodeRef {
+			id,
+			gen,
+			_node: PhantomData,
+		}
+	}
+	
+	pub fn id(&self) -> usize {
+		self.id
 	}
+	
+	pub fn gen(&self) -> usize {
+		self.gen
+	}
+	
+	/// Returns the node this refers to, or None if it has been removed.
+	pub fn get<'g, J>(&self, graph: &'g Graph<T, J>) -> Option<&'g T> {
+		graph.get(*self)
+	}
+}
 
-	fn get_mut(&self, graph: &mut Graph) -> Option<&mut T> {
-		graph.get_mut(self);
+// These are implemented by hand so that they don't need the same traits on T
+impl<T> Clone for NodeRef<T> {
+	fn clone(&self) -> NodeRef<T> {
+		*self
 	}
 }
 
-/*pub struct TypedRef<T>(NodeRef);
+impl<T> Copy for NodeRef<T> {}
 
-impl<T> TypedRef<T> {
-	fn get(&self, graph: &Graph) -> &T {
-		self.0.get(graph).as_any().downcast_ref().unwrap()
+impl<T> PartialEq for NodeRef<T> {
+	fn eq(&self, other: &NodeRef<T>) -> bool {
+		self.id == other.id && self.gen == other.gen
 	}
+}
 
-	fn get_mut(&self, graph: &mut Graph) -> Option<&mut T> {
-		self.0.get_mut(graph).as_any_mut().downcast_mut().unwrap()
-	}
-}*/
+impl<T> Eq for NodeRef<T> {}
 
-struct NodeSlot<T: Node, JoinT: Join> {
-	gen: usize,
-	node: RefCell<T>,
-	joins: Vec<(NodeRef<T>, JoinT)>,
+impl<T> Hash for NodeRef<T> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.id.hash(state);
+		self.gen.hash(state);
+	}
 }
 
-trait GraphVisitor {
-	fn visit(&mut self, node: NodeRef);
+impl<T> fmt::Debug for NodeRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "NodeRef({}+{})", self.id, self.gen)
+	}
 }
 
-trait Node {
-	fn visit(&self, visitor: &mut dyn GraphVisitor);
+/// A reference is serialized as a string of its ID and generation like "5+9", so that it can be a
+/// key in a JSON map.
+#[cfg(feature = "serde")]
+impl<T> Serialize for NodeRef<T> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(&format_args!("{}+{}", self.id, self.gen))
+	}
+}
 
-	//fn as_any(&self) -> &Any;
-	//fn as_any_mut(&mut self) -> &mut Any;
+#[cfg(feature = "serde")]
+impl<'de, T> Deserialize<'de> for NodeRef<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NodeRef<T>, D::Error> {
+		let key = String::deserialize(deserializer)?;
+		let mut parts = key.splitn(2, '+').map(str::parse::<usize>);
+		match (parts.next(), parts.next()) {
+			(Some(Ok(id)), Some(Ok(gen))) => Ok(NodeRef::new(id, gen)),
+			_ => Err(D::Error::custom(format!("Expected a node reference like \"5+9\", found {:?}", key))),
+		}
+	}
 }
 
-trait Join : PartialEq {
+/// The kind of connection from one node to another, such as being its parent or referring to it.
+pub trait Join: Clone + PartialEq {
+	/// Whether the join keeps the node it joins to alive. Nodes that can't be reached from the
+	/// roots of the graph through strong joins are garbage.
 	fn is_strong(&self) -> bool;
 }
 
-struct Graph<T: Node, JoinT: Join> {
-	current_gen: usize,
-	slots: Vec<Option<NodeSlot<T, JoinT>>>,
-	roots: Vec<NodeRef>,
+/// Receives each join from a node.
+pub trait GraphVisitor<T, J> {
+	fn visit(&mut self, node: NodeRef<T>, join: J);
 }
 
-impl<T: Node> Graph<T> {
-	fn get(&self, node_ref: NodeRef) -> &Node {
-
+impl<T, J, F: FnMut(NodeRef<T>, J)> GraphVisitor<T, J> for F {
+	fn visit(&mut self, node: NodeRef<T>, join: J) {
+		self(node, join);
 	}
+}
+
+/// Implement this on the type of the nodes in a Graph to tell it which other nodes each node joins
+/// to. The joins are found again whenever a node is changed.
+pub trait Node<J: Join>: Sized {
+	fn visit_joins(&self, visitor: &mut dyn GraphVisitor<Self, J>);
+}
 
-	fn get_mut(&mut self, node_ref: NodeRef) -> &mut Node {
+#[derive(Debug, Clone)]
+struct NodeSlot<T, J> {
+	/// The generation of the node that is in the slot, or of the last one if it is empty.
+	gen: usize,
+	node: Option<T>,
+	/// The joins from the node, as of the last time it was added or changed.
+	joins: Vec<(NodeRef<T>, J)>,
+}
 
+impl<T, J> NodeSlot<T, J> {
+	fn new() -> NodeSlot<T, J> {
+		NodeSlot {
+			gen: 0,
+			node: None,
+			joins: vec![],
+		}
 	}
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct GraphSignal<ST> {
-	Add(NodeRef<T>),
-	Remove(NodeRef<T>),
-	Change(NodeRef<T>, ST),
+/// A collection of nodes that refer to each other with [`NodeRef`](struct.NodeRef.html)s, such as
+/// a document where elements can contain and refer to other elements.
+///
+/// Nodes are added with the NodeRef they will be known by, which is usually from `next_ref`, so
+/// that undoing and redoing a change gives the node the same reference every time. Removing a node
+/// doesn't remove the nodes it joins to, which can be found with `unreachable` instead.
+///
+/// With the `serde` feature, a graph is serialized as a map of its nodes keyed by their
+/// references, along with the generations of its empty slots and its roots, so references to its
+/// nodes from elsewhere stay valid after it is loaded again.
+#[derive(Debug, Clone)]
+pub struct Graph<T, J> {
+	slots: Vec<NodeSlot<T, J>>,
+	free_ids: Vec<usize>,
+	roots: Vec<NodeRef<T>>,
+	/// For each node that is joined to, the nodes that join to it and how.
+	incoming: HashMap<NodeRef<T>, Vec<(NodeRef<T>, J)>>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct GraphChange<T: Changeable<C>, C: Change> {
-	Add(NodeRef<T>),
-	Remove(NodeRef<T>),
-	Change(NodeRef<T>, C),
+// The joins are found from the nodes, so only the nodes and roots need comparing
+impl<T: PartialEq, J> PartialEq for Graph<T, J> {
+	fn eq(&self, other: &Graph<T, J>) -> bool {
+		self.roots == other.roots && self.slots.len() == other.slots.len() &&
+			self.slots.iter().zip(other.slots.iter()).all(|(slot, other_slot)| slot.gen == other_slot.gen && slot.node == other_slot.node)
+	}
 }
 
-impl<T: 'static + Changeable<C> + Send, C: Change> Change for GraphChange<T, C> {
-	type SignalType = GraphSignal<C::SignalType>;
+impl<T, J> Default for Graph<T, J> {
+	fn default() -> Graph<T, J> {
+		Graph::new()
+	}
 }
 
-impl<T: 'static + Changeable<C> + Send, C: Change> Changeable<VecChange<T, C>> for Graph<T> {
-	fn changeable_apply(&mut self, change: VecChange<T, C>, watcher: &mut Watcher<VecSignal<C::SignalType>>) {
-		use self::VecChange::*;
-		match change {
-			Add(node_ref) => {
-				self.insert(index, item);
-				watcher.send_signal(GraphSignal::Add(node_ref));
-			},
-			Remove(node_ref) => {
-				self.remove(index);
-				watcher.send_signal(GraphSignal::Remove(node_ref));
-			},
-			Change(node_ref, subchange) => {
-				let mut watcher_fn = |signal| {
-					watcher.send_signal(GraphSignal::Change(node_ref, signal));
-				};
-				self[index].changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
-			},
+impl<T, J> Graph<T, J> {
+	pub fn new() -> Graph<T, J> {
+		Graph {
+			slots: vec![],
+			free_ids: vec![],
+			roots: vec![],
+			incoming: HashMap::new(),
 		}
 	}
-
-	fn reset_view_signals(&self) -> Vec<VecSignal<C::SignalType>> {
-		let mut signals = vec![];
-		for slot in &self.slots {
-
+	
+	/// Returns the node that `node` refers to, or None if it has been removed.
+	pub fn get(&self, node: NodeRef<T>) -> Option<&T> {
+		self.slots.get(node.id)
+			.filter(|slot| slot.gen == node.gen)
+			.and_then(|slot| slot.node.as_ref())
+	}
+	
+	pub fn contains(&self, node: NodeRef<T>) -> bool {
+		self.get(node).is_some()
+	}
+	
+	/// The number of nodes in the graph.
+	pub fn len(&self) -> usize {
+		self.slots.len() - self.free_ids.len()
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+	
+	/// Iterates over the nodes in the graph, in the order of their IDs.
+	pub fn nodes(&self) -> impl Iterator<Item = (NodeRef<T>, &T)> + '_ {
+		self.slots.iter().enumerate().filter_map(|(id, slot)| {
+			slot.node.as_ref().map(|node| (NodeRef::new(id, slot.gen), node))
+		})
+	}
+	
+	/// The reference that a new node should be added with, which reuses a free slot if there is
+	/// one.
+	pub fn next_ref(&self) -> NodeRef<T> {
+		match self.free_ids.last() {
+			Some(&id) => NodeRef::new(id, self.slots[id].gen + 1),
+			None => NodeRef::new(self.slots.len(), 0),
+		}
+	}
+	
+	/// The nodes that are kept alive regardless of what joins to them. This can include nodes
+	/// that have been removed, which become roots again if the removal is undone.
+	pub fn roots(&self) -> &[NodeRef<T>] {
+		&self.roots
+	}
+	
+	pub fn is_root(&self, node: NodeRef<T>) -> bool {
+		self.roots.contains(&node)
+	}
+	
+	/// The joins from `node` to other nodes, which can include nodes that have been removed.
+	pub fn joins_from(&self, node: NodeRef<T>) -> &[(NodeRef<T>, J)] {
+		match self.slots.get(node.id) {
+			Some(slot) if slot.gen == node.gen => &slot.joins,
+			_ => &[],
+		}
+	}
+	
+	/// The nodes that join to `node`, and how.
+	pub fn joins_to(&self, node: NodeRef<T>) -> &[(NodeRef<T>, J)] {
+		self.incoming.get(&node).map_or(&[], |joins| &joins[..])
+	}
+	
+	/// The nodes that join to `node` with `join`, such as all the nodes that refer to it.
+	pub fn joined_by(&self, node: NodeRef<T>, join: &J) -> Vec<NodeRef<T>> where J: PartialEq {
+		self.joins_to(node).iter()
+			.filter(|&(_, source_join)| source_join == join)
+			.map(|&(source, _)| source)
+			.collect()
+	}
+	
+	/// The nodes that can't be reached from the roots through strong joins, in the order of their
+	/// IDs.
+	pub fn unreachable(&self) -> Vec<NodeRef<T>> where J: Join {
+		let mut reachable = vec![false; self.slots.len()];
+		let mut stack: Vec<NodeRef<T>> = self.roots.iter().cloned().filter(|&root| self.contains(root)).collect();
+		while let Some(node) = stack.pop() {
+			if reachable[node.id] {
+				continue;
+			}
+			reachable[node.id] = true;
+			for &(target, ref join) in &self.slots[node.id].joins {
+				if join.is_strong() && self.contains(target) && !reachable[target.id] {
+					stack.push(target);
+				}
+			}
+		}
+		
+		self.nodes().map(|(node, _)| node).filter(|node| !reachable[node.id]).collect()
+	}
+	
+	/// The changes that remove the unreachable nodes. Applying these instead of removing the nodes
+	/// directly lets the collection be undone in a History.
+	pub fn garbage_changes<C: Change>(&self) -> Vec<GraphChange<T, C>> where T: Changeable<C>, J: Join {
+		self.unreachable().into_iter().map(GraphChange::Remove).collect()
+	}
+	
+	/// Puts `item` in the slot for `node`, which must be empty.
+	fn insert_node(&mut self, node: NodeRef<T>, item: T) where T: Node<J>, J: Join {
+		while self.slots.len() <= node.id {
+			self.free_ids.push(self.slots.len());
+			self.slots.push(NodeSlot::new());
+		}
+		let slot = &mut self.slots[node.id];
+		if slot.node.is_some() {
+			panic!("GraphChange::Add applied to a node slot that is in use");
+		}
+		slot.gen = node.gen;
+		slot.node = Some(item);
+		self.free_ids.retain(|&id| id != node.id);
+		self.update_joins(node);
+	}
+	
+	/// Takes the node out of the graph, or returns None if it was already removed.
+	fn remove_node(&mut self, node: NodeRef<T>) -> Option<T> where T: Node<J>, J: Join {
+		if !self.contains(node) {
+			return None;
+		}
+		let item = self.slots[node.id].node.take();
+		self.free_ids.push(node.id);
+		self.update_joins(node);
+		item
+	}
+	
+	/// Adds or removes `node` from the roots, returning whether it was a root before.
+	fn set_root(&mut self, node: NodeRef<T>, root: bool) -> bool {
+		let was_root = self.is_root(node);
+		if root && !was_root {
+			self.roots.push(node);
+		} else if !root && was_root {
+			self.roots.retain(|&other| other != node);
+		}
+		was_root
+	}
+	
+	/// Finds the joins from `node` again, and updates the joins to the nodes it joined to before
+	/// and after.
+	fn update_joins(&mut self, node: NodeRef<T>) where T: Node<J>, J: Join {
+		let mut joins = vec![];
+		if let Some(item) = self.get(node) {
+			item.visit_joins(&mut |target: NodeRef<T>, join: J| joins.push((target, join)));
+		}
+		
+		let old_joins = std::mem::replace(&mut self.slots[node.id].joins, joins.clone());
+		for (target, _) in old_joins {
+			let now_empty = match self.incoming.get_mut(&target) {
+				Some(sources) => {
+					sources.retain(|&(source, _)| source != node);
+					sources.is_empty()
+				},
+				None => false,
+			};
+			if now_empty {
+				self.incoming.remove(&target);
+			}
+		}
+		for (target, join) in joins {
+			self.incoming.entry(target).or_default().push((node, join));
 		}
-		signals
 	}
 }
 
-//////////////////////////
+#[cfg(feature = "serde")]
+struct SerializeNodes<'a, T, J>(&'a Graph<T, J>);
 
-// Any element should be able to query the graph for nodes that join via a particular type.
-// Eg. if A (a reference) joins to B (a document) via the Reference type, then B should be able to
-// ask for all Reference joins to itself and get back A.
-#[derive(PartialEq)]
-enum ElementJoin {
-	Child,
-	Content,
-	Reference,
+#[cfg(feature = "serde")]
+impl<'a, T: Serialize, J> Serialize for SerializeNodes<'a, T, J> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_map(self.0.nodes())
+	}
 }
 
-impl Join for ElementJoin {
-	fn is_strong(&self) -> bool {
-		match self {
-			ElementJoin::Child | ElementJoin::Content => true,
-			_ => false,
-		}
+#[cfg(feature = "serde")]
+impl<T: Serialize, J> Serialize for Graph<T, J> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let mut free: Vec<NodeRef<T>> = self.free_ids.iter().map(|&id| NodeRef::new(id, self.slots[id].gen)).collect();
+		free.sort_by_key(|node| node.id);
+		
+		let mut state = serializer.serialize_struct("Graph", 3)?;
+		state.serialize_field("nodes", &SerializeNodes(self))?;
+		state.serialize_field("free", &free)?;
+		state.serialize_field("roots", &self.roots)?;
+		state.end()
 	}
 }
 
-struct Document {
-
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+struct GraphData<T> {
+	nodes: HashMap<NodeRef<T>, T>,
+	#[serde(default)]
+	free: Vec<NodeRef<T>>,
+	#[serde(default)]
+	roots: Vec<NodeRef<T>>,
 }
 
-struct TextRun {
-	text: String,
+/// Loading a graph fails if two entries use the same slot, or if a node or root refers to a node
+/// that can never have been in the graph. References to removed nodes are allowed, because weak
+/// joins can outlive the nodes they join to.
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de> + Node<J>, J: Join> Deserialize<'de> for Graph<T, J> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Graph<T, J>, D::Error> {
+		let data = GraphData::<T>::deserialize(deserializer)?;
+		let len = data.nodes.keys().chain(data.free.iter()).map(|node| node.id + 1).max().unwrap_or(0);
+		let mut graph = Graph::new();
+		graph.slots = (0..len).map(|_| NodeSlot::new()).collect();
+		
+		let mut used = vec![false; len];
+		for node in data.free.iter().chain(data.nodes.keys()) {
+			if used[node.id] {
+				return Err(D::Error::custom(format!("More than one node uses slot {}", node.id)));
+			}
+			used[node.id] = true;
+			graph.slots[node.id].gen = node.gen;
+		}
+		
+		let mut nodes: Vec<(NodeRef<T>, T)> = data.nodes.into_iter().collect();
+		nodes.sort_by_key(|&(node, _)| node.id);
+		for (node, item) in nodes {
+			graph.slots[node.id].node = Some(item);
+			graph.update_joins(node);
+		}
+		// Lower IDs are reused first
+		graph.free_ids = (0..len).rev().filter(|&id| graph.slots[id].node.is_none()).collect();
+		graph.roots = data.roots;
+		
+		let could_exist = |node: &NodeRef<T>| node.id < len && node.gen <= graph.slots[node.id].gen;
+		for root in &graph.roots {
+			if !could_exist(root) {
+				return Err(D::Error::custom(format!("Root {:?} was never in the graph", root)));
+			}
+		}
+		for (source, _) in graph.nodes() {
+			for &(target, _) in graph.joins_from(source) {
+				if !could_exist(&target) {
+					return Err(D::Error::custom(format!("{:?} joins to {:?}, which was never in the graph", source, target)));
+				}
+			}
+		}
+		Ok(graph)
+	}
 }
 
-struct TextReference {
-	ref_element: NodeRef<Element>,
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "ST: Serialize", deserialize = "ST: Deserialize<'de>")))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphSignal<T, ST> {
+	/// The node was added
+	Add(NodeRef<T>),
+	/// The node was removed
+	Remove(NodeRef<T>),
+	/// The node was changed, as described by the signal
+	Change(NodeRef<T>, ST),
+	/// The node was added to or removed from the roots
+	Root(NodeRef<T>),
 }
 
-impl TextReference {
-	fn visit_refs(&self, visitor: &mut dyn GraphVisitor) {
-		visitor.visit(self.ref_element, ElementJoin::Reference);
-	}
+/// A change for a [`Graph`](struct.Graph.html), which supports nested changes to its nodes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphChange<T: Changeable<C>, C: Change> {
+	/// Add the node with the given reference. Its slot must be empty, and the generation can't be
+	/// older than the last node in the slot.
+	Add(NodeRef<T>, T),
+	/// Remove the node, if it is still in the graph
+	Remove(NodeRef<T>),
+	/// Apply the given change to the node
+	Change(NodeRef<T>, C),
+	/// Add the node to the roots if true, or remove it from them if false
+	SetRoot(NodeRef<T>, bool),
 }
 
-struct TextFootnote {
-	content_element: NodeRef<Element>,
+impl<T, C> Change for GraphChange<T, C> where
+	T: 'static + Changeable<C> + Send + Debug + Clone + PartialEq,
+	C: Change,
+{
+	type SignalType = GraphSignal<T, C::SignalType>;
 }
 
-impl TextFootnote {
-	fn visit_refs(&self, visitor: &mut dyn GraphVisitor) {
-		visitor.visit(self.content_element, ElementJoin::Content);
+impl<T, C> ChangeSize for GraphChange<T, C> where
+	T: 'static + Changeable<C> + Send + Debug + Clone + PartialEq,
+	C: ChangeSize,
+{
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of::<GraphChange<T, C>>() + match *self {
+			GraphChange::Change(_, ref change) => change.estimated_size(),
+			_ => 0,
+		}
 	}
 }
 
-enum ParaPart {
-	Run(TextRun),
-	Reference(TextReference),
-	Footnote(TextFootnote),
+impl<T, J, C> Changeable<GraphChange<T, C>> for Graph<T, J> where
+	T: 'static + Node<J> + Changeable<C> + Send + Debug + Clone + PartialEq,
+	J: 'static + Join + Send,
+	C: Change,
+{
+	fn changeable_apply(&mut self, change: GraphChange<T, C>, watcher: &mut Watcher<GraphSignal<T, C::SignalType>>) {
+		use self::GraphChange::*;
+		match change {
+			Add(node, item) => {
+				self.insert_node(node, item);
+				watcher.send_signal(GraphSignal::Add(node));
+			},
+			Remove(node) => {
+				if self.remove_node(node).is_some() {
+					watcher.send_signal(GraphSignal::Remove(node));
+				}
+			},
+			Change(node, subchange) => {
+				{
+					let item = self.slots.get_mut(node.id)
+						.filter(|slot| slot.gen == node.gen)
+						.and_then(|slot| slot.node.as_mut())
+						.expect("GraphChange::Change applied to a node that is not in the graph");
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(GraphSignal::Change(node, signal));
+					};
+					item.changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
+				}
+				self.update_joins(node);
+			},
+			SetRoot(node, root) => {
+				if self.set_root(node, root) != root {
+					watcher.send_signal(GraphSignal::Root(node));
+				}
+			},
+		}
+	}
+	
+	fn reset_view_signals(&self) -> Vec<GraphSignal<T, C::SignalType>> {
+		let mut signals: Vec<_> = self.nodes().map(|(node, _)| GraphSignal::Add(node)).collect();
+		signals.extend(self.roots.iter().map(|&root| GraphSignal::Root(root)));
+		signals
+	}
+	
+	fn validate_change(&self, change: &GraphChange<T, C>) -> Result<(), ApplyError> {
+		match *change {
+			GraphChange::Add(node, _) => {
+				match self.slots.get(node.id) {
+					Some(slot) if slot.node.is_some() || node.gen < slot.gen => {
+						Err(ApplyErrorKind::Other(format!("The slot for {:?} is in use or has a newer generation", node)).into())
+					},
+					_ => Ok(()),
+				}
+			},
+			GraphChange::Change(node, ref subchange) => {
+				match self.get(node) {
+					Some(item) => item.validate_change(subchange).map_err(|err| err.within(format!("[{:?}]", node))),
+					None => Err(ApplyErrorKind::MissingKey(format!("{:?}", node)).into()),
+				}
+			},
+			_ => Ok(()),
+		}
+	}
 }
 
-impl ParaPart {
-	fn visit_refs(&self, visitor: &mut dyn GraphVisitor) {
-		match self {
-			ParaPart::Run(run) => {}
-			ParaPart::Reference(reference) => reference.visit_refs(visitor)
-			ParaPart::Footnote(footnote) => footnote.visit_refs(visitor)
+impl<T, J, C> Revertable<GraphChange<T, C>> for Graph<T, J> where
+	T: 'static + Node<J> + Revertable<C> + Send + Debug + Clone + PartialEq,
+	J: 'static + Join + Send,
+	C: Change,
+{
+	fn revertable_apply(&mut self, change: GraphChange<T, C>, watcher: &mut Watcher<GraphSignal<T, C::SignalType>>) -> GraphChange<T, C> {
+		use self::GraphChange::*;
+		match change {
+			Add(node, item) => {
+				self.insert_node(node, item);
+				watcher.send_signal(GraphSignal::Add(node));
+				Remove(node)
+			},
+			Remove(node) => {
+				if let Some(item) = self.remove_node(node) {
+					watcher.send_signal(GraphSignal::Remove(node));
+					Add(node, item)
+				} else {
+					// Nothing was removed, so removing the node again does nothing as well.
+					Remove(node)
+				}
+			},
+			Change(node, subchange) => {
+				let revertchange = {
+					let item = self.slots.get_mut(node.id)
+						.filter(|slot| slot.gen == node.gen)
+						.and_then(|slot| slot.node.as_mut())
+						.expect("GraphChange::Change applied to a node that is not in the graph");
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(GraphSignal::Change(node, signal));
+					};
+					item.revertable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn))
+				};
+				self.update_joins(node);
+				Change(node, revertchange)
+			},
+			SetRoot(node, root) => {
+				let was_root = self.set_root(node, root);
+				if was_root != root {
+					watcher.send_signal(GraphSignal::Root(node));
+				}
+				SetRoot(node, was_root)
+			},
 		}
 	}
 }
 
-struct Paragraph {
-	parts: Vec<ParaPart>,
+/// A ChangeConstructor that builds changes for a particular node in a graph. It becomes invalid
+/// when the node is removed.
+pub struct GraphChangeConstructor<T, C: Change> {
+	node: NodeRef<T>,
+	sub: Box<ChangeConstructor<C>>,
 }
 
-struct ElementData {
-	Document(Document),
-	Paragraph(Paragraph),
+impl<T, C: Change> GraphChangeConstructor<T, C> {
+	pub fn new(node: NodeRef<T>, sub: Box<ChangeConstructor<C>>) -> GraphChangeConstructor<T, C> {
+		GraphChangeConstructor { node, sub }
+	}
 }
 
-impl ElementData {
-	fn visit_refs(&self, visitor: &mut dyn GraphVisitor) {
-		match self {
-			ElementData::Document(document) => {}
-			ElementData::Paragraph(paragraph) => paragraph.visit_refs(visitor)
+impl<T, C> ChangeConstructor<GraphChange<T, C>> for GraphChangeConstructor<T, C> where
+	T: 'static + Changeable<C> + Send + Debug + Clone + PartialEq,
+	C: Change,
+{
+	fn create(&self, leaf_change: Box<std::any::Any>) -> GraphChange<T, C> {
+		GraphChange::Change(self.node, self.sub.create(leaf_change))
+	}
+	
+	fn update(&mut self, change: &GraphChange<T, C>) -> bool {
+		match *change {
+			GraphChange::Remove(node) if node == self.node => false,
+			GraphChange::Change(node, ref subchange) if node == self.node => self.sub.update(subchange),
+			_ => true,
 		}
 	}
+	
+	fn affects(&self, change: &GraphChange<T, C>) -> bool {
+		match *change {
+			GraphChange::Remove(node) => node == self.node,
+			GraphChange::Change(node, ref subchange) => node == self.node && self.sub.affects(subchange),
+			_ => false,
+		}
+	}
+	
+	fn debug_string(&self) -> String {
+		format!("GraphChangeConstructor({:?}) -> {}", self.node, self.sub.debug_string())
+	}
 }
 
-struct Element {
-	children: Vec<NodeRef<Element>>,
-	id: u128,
-	data: ElementData,
-}
-
-impl Node for Element {
-	// TODO: This is for GC, but maybe the Graph type doesn't need to deal with it?
-	fn visit_refs(&self, visitor: &mut dyn GraphVisitor) {
-		for child in &children {
-			visitor.visit(child, ElementJoin::Child);
-			child.visit_refs(visitor);
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{NoWatcher, SpyWatcher};
+	use crate::change_value::ValueChange;
+	use crate::history::{History, HistoryChange};
+	
+	// Any element should be able to query the graph for nodes that join via a particular type.
+	// Eg. if A (a reference) joins to B (a document) via the Reference type, then B should be able
+	// to ask for all Reference joins to itself and get back A.
+	#[derive(Debug, Clone, PartialEq)]
+	enum ElementJoin {
+		Child,
+		Reference,
+	}
+	
+	impl Join for ElementJoin {
+		fn is_strong(&self) -> bool {
+			*self == ElementJoin::Child
+		}
+	}
+	
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+	#[derive(Debug, Clone, PartialEq)]
+	struct Element {
+		text: String,
+		children: Vec<NodeRef<Element>>,
+		reference: Option<NodeRef<Element>>,
+	}
+	
+	impl Element {
+		fn new(text: &str, children: Vec<NodeRef<Element>>, reference: Option<NodeRef<Element>>) -> Element {
+			Element { text: text.into(), children, reference }
 		}
 	}
+	
+	impl Node<ElementJoin> for Element {
+		fn visit_joins(&self, visitor: &mut dyn GraphVisitor<Element, ElementJoin>) {
+			for &child in &self.children {
+				visitor.visit(child, ElementJoin::Child);
+			}
+			if let Some(reference) = self.reference {
+				visitor.visit(reference, ElementJoin::Reference);
+			}
+		}
+	}
+	
+	type ElementGraph = Graph<Element, ElementJoin>;
+	type ElementGraphChange = GraphChange<Element, ValueChange<Element>>;
+	
+	fn add(graph: &mut ElementGraph, element: Element) -> NodeRef<Element> {
+		let node = graph.next_ref();
+		graph.changeable_apply(ElementGraphChange::Add(node, element), &mut NoWatcher);
+		node
+	}
+	
+	#[test] fn graph_joins_and_garbage() {
+		let mut graph = ElementGraph::new();
+		let footnote = add(&mut graph, Element::new("Footnote", vec![], None));
+		let para = add(&mut graph, Element::new("Para", vec![], Some(footnote)));
+		let doc = add(&mut graph, Element::new("Doc", vec![para, footnote], None));
+		graph.changeable_apply(ElementGraphChange::SetRoot(doc, true), &mut NoWatcher);
+		
+		assert_eq!(graph.joined_by(footnote, &ElementJoin::Reference), vec![para]);
+		assert_eq!(graph.joined_by(footnote, &ElementJoin::Child), vec![doc]);
+		assert!(graph.unreachable().is_empty());
+		
+		// Dropping the footnote from the document leaves it only weakly joined
+		let mut spy = SpyWatcher::new();
+		graph.changeable_apply(ElementGraphChange::Change(doc, ValueChange(Element::new("Doc", vec![para], None))), &mut spy);
+		assert_eq!(spy.signals, vec![GraphSignal::Change(doc, crate::change_value::ValueSignal)]);
+		assert_eq!(graph.joined_by(footnote, &ElementJoin::Child), vec![]);
+		assert_eq!(graph.unreachable(), vec![footnote]);
+		
+		for change in graph.garbage_changes::<ValueChange<Element>>() {
+			graph.changeable_apply(change, &mut NoWatcher);
+		}
+		assert_eq!(graph.len(), 2);
+		assert_eq!(footnote.get(&graph), None);
+		
+		// The slot is reused with a new generation, so the old reference stays invalid
+		let replacement = add(&mut graph, Element::new("Replacement", vec![], None));
+		assert_eq!(replacement.id(), footnote.id());
+		assert_ne!(replacement, footnote);
+		assert_eq!(footnote.get(&graph), None);
+		assert!(graph.validate_change(&ElementGraphChange::Change(footnote, ValueChange(Element::new("", vec![], None)))).is_err());
+		assert!(graph.validate_change(&ElementGraphChange::Add(replacement, Element::new("", vec![], None))).is_err());
+	}
+	
+	#[test] fn graph_undo_in_history() {
+		let mut history: History<ElementGraph, ElementGraphChange> = History::new(Graph::new());
+		let doc = history.model.next_ref();
+		history.changeable_apply(HistoryChange::Push(GraphChange::Add(doc, Element::new("Doc", vec![], None))), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::NewTransaction("Add para".into()), &mut NoWatcher);
+		let para = history.model.next_ref();
+		history.changeable_apply(HistoryChange::Push(GraphChange::Add(para, Element::new("Para", vec![], Some(doc)))), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(GraphChange::Change(doc, ValueChange(Element::new("Doc", vec![para], None)))), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::NewTransaction("Remove doc".into()), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(GraphChange::Remove(doc)), &mut NoWatcher);
+		assert_eq!(history.model.joins_to(para), &[]);
+		
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(doc.get(&history.model).unwrap().children, vec![para]);
+		assert_eq!(history.model.joined_by(para, &ElementJoin::Child), vec![doc]);
+		
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(para.get(&history.model), None);
+		assert_eq!(history.model.joins_to(doc), &[]);
+		
+		history.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		assert_eq!(history.model.joined_by(doc, &ElementJoin::Reference), vec![para]);
+	}
+	
+	#[cfg(feature = "serde")]
+	#[test] fn graph_serialization_keeps_generations() {
+		let mut graph = ElementGraph::new();
+		let old = add(&mut graph, Element::new("Old", vec![], None));
+		graph.changeable_apply(ElementGraphChange::Remove(old), &mut NoWatcher);
+		let doc = add(&mut graph, Element::new("Doc", vec![], Some(old)));
+		let para = add(&mut graph, Element::new("Para", vec![], Some(doc)));
+		graph.changeable_apply(ElementGraphChange::Change(doc, ValueChange(Element::new("Doc", vec![para], Some(old)))), &mut NoWatcher);
+		graph.changeable_apply(ElementGraphChange::SetRoot(doc, true), &mut NoWatcher);
+		
+		let json = serde_json::to_string(&graph).unwrap();
+		assert!(json.contains(r#""0+1":{"text":"Doc","children":["1+0"],"reference":"0+0"}"#));
+		let loaded: ElementGraph = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded, graph);
+		assert_eq!(doc.get(&loaded).unwrap().text, "Doc");
+		assert_eq!(loaded.joined_by(doc, &ElementJoin::Reference), vec![para]);
+		assert_eq!(loaded.next_ref(), NodeRef::new(2, 0));
+		
+		let never_existed = r#"{"nodes": {"0+0": {"text": "", "children": ["3+0"], "reference": null}}}"#;
+		assert!(serde_json::from_str::<ElementGraph>(never_existed).is_err());
+		let shared_slot = r#"{"nodes": {"0+0": {"text": "", "children": [], "reference": null}}, "free": ["0+1"]}"#;
+		assert!(serde_json::from_str::<ElementGraph>(shared_slot).is_err());
+		assert!(serde_json::from_str::<NodeRef<Element>>(r#""5""#).is_err());
+	}
 }
@@ -1,28 +1,152 @@
-use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, apply_pipe_to_mut_ref};
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ApplyError, ApplyErrorKind, apply_pipe_to_mut_ref};
 
+use std;
 use std::fmt;
+use std::time::SystemTime;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone, PartialEq)]
-struct ChangeSet<C> {
-	name: String,
-	changes: Vec<C>,
+/// Estimates how much memory a change takes up, which a History uses to stay within its byte
+/// budget. The default only counts the change itself, so changes that own heap data such as
+/// strings or vectors should add that on.
+///
+/// The estimate doesn't need to be exact. Values held by a change, such as the items of a
+/// VecChange or the keys of a MapChange, are counted by their inline size only, since there is no
+/// general way to measure the heap data they own.
+pub trait ChangeSize: Change {
+	fn estimated_size(&self) -> usize {
+		std::mem::size_of_val(self)
+	}
+}
+
+type SizeFn<C> = fn(&C) -> usize;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub(crate) struct ChangeSet<C> {
+	pub(crate) name: String,
+	pub(crate) changes: Vec<C>,
+	/// When the transaction was started.
+	pub(crate) time: SystemTime,
+	/// The estimated size of the changes, when the History has a byte budget.
+	#[cfg_attr(feature = "serde", serde(skip))]
+	bytes: usize,
 }
 
-impl<C> ChangeSet<C> {
-	fn new(name: String) -> ChangeSet<C> {
+// Only what the transaction does is compared, not when it happened
+impl<C: PartialEq> PartialEq for ChangeSet<C> {
+	fn eq(&self, other: &ChangeSet<C>) -> bool {
+		self.name == other.name && self.changes == other.changes
+	}
+}
+
+impl<C: Change> ChangeSet<C> {
+	pub(crate) fn new(name: String) -> ChangeSet<C> {
 		ChangeSet {
 			name,
 			changes: vec![],
+			time: SystemTime::now(),
+			bytes: 0,
+		}
+	}
+	
+	pub(crate) fn info(&self) -> TransactionInfo<'_> {
+		TransactionInfo {
+			name: &self.name,
+			change_count: self.changes.len(),
+			time: self.time,
+		}
+	}
+	
+	/// Adds a revert change to the set. If `coalesce` is set, the new change gets the chance to
+	/// absorb the previous one to keep the set small, since revert changes are applied in reverse
+	/// order. Changes before `floor` are left alone, so that a nested transaction starting there
+	/// can still be aborted.
+	pub(crate) fn push(&mut self, mut revertchange: C, floor: usize, coalesce: bool, size_fn: Option<SizeFn<C>>) {
+		let size = |change: &C| size_fn.map_or(0, |size_fn| size_fn(change));
+		
+		if coalesce && self.changes.len() > floor {
+			if let Some(last) = self.changes.pop() {
+				self.bytes -= size(&last);
+				if let Err(last) = revertchange.coalesce(last) {
+					self.bytes += size(&last);
+					self.changes.push(last);
+				}
+			}
+		}
+		self.bytes += size(&revertchange);
+		self.changes.push(revertchange);
+	}
+	
+	fn update_bytes(&mut self, size_fn: Option<SizeFn<C>>) {
+		self.bytes = size_fn.map_or(0, |size_fn| self.changes.iter().map(size_fn).sum());
+	}
+}
+
+/// A read-only view of a transaction in a History.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionInfo<'a> {
+	pub name: &'a str,
+	/// The number of changes it takes to undo or redo the transaction. This can be fewer than the
+	/// number of changes that were pushed, when consecutive changes are coalesced.
+	pub change_count: usize,
+	/// When the transaction was started.
+	pub time: SystemTime,
+}
+
+/// Limits on how much undo history a History keeps.
+struct HistoryLimits<C> {
+	max_transactions: Option<usize>,
+	max_bytes: Option<usize>,
+	/// Estimates the size of a change. This is only set when there is a max_bytes.
+	size_fn: Option<SizeFn<C>>,
+}
+
+impl<C> Default for HistoryLimits<C> {
+	fn default() -> HistoryLimits<C> {
+		HistoryLimits {
+			max_transactions: None,
+			max_bytes: None,
+			size_fn: None,
 		}
 	}
 }
 
+impl<C> Clone for HistoryLimits<C> {
+	fn clone(&self) -> HistoryLimits<C> {
+		HistoryLimits {
+			max_transactions: self.max_transactions,
+			max_bytes: self.max_bytes,
+			size_fn: self.size_fn,
+		}
+	}
+}
+
+// The size_fn is always ChangeSize::estimated_size when it is set, so it doesn't need comparing
+impl<C> PartialEq for HistoryLimits<C> {
+	fn eq(&self, other: &HistoryLimits<C>) -> bool {
+		self.max_transactions == other.max_transactions && self.max_bytes == other.max_bytes
+	}
+}
+
 /// Allows undo and redo within a [`Changeable`](trait.Changeable.html) data model. 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, PartialEq)]
 pub struct History<T: Revertable<C>, C: Change> {
 	pub model: T,
 	undo_stack: Vec<ChangeSet<C>>,
 	redo_stack: Vec<ChangeSet<C>>,
+	/// For each open transaction scope, the number of changes the current transaction had when it
+	/// began. The first one is the outermost scope.
+	open_transactions: Vec<usize>,
+	/// The number of transactions that were undoable when the clean state was marked, or None
+	/// if it can't be returned to.
+	clean_depth: Option<usize>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	coalesce_changes: bool,
+	// Naming the default function stops serde from requiring C: Default
+	#[cfg_attr(feature = "serde", serde(skip, default = "HistoryLimits::default"))]
+	limits: HistoryLimits<C>,
 }
 
 impl<T: Revertable<C> + fmt::Debug, C: Change> fmt::Debug for History<T, C> {
@@ -38,6 +162,10 @@ impl<T: Revertable<C>, C: Change> History<T, C> {
 			model,
 			undo_stack: vec![],
 			redo_stack: vec![],
+			open_transactions: vec![],
+			clean_depth: Some(0),
+			coalesce_changes: false,
+			limits: HistoryLimits::default(),
 		}
 	}
 	
@@ -48,8 +176,183 @@ impl<T: Revertable<C>, C: Change> History<T, C> {
 	pub fn can_redo(&self) -> bool {
 		!self.redo_stack.is_empty()
 	}
+	
+	/// The number of transaction scopes that have begun and haven't been committed or aborted.
+	pub fn transaction_depth(&self) -> usize {
+		self.open_transactions.len()
+	}
+	
+	/// Whether the model is in the state that was marked by `HistoryChange::MarkClean`. A new or
+	/// reset history is clean. Transactions without any changes don't count, so starting one
+	/// doesn't make the history modified.
+	pub fn is_clean(&self) -> bool {
+		match self.clean_depth {
+			Some(depth) if depth <= self.undo_stack.len() => {
+				self.undo_stack[depth..].iter().all(|changeset| changeset.changes.is_empty())
+			},
+			Some(depth) => {
+				let count = depth - self.undo_stack.len();
+				count <= self.redo_stack.len() &&
+					self.redo_stack[self.redo_stack.len() - count..].iter().all(|changeset| changeset.changes.is_empty())
+			},
+			None => false,
+		}
+	}
+	
+	/// Forgets the clean state if it comes after the transaction at `index`, because that
+	/// transaction was modified or dropped.
+	fn invalidate_clean_after(&mut self, index: usize) {
+		if let Some(depth) = self.clean_depth {
+			if depth > index {
+				self.clean_depth = None;
+			}
+		}
+	}
+	
+	/// The transactions that can be undone, oldest first. `HistoryChange::UndoTo(n)` leaves the
+	/// first `n` of these.
+	pub fn undo_transactions(&self) -> Vec<TransactionInfo<'_>> {
+		self.undo_stack.iter().map(ChangeSet::info).collect()
+	}
+	
+	/// The transactions that can be redone, with the one that Redo reapplies last.
+	/// `HistoryChange::RedoTo(n)` leaves the first `n` of these.
+	pub fn redo_transactions(&self) -> Vec<TransactionInfo<'_>> {
+		self.redo_stack.iter().map(ChangeSet::info).collect()
+	}
+	
+	/// Undoes up to `count` transactions, sending one Undo signal at the end if any were undone.
+	fn undo_steps(&mut self, count: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let mut undone = false;
+		for _ in 0..count {
+			if let Some(mut revertchangeset) = self.undo_stack.pop() {
+				{
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(HistorySignal::Change(signal));
+					};
+					
+					for revertchange in &mut revertchangeset.changes.iter_mut().rev() {
+						//self.change_queue.push(revertchange.clone());
+						apply_pipe_to_mut_ref(|change| self.model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), revertchange);
+					}
+				}
+				
+				revertchangeset.update_bytes(self.limits.size_fn);
+				self.redo_stack.push(revertchangeset);
+				undone = true;
+			}
+		}
+		
+		if undone {
+			watcher.send_signal(HistorySignal::Undo);
+			watcher.send_signal(HistorySignal::CurrentTransaction);
+		}
+	}
+	
+	/// Redoes up to `count` transactions, sending one Redo signal at the end if any were redone.
+	fn redo_steps(&mut self, count: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let mut redone = false;
+		for _ in 0..count {
+			if let Some(mut reapplychangeset) = self.redo_stack.pop() {
+				{
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(HistorySignal::Change(signal));
+					};
+					
+					for reapplychange in &mut reapplychangeset.changes.iter_mut() {
+						//self.change_queue.push(reapplychange.clone());
+						apply_pipe_to_mut_ref(|change| self.model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), reapplychange);
+					}
+				}
+				
+				reapplychangeset.update_bytes(self.limits.size_fn);
+				self.undo_stack.push(reapplychangeset);
+				redone = true;
+			}
+		}
+		
+		if redone {
+			watcher.send_signal(HistorySignal::Redo);
+			watcher.send_signal(HistorySignal::CurrentTransaction);
+		}
+	}
+	
+	pub fn coalesce_changes(&self) -> bool {
+		self.coalesce_changes
+	}
+	
+	/// When enabled, each pushed change is merged with the one pushed before it in the same
+	/// transaction where possible, so typing a word can be undone with a single change. Disabled
+	/// by default.
+	pub fn set_coalesce_changes(&mut self, coalesce_changes: bool) {
+		self.coalesce_changes = coalesce_changes;
+	}
+	
+	pub fn max_transactions(&self) -> Option<usize> {
+		self.limits.max_transactions
+	}
+	
+	/// Limits the number of transactions that can be undone. When there are too many, the oldest
+	/// ones are dropped, though the newest is always kept. Unlimited by default.
+	///
+	/// Transactions dropped by this call don't send HistorySignal::Trim, because it isn't applied
+	/// as a change.
+	pub fn set_max_transactions(&mut self, max_transactions: Option<usize>) {
+		self.limits.max_transactions = max_transactions;
+		self.trim();
+	}
+	
+	pub fn max_bytes(&self) -> Option<usize> {
+		self.limits.max_bytes
+	}
+	
+	/// Limits the estimated size of the changes that can be undone. When they take up too much,
+	/// the oldest transactions are dropped, though the newest is always kept. Unlimited by
+	/// default.
+	///
+	/// Transactions dropped by this call don't send HistorySignal::Trim, because it isn't applied
+	/// as a change.
+	pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) where C: ChangeSize {
+		self.limits.max_bytes = max_bytes;
+		self.limits.size_fn = max_bytes.map(|_| ChangeSize::estimated_size as SizeFn<C>);
+		for changeset in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+			changeset.update_bytes(self.limits.size_fn);
+		}
+		self.trim();
+	}
+	
+	/// The estimated size of the changes that can be undone, which is only tracked when there is
+	/// a max_bytes.
+	pub fn estimated_size(&self) -> usize {
+		self.undo_stack.iter().map(|changeset| changeset.bytes).sum()
+	}
+	
+	/// Drops the oldest transactions until the history is within its limits. Returns whether any
+	/// were dropped.
+	fn trim(&mut self) -> bool {
+		if self.limits.max_transactions.is_none() && self.limits.max_bytes.is_none() {
+			return false;
+		}
+		
+		let max_transactions = self.limits.max_transactions.unwrap_or(usize::MAX);
+		let max_bytes = self.limits.max_bytes.unwrap_or(usize::MAX);
+		let mut bytes = self.estimated_size();
+		let mut count = 0;
+		while self.undo_stack.len() - count > 1 && (self.undo_stack.len() - count > max_transactions || bytes > max_bytes) {
+			bytes -= self.undo_stack[count].bytes;
+			count += 1;
+		}
+		
+		self.undo_stack.drain(..count);
+		self.clean_depth = match self.clean_depth {
+			Some(depth) if depth >= count => Some(depth - count),
+			_ => None,
+		};
+		count > 0
+	}
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum HistorySignal<ST> {
 	/// This signal is for any change that touches the History's model,
@@ -63,16 +366,59 @@ pub enum HistorySignal<ST> {
 	Undo,
 	Redo,
 	ClearHistory,
+	/// Signal for when the oldest transactions are dropped to keep the history within its
+	/// limits.
+	Trim,
+	/// Signal for when an UndoTree moves to another node other than by Undo or Redo.
+	Jump,
+	/// Signal for when an UndoTree changes which branch Redo follows.
+	SelectBranch,
+	/// Signal for when the model changes between being in and out of the state marked by
+	/// MarkClean.
+	CleanStateChanged,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub enum HistoryChange<T: Revertable<C>, C: Change> {
 	Reset(Box<T>),
+	/// Starts a new transaction that following changes are added to. This is ignored while a
+	/// transaction scope is open.
 	NewTransaction(String),
+	/// Opens a transaction scope. The outermost scope starts a new transaction like
+	/// NewTransaction, and nested scopes are grouped into it.
+	BeginTransaction(String),
+	/// Closes the innermost transaction scope, keeping its changes.
+	CommitTransaction,
+	/// Closes the innermost transaction scope, reverting the changes made since it began. If it
+	/// is the outermost scope, its transaction is removed too.
+	AbortTransaction,
 	Push(C),
 	Undo,
 	Redo,
+	/// Undoes transactions until there are the given number left that can be undone, sending
+	/// the signals for a single Undo.
+	UndoTo(usize),
+	/// Redoes transactions until there are the given number left that can be redone, sending
+	/// the signals for a single Redo.
+	RedoTo(usize),
 	ClearHistory,
+	/// Marks the current state as clean, such as when the document is saved. Undoing or redoing
+	/// back to it makes the history clean again.
+	MarkClean,
+	/// Moves an UndoTree to the node with the given ID, undoing and redoing along the way. The
+	/// root node, which is the state before any transactions, is 0.
+	JumpTo(usize),
+	/// Moves an UndoTree back the given number of nodes in the order they were created, which can
+	/// cross between branches.
+	Earlier(usize),
+	/// Moves an UndoTree forward the given number of nodes in the order they were created.
+	Later(usize),
+	/// Moves an UndoTree to the newest node that was created at or before the given time.
+	JumpToTime(SystemTime),
+	/// Chooses which branch of the current node Redo follows in an UndoTree, by index into its
+	/// children.
+	SelectBranch(usize),
 	//Pop,
 }
 
@@ -108,17 +454,72 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Change for HistoryChange<T, C
 impl<T: 'static + Revertable<C> + Send, C: Change> Changeable<HistoryChange<T, C>> for History<T, C> {
 	fn changeable_apply(&mut self, change: HistoryChange<T, C>, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
 		use self::HistoryChange::*;
+		let was_clean = self.is_clean();
 		match change {
 			Reset(newmodel) => {
 				self.model = *newmodel;
 				self.undo_stack.clear();
 				self.redo_stack.clear();
+				self.open_transactions.clear();
+				self.clean_depth = Some(0);
 				watcher.send_signal(HistorySignal::Reset);
 			},
 			NewTransaction(name) => {
-				self.undo_stack.push(ChangeSet::new(name));
-				self.redo_stack.clear();
-				watcher.send_signal(HistorySignal::CurrentTransaction);
+				// Nested transactions are grouped into the open one
+				if self.open_transactions.is_empty() {
+					let len = self.undo_stack.len();
+					self.invalidate_clean_after(len);
+					self.undo_stack.push(ChangeSet::new(name));
+					self.redo_stack.clear();
+					watcher.send_signal(HistorySignal::CurrentTransaction);
+				}
+			},
+			BeginTransaction(name) => {
+				if self.open_transactions.is_empty() {
+					let len = self.undo_stack.len();
+					self.invalidate_clean_after(len);
+					self.undo_stack.push(ChangeSet::new(name));
+					self.redo_stack.clear();
+					self.open_transactions.push(0);
+					watcher.send_signal(HistorySignal::CurrentTransaction);
+				} else {
+					let start = self.undo_stack.last().map_or(0, |current| current.changes.len());
+					self.open_transactions.push(start);
+				}
+			},
+			CommitTransaction => {
+				self.open_transactions.pop();
+			},
+			AbortTransaction => {
+				if let Some(start) = self.open_transactions.pop() {
+					if let Some(mut current) = self.undo_stack.pop() {
+						if current.changes.len() > start {
+							let len = self.undo_stack.len();
+							self.invalidate_clean_after(len);
+						}
+						{
+							let mut watcher_fn = |signal| {
+								watcher.send_signal(HistorySignal::Change(signal));
+							};
+							
+							for revertchange in current.changes.drain(start..).rev() {
+								self.model.changeable_apply(revertchange, &mut SubWatcher::new(&mut watcher_fn));
+							}
+						}
+						current.update_bytes(self.limits.size_fn);
+						
+						if !self.open_transactions.is_empty() {
+							self.undo_stack.push(current);
+						} else if self.clean_depth == Some(self.undo_stack.len() + 1) {
+							// The removed transaction was empty, so it was clean without it too
+							self.clean_depth = Some(self.undo_stack.len());
+						}
+					}
+					
+					if self.open_transactions.is_empty() {
+						watcher.send_signal(HistorySignal::CurrentTransaction);
+					}
+				}
 			},
 			Push(subchange) => {
 				let revertchange = {
@@ -131,15 +532,20 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Changeable<HistoryChange<T, C
 				
 				self.redo_stack.clear();
 				
+				// The state after the transaction being added to can't be returned to
+				let current_index = self.undo_stack.len().saturating_sub(1);
+				self.invalidate_clean_after(current_index);
+				
 				if self.undo_stack.is_empty() {
 					// There were no transactions to add to, so just add to a
 					// new, nameless one
 					let mut first_changeset = ChangeSet::new("".into());
-					first_changeset.changes.push(revertchange);
+					first_changeset.push(revertchange, 0, self.coalesce_changes, self.limits.size_fn);
 					self.undo_stack.push(first_changeset);
 				} else {
+					let floor = self.open_transactions.last().cloned().unwrap_or(0);
 					if let Some(ref mut current) = self.undo_stack.last_mut() {
-						current.changes.push(revertchange);
+						current.push(revertchange, floor, self.coalesce_changes, self.limits.size_fn);
 					} else {
 						panic!("Should not be reachable");
 					}
@@ -147,53 +553,63 @@ impl<T: 'static + Revertable<C> + Send, C: Change> Changeable<HistoryChange<T, C
 				watcher.send_signal(HistorySignal::Push);
 			},
 			Undo => {
-				if let Some(mut revertchangeset) = self.undo_stack.pop() {
-					{
-						let mut watcher_fn = |signal| {
-							watcher.send_signal(HistorySignal::Change(signal));
-						};
-						
-						for revertchange in &mut revertchangeset.changes.iter_mut().rev() {
-							//self.change_queue.push(revertchange.clone());
-							apply_pipe_to_mut_ref(|change| self.model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), revertchange);
-						}
-					}
-					
-					self.redo_stack.push(revertchangeset);
-					watcher.send_signal(HistorySignal::Undo);
-					watcher.send_signal(HistorySignal::CurrentTransaction);
-				}
+				self.open_transactions.clear();
+				self.undo_steps(1, watcher);
 			},
 			Redo => {
-				if let Some(mut reapplychangeset) = self.redo_stack.pop() {
-					{
-						let mut watcher_fn = |signal| {
-							watcher.send_signal(HistorySignal::Change(signal));
-						};
-						
-						for reapplychange in &mut reapplychangeset.changes.iter_mut() {
-							//self.change_queue.push(reapplychange.clone());
-							apply_pipe_to_mut_ref(|change| self.model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), reapplychange);
-						}
-					}
-					
-					self.undo_stack.push(reapplychangeset);
-					watcher.send_signal(HistorySignal::Redo);
-					watcher.send_signal(HistorySignal::CurrentTransaction);
-				}
+				self.open_transactions.clear();
+				self.redo_steps(1, watcher);
+			},
+			UndoTo(remaining) => {
+				self.open_transactions.clear();
+				let count = self.undo_stack.len().saturating_sub(remaining);
+				self.undo_steps(count, watcher);
+			},
+			RedoTo(remaining) => {
+				self.open_transactions.clear();
+				let count = self.redo_stack.len().saturating_sub(remaining);
+				self.redo_steps(count, watcher);
 			},
 			ClearHistory => {
+				self.clean_depth = if was_clean { Some(0) } else { None };
 				self.undo_stack.clear();
 				self.redo_stack.clear();
+				self.open_transactions.clear();
 				watcher.send_signal(HistorySignal::ClearHistory);
 				watcher.send_signal(HistorySignal::CurrentTransaction);
 			},
+			MarkClean => {
+				self.clean_depth = Some(self.undo_stack.len());
+			},
+			JumpTo(_) | Earlier(_) | Later(_) | JumpToTime(_) | SelectBranch(_) => {
+				// A linear history has no branches to navigate
+			},
+		}
+		
+		if self.trim() {
+			watcher.send_signal(HistorySignal::Trim);
+		}
+		if self.is_clean() != was_clean {
+			watcher.send_signal(HistorySignal::CleanStateChanged);
 		}
 	}
 	
 	fn reset_view_signals(&self) -> Vec<HistorySignal<C::SignalType>> {
 		vec![HistorySignal::Reset, HistorySignal::CurrentTransaction]
 	}
+	
+	fn validate_change(&self, change: &HistoryChange<T, C>) -> Result<(), ApplyError> {
+		match *change {
+			HistoryChange::Push(ref subchange) => {
+				self.model.validate_change(subchange).map_err(|err| err.within("Push"))
+			}
+			HistoryChange::JumpTo(_) | HistoryChange::Earlier(_) | HistoryChange::Later(_) |
+			HistoryChange::JumpToTime(_) | HistoryChange::SelectBranch(_) => {
+				Err(ApplyErrorKind::Other("Branch navigation needs an UndoTree".into()).into())
+			}
+			_ => Ok(()),
+		}
+	}
 }
 
 /*#[derive(Clone, Debug)]
@@ -220,7 +636,7 @@ impl<C> Hist<C> {
 			}
 		}
 	}
-
+	
 	fn undo<T: Revertable<C>>(&mut self, cxt: &mut ApplyContext<T, C>) {
 		if let Some(mut revertchangeset) = self.undo_stack.pop() {
 			for revertchange in &mut revertchangeset.changes.iter_mut().rev() {
@@ -261,3 +677,186 @@ impl<T: Revertable<C>, C> Revertable<C> for ValWithHist<T, C> {
 		revertchange
 	}
 }*/
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{NoWatcher, SpyWatcher};
+	use crate::change_string::{StringChange, StringSignal};
+	
+	#[test] fn history_coalesces_pushed_changes() {
+		let type_changes = |history: &mut History<String, StringChange>| {
+			history.changeable_apply(HistoryChange::NewTransaction("Typing".into()), &mut NoWatcher);
+			for (i, c) in " World".chars().enumerate() {
+				history.changeable_apply(HistoryChange::Push(StringChange{index: 5 + i, len: 0, new: c.to_string()}), &mut NoWatcher);
+			}
+			history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 1, new: "J".into()}), &mut NoWatcher);
+			assert_eq!(history.model, "Jello World");
+		};
+		
+		// Each change is kept unless coalescing is enabled
+		let mut history: History<String, StringChange> = History::new("Hello".into());
+		type_changes(&mut history);
+		assert_eq!(history.undo_stack[0].changes.len(), 7);
+		
+		let mut history: History<String, StringChange> = History::new("Hello".into());
+		history.set_coalesce_changes(true);
+		type_changes(&mut history);
+		assert_eq!(history.undo_stack[0].changes.len(), 2);
+		
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(history.model, "Hello");
+		history.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		assert_eq!(history.model, "Jello World");
+	}
+	
+	#[test] fn history_transaction_scopes() {
+		let mut history: History<String, StringChange> = History::new("".into());
+		let mut spy = SpyWatcher::new();
+		let changes = vec![
+			HistoryChange::BeginTransaction("Typing".into()),
+			HistoryChange::Push(StringChange{index: 0, len: 0, new: "Hello".into()}),
+			HistoryChange::BeginTransaction("Suffix".into()),
+			HistoryChange::NewTransaction("Ignored".into()),
+			HistoryChange::Push(StringChange{index: 5, len: 0, new: " World".into()}),
+			HistoryChange::AbortTransaction,
+			HistoryChange::Push(StringChange{index: 5, len: 0, new: "!".into()}),
+			HistoryChange::CommitTransaction,
+		];
+		for change in changes {
+			history.changeable_apply(change, &mut spy);
+		}
+		assert_eq!(history.model, "Hello!");
+		assert_eq!(history.transaction_depth(), 0);
+		assert_eq!(history.undo_stack.len(), 1);
+		assert!(spy.signals.contains(&HistorySignal::Change(StringSignal{index: 5, from_len: 6, to_len: 0})));
+		
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(history.model, "");
+		history.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		assert_eq!(history.model, "Hello!");
+		
+		history.changeable_apply(HistoryChange::BeginTransaction("Cleared".into()), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 6, new: "".into()}), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::AbortTransaction, &mut NoWatcher);
+		assert_eq!(history.model, "Hello!");
+		assert_eq!(history.undo_stack.len(), 1);
+		assert!(!history.can_redo());
+	}
+	
+	#[test] fn history_limits() {
+		let mut history: History<String, StringChange> = History::new("".into());
+		history.set_max_transactions(Some(2));
+		
+		let mut spy = SpyWatcher::new();
+		for word in &["one ", "two ", "three "] {
+			history.changeable_apply(HistoryChange::NewTransaction(word.to_string()), &mut spy);
+			let index = history.model.len();
+			history.changeable_apply(HistoryChange::Push(StringChange{index, len: 0, new: word.to_string()}), &mut spy);
+		}
+		assert_eq!(history.undo_stack.len(), 2);
+		assert_eq!(spy.signals.iter().filter(|signal| **signal == HistorySignal::Trim).count(), 1);
+		
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(history.model, "one ");
+		assert!(!history.can_undo());
+		
+		history.set_max_transactions(None);
+		history.set_max_bytes(Some(200));
+		// Inserting and then removing the text coalesces into a change that fits
+		history.set_coalesce_changes(true);
+		history.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		history.changeable_apply(HistoryChange::NewTransaction("Big".into()), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 0, new: "x".repeat(100)}), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 100, new: "".into()}), &mut NoWatcher);
+		assert_eq!(history.undo_stack.len(), 2);
+		assert!(history.estimated_size() <= 200);
+		
+		history.changeable_apply(HistoryChange::NewTransaction("Insert".into()), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 0, new: "y".repeat(300)}), &mut NoWatcher);
+		assert_eq!(history.undo_stack.len(), 3);
+		
+		// Reverting the removal needs all the removed text, which doesn't fit
+		history.changeable_apply(HistoryChange::NewTransaction("Remove".into()), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 300, new: "".into()}), &mut NoWatcher);
+		assert_eq!(history.undo_stack.len(), 1);
+		assert!(history.estimated_size() > 200);
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(history.model.len(), 308);
+	}
+	
+	#[test] fn history_undo_to() {
+		let mut history: History<String, StringChange> = History::new("".into());
+		for word in &["one ", "two ", "three "] {
+			history.changeable_apply(HistoryChange::NewTransaction(word.to_string()), &mut NoWatcher);
+			let index = history.model.len();
+			history.changeable_apply(HistoryChange::Push(StringChange{index, len: 0, new: word.to_string()}), &mut NoWatcher);
+		}
+		let names = |transactions: Vec<TransactionInfo>| transactions.iter().map(|info| info.name.to_string()).collect::<Vec<_>>();
+		assert_eq!(names(history.undo_transactions()), vec!["one ", "two ", "three "]);
+		assert_eq!(history.undo_transactions()[0].change_count, 1);
+		
+		let mut spy = SpyWatcher::new();
+		history.changeable_apply(HistoryChange::UndoTo(1), &mut spy);
+		assert_eq!(history.model, "one ");
+		assert_eq!(names(history.undo_transactions()), vec!["one "]);
+		assert_eq!(names(history.redo_transactions()), vec!["three ", "two "]);
+		assert_eq!(spy.signals.iter().filter(|signal| **signal == HistorySignal::Undo).count(), 1);
+		assert_eq!(spy.signals.len(), 4);
+		
+		history.changeable_apply(HistoryChange::RedoTo(1), &mut NoWatcher);
+		assert_eq!(history.model, "one two ");
+		history.changeable_apply(HistoryChange::RedoTo(5), &mut NoWatcher);
+		assert_eq!(history.model, "one two ");
+		history.changeable_apply(HistoryChange::UndoTo(0), &mut NoWatcher);
+		assert_eq!(history.model, "");
+	}
+	
+	#[test] fn history_clean_state() {
+		let mut history: History<String, StringChange> = History::new("".into());
+		let type_text = |history: &mut History<String, StringChange>, text: &str, watcher: &mut Watcher<HistorySignal<StringSignal>>| {
+			let index = history.model.len();
+			history.changeable_apply(HistoryChange::NewTransaction(text.into()), watcher);
+			history.changeable_apply(HistoryChange::Push(StringChange{index, len: 0, new: text.into()}), watcher);
+		};
+		assert!(history.is_clean());
+		
+		let mut spy = SpyWatcher::new();
+		type_text(&mut history, "a", &mut spy);
+		assert!(!history.is_clean());
+		assert_eq!(spy.signals.iter().filter(|signal| **signal == HistorySignal::CleanStateChanged).count(), 1);
+		
+		type_text(&mut history, "b", &mut NoWatcher);
+		history.changeable_apply(HistoryChange::MarkClean, &mut NoWatcher);
+		assert!(history.is_clean());
+		history.changeable_apply(HistoryChange::NewTransaction("Empty".into()), &mut NoWatcher);
+		assert!(history.is_clean());
+		
+		type_text(&mut history, "c", &mut NoWatcher);
+		history.changeable_apply(HistoryChange::UndoTo(1), &mut NoWatcher);
+		assert!(!history.is_clean());
+		let mut spy = SpyWatcher::new();
+		history.changeable_apply(HistoryChange::Redo, &mut spy);
+		assert_eq!(history.model, "ab");
+		assert!(history.is_clean());
+		assert!(spy.signals.contains(&HistorySignal::CleanStateChanged));
+		
+		// Undoing past the clean state and branching off loses it
+		history.changeable_apply(HistoryChange::UndoTo(1), &mut NoWatcher);
+		type_text(&mut history, "d", &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(history.model, "a");
+		assert!(!history.is_clean());
+		
+		// Adding to the marked transaction loses it too
+		history.changeable_apply(HistoryChange::MarkClean, &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Push(StringChange{index: 1, len: 0, new: "e".into()}), &mut NoWatcher);
+		history.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert!(!history.is_clean());
+		
+		history.changeable_apply(HistoryChange::MarkClean, &mut NoWatcher);
+		history.changeable_apply(HistoryChange::ClearHistory, &mut NoWatcher);
+		assert!(history.is_clean());
+	}
+}
@@ -1,59 +1,204 @@
+use std::fmt;
+use std::iter::Enumerate;
+use std::slice;
+
+/// An ID given out by an IdAlloc. The generation tells apart the entries that have used the same
+/// slot, so an ID for an entry that has been deallocated never refers to a newer entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AllocId {
+	index: usize,
+	gen: usize,
+}
+
+impl AllocId {
+	/// The slot the entry is stored in, which is reused by later entries.
+	pub fn index(&self) -> usize {
+		self.index
+	}
+	
+	pub fn gen(&self) -> usize {
+		self.gen
+	}
+}
+
+impl fmt::Display for AllocId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}+{}", self.index, self.gen)
+	}
+}
+
+struct Slot<T> {
+	/// The generation of the entry in the slot, or of the last one if it is empty.
+	gen: usize,
+	entry: Option<T>,
+}
+
 /// Basic linear allocator.
 pub struct IdAlloc<T> {
-	entries: Vec<Option<T>>,
+	slots: Vec<Slot<T>>,
 	empty_ids: Vec<usize>,
 }
 
 impl<T> IdAlloc<T> {
 	pub fn new() -> IdAlloc<T> {
 		IdAlloc {
-			entries: vec![],
+			slots: vec![],
 			empty_ids: vec![],
 		}
 	}
 	
-	/// Get the entry with the given ID as mut.
-	pub fn get_mut(&mut self, id: usize) -> &mut T {
-		self.entries[id].as_mut().unwrap()
+	/// Get the entry with the given ID as mut, or None if it has been deallocated.
+	pub fn try_get_mut(&mut self, id: AllocId) -> Option<&mut T> {
+		match self.slots.get_mut(id.index) {
+			Some(slot) if slot.gen == id.gen => slot.entry.as_mut(),
+			_ => None,
+		}
+	}
+	
+	/// Get the entry with the given ID, or None if it has been deallocated.
+	pub fn try_get(&self, id: AllocId) -> Option<&T> {
+		match self.slots.get(id.index) {
+			Some(slot) if slot.gen == id.gen => slot.entry.as_ref(),
+			_ => None,
+		}
+	}
+	
+	/// Get the entry with the given ID as mut. Panics if it has been deallocated.
+	pub fn get_mut(&mut self, id: AllocId) -> &mut T {
+		self.try_get_mut(id).expect("IdAlloc::get_mut called with a deallocated ID")
+	}
+	
+	/// Get the entry with the given ID. Panics if it has been deallocated.
+	pub fn get(&self, id: AllocId) -> &T {
+		self.try_get(id).expect("IdAlloc::get called with a deallocated ID")
+	}
+	
+	pub fn contains(&self, id: AllocId) -> bool {
+		self.try_get(id).is_some()
+	}
+	
+	/// The number of allocated entries.
+	pub fn len(&self) -> usize {
+		self.slots.len() - self.empty_ids.len()
 	}
 	
-	/// Get the entry with the given ID.
-	pub fn get(&self, id: usize) -> &T {
-		self.entries[id].as_ref().unwrap()
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
 	}
 	
 	/// Store the given new_entry with a new ID. Returns (the stored entry, the entry's ID).
-	pub fn allocate(&mut self, new_entry: T) -> (&mut T, usize) {
-		if let Some(id) = self.empty_ids.pop() {
-			let entry = &mut self.entries[id];
-			*entry = Some(new_entry);
-			(entry.as_mut().unwrap(), id)
+	pub fn allocate(&mut self, new_entry: T) -> (&mut T, AllocId) {
+		let index = if let Some(index) = self.empty_ids.pop() {
+			let slot = &mut self.slots[index];
+			slot.gen += 1;
+			slot.entry = Some(new_entry);
+			index
 		} else {
-			let id = self.entries.len();
-			self.entries.push(Some(new_entry));
-			(self.entries.last_mut().unwrap().as_mut().unwrap(), id)
+			self.slots.push(Slot { gen: 0, entry: Some(new_entry) });
+			self.slots.len() - 1
+		};
+		
+		let slot = &mut self.slots[index];
+		(slot.entry.as_mut().unwrap(), AllocId { index, gen: slot.gen })
+	}
+	
+	/// Deallocate the entry with the given ID, freeing up its slot for later allocations. Returns
+	/// the entry, or None if it was already deallocated.
+	pub fn deallocate(&mut self, id: AllocId) -> Option<T> {
+		let entry = match self.slots.get_mut(id.index) {
+			Some(slot) if slot.gen == id.gen => slot.entry.take(),
+			_ => None,
+		};
+		if entry.is_some() {
+			self.empty_ids.push(id.index);
 		}
+		entry
 	}
 	
-	/// Deallocate the entry with the given ID, freeing up the ID for later allocations.
-	pub fn deallocate(&mut self, id: usize) {
-		self.entries[id] = None;
-		self.empty_ids.push(id);
+	/// Iterates over the allocated entries and their IDs, in the order of their slots.
+	pub fn iter(&self) -> Iter<'_, T> {
+		Iter { slots: self.slots.iter().enumerate() }
 	}
 	
-	pub fn apply_to_all(&self, func: &mut FnMut(usize, &T)) {
-		for (id, ref opt_entry) in self.entries.iter().enumerate() {
-			if let &Some(ref entry) = *opt_entry {
-				func(id, entry);
+	/// Iterates over the allocated entries as mut, with their IDs.
+	pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+		IterMut { slots: self.slots.iter_mut().enumerate() }
+	}
+}
+
+pub struct Iter<'a, T> {
+	slots: Enumerate<slice::Iter<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = (AllocId, &'a T);
+	
+	fn next(&mut self) -> Option<(AllocId, &'a T)> {
+		for (index, slot) in &mut self.slots {
+			if let Some(ref entry) = slot.entry {
+				return Some((AllocId { index, gen: slot.gen }, entry));
 			}
 		}
+		None
 	}
+}
+
+pub struct IterMut<'a, T> {
+	slots: Enumerate<slice::IterMut<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+	type Item = (AllocId, &'a mut T);
 	
-	pub fn apply_to_all_mut(&mut self, func: &mut FnMut(usize, &mut T)) {
-		for (id, ref mut opt_entry) in self.entries.iter_mut().enumerate() {
-			if let &mut Some(ref mut entry) = *opt_entry {
-				func(id, entry);
+	fn next(&mut self) -> Option<(AllocId, &'a mut T)> {
+		for (index, slot) in &mut self.slots {
+			let gen = slot.gen;
+			if let Some(ref mut entry) = slot.entry {
+				return Some((AllocId { index, gen }, entry));
 			}
 		}
+		None
+	}
+}
+
+impl<'a, T> IntoIterator for &'a IdAlloc<T> {
+	type Item = (AllocId, &'a T);
+	type IntoIter = Iter<'a, T>;
+	
+	fn into_iter(self) -> Iter<'a, T> {
+		self.iter()
+	}
+}
+
+impl<'a, T> IntoIterator for &'a mut IdAlloc<T> {
+	type Item = (AllocId, &'a mut T);
+	type IntoIter = IterMut<'a, T>;
+	
+	fn into_iter(self) -> IterMut<'a, T> {
+		self.iter_mut()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test] fn stale_ids_miss_reused_slots() {
+		let mut alloc = IdAlloc::new();
+		let (_, a) = alloc.allocate("a");
+		let (_, b) = alloc.allocate("b");
+		assert_eq!(alloc.deallocate(a), Some("a"));
+		assert_eq!(alloc.deallocate(a), None);
+		
+		let (_, c) = alloc.allocate("c");
+		assert_eq!(c.index(), a.index());
+		assert_eq!(alloc.try_get(a), None);
+		assert_eq!(alloc.try_get(c), Some(&"c"));
+		assert_eq!(alloc.len(), 2);
+		
+		for (_, entry) in &mut alloc {
+			*entry = "d";
+		}
+		assert_eq!(alloc.iter().collect::<Vec<_>>(), vec![(c, &"d"), (b, &"d")]);
 	}
 }
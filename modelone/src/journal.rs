@@ -0,0 +1,321 @@
+use crate::model::{Change, Changeable, Watcher, NoWatcher, ApplyError};
+use crate::object::{Object, ApplyContext};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// Converts the records written by a [`Journaled`](struct.Journaled.html) model to bytes and
+/// back. A Journaled model needs an encoder for both its model, which is written in snapshots,
+/// and its change type.
+pub trait JournalEncoder<R> {
+	fn encode(&mut self, record: &R) -> io::Result<Vec<u8>>;
+	fn decode(&mut self, bytes: &[u8]) -> io::Result<R>;
+}
+
+/// Each snapshot and journal file starts with this many bytes holding a generation number, so
+/// that a journal that was already written into a snapshot is never replayed.
+const HEADER_LEN: usize = 8;
+
+/// Wraps a model so that every change applied to it is appended to a journal file, so it can be
+/// rebuilt after a crash.
+///
+/// The journal is replayed onto the last snapshot when it is opened, so wrapping a
+/// [`History`](../history/struct.History.html) rebuilds its undo and redo stacks too, since
+/// transaction boundaries, undo and redo are all HistoryChanges. This implements Changeable and
+/// Object, so it can be used as the model of a [`Manager`](../object/struct.Manager.html).
+pub struct Journaled<M, CT, E> {
+	model: M,
+	encoder: E,
+	journal: File,
+	snapshot_path: PathBuf,
+	generation: u64,
+	/// The first error from writing the journal. Nothing more is written after an error, because
+	/// the file might have been left with part of a record, until a snapshot starts a new one.
+	error: Option<io::Error>,
+	_change: PhantomData<fn(CT)>,
+}
+
+impl<M, CT, E> Journaled<M, CT, E> where
+	M: Changeable<CT>,
+	CT: Change,
+	E: JournalEncoder<M> + JournalEncoder<CT>,
+{
+	/// Opens the journal at `path`, rebuilding the model from the snapshot next to it and the
+	/// changes recorded since. `initial` is used when there is no snapshot yet. A record that was
+	/// only partly written when the program stopped is discarded.
+	pub fn open<P: AsRef<Path>, F: FnOnce() -> M>(path: P, mut encoder: E, initial: F) -> io::Result<Journaled<M, CT, E>> {
+		let path = path.as_ref();
+		let snapshot_path = snapshot_path(path);
+		
+		let (mut model, generation) = match fs::read(&snapshot_path) {
+			Ok(bytes) => {
+				let (generation, body) = split_header(&bytes)
+					.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Snapshot is missing its header"))?;
+				(JournalEncoder::<M>::decode(&mut encoder, body)?, generation)
+			},
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => (initial(), 0),
+			Err(err) => return Err(err),
+		};
+		
+		// The existing journal is kept so it can be replayed
+		let mut journal = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+		let mut bytes = vec![];
+		journal.read_to_end(&mut bytes)?;
+		
+		let mut valid_len = 0;
+		if let Some((journal_generation, mut records)) = split_header(&bytes) {
+			// An older journal was already included in the snapshot before it could be cleared
+			if journal_generation == generation {
+				valid_len = HEADER_LEN;
+				while let Some((record, rest)) = split_record(records) {
+					let change: CT = JournalEncoder::<CT>::decode(&mut encoder, record)?;
+					model.validate_change(&change).map_err(invalid_record)?;
+					model.changeable_apply(change, &mut NoWatcher);
+					valid_len += records.len() - rest.len();
+					records = rest;
+				}
+			}
+		}
+		
+		if valid_len == 0 {
+			journal.set_len(0)?;
+			journal.seek(SeekFrom::Start(0))?;
+			journal.write_all(&generation.to_le_bytes())?;
+		} else {
+			journal.set_len(valid_len as u64)?;
+			journal.seek(SeekFrom::End(0))?;
+		}
+		
+		Ok(Journaled {
+			model,
+			encoder,
+			journal,
+			snapshot_path,
+			generation,
+			error: None,
+			_change: PhantomData,
+		})
+	}
+	
+	/// Writes the whole model to the snapshot file and starts an empty journal, so that opening
+	/// it again doesn't need to replay as many changes. This also resumes journaling after a
+	/// write error.
+	pub fn snapshot(&mut self) -> io::Result<()> {
+		let generation = self.generation + 1;
+		let mut bytes = generation.to_le_bytes().to_vec();
+		bytes.extend(JournalEncoder::<M>::encode(&mut self.encoder, &self.model)?);
+		
+		// Writing to another file and renaming it means a crash leaves either the old snapshot
+		// or the new one
+		let mut temp_path = self.snapshot_path.clone().into_os_string();
+		temp_path.push(".tmp");
+		{
+			let mut temp = File::create(&temp_path)?;
+			temp.write_all(&bytes)?;
+			temp.sync_all()?;
+		}
+		fs::rename(&temp_path, &self.snapshot_path)?;
+		self.generation = generation;
+		
+		self.journal.set_len(0)?;
+		self.journal.seek(SeekFrom::Start(0))?;
+		self.journal.write_all(&generation.to_le_bytes())?;
+		self.error = None;
+		Ok(())
+	}
+}
+
+impl<M, CT, E> Journaled<M, CT, E> {
+	pub fn get(&self) -> &M {
+		&self.model
+	}
+	
+	/// Unwraps the model, leaving the journal and snapshot on disk.
+	pub fn into_inner(self) -> M {
+		self.model
+	}
+	
+	/// Returns the error that stopped the journal from being written, if there was one. Changes
+	/// are still applied to the model after an error, but they aren't journaled until the next
+	/// successful snapshot.
+	pub fn take_error(&mut self) -> Option<io::Error> {
+		self.error.take()
+	}
+	
+	/// Whether an error has stopped the journal from being written.
+	pub fn has_error(&self) -> bool {
+		self.error.is_some()
+	}
+	
+	/// Flushes the journal to the disk. Records are written as soon as changes are applied, so
+	/// they survive the program crashing, but this is needed for them to survive the system
+	/// crashing too.
+	pub fn sync(&mut self) -> io::Result<()> {
+		self.journal.sync_data()
+	}
+}
+
+impl<M, CT, E> Changeable<CT> for Journaled<M, CT, E> where
+	M: Changeable<CT>,
+	CT: Change,
+	E: JournalEncoder<M> + JournalEncoder<CT> + Send,
+{
+	fn changeable_apply(&mut self, change: CT, watcher: &mut Watcher<CT::SignalType>) {
+		// The change is encoded before it is consumed, but only written once it has been applied,
+		// so a change that panics isn't replayed. A change that the model doesn't accept isn't
+		// written either, because it would stop the journal from being replayed.
+		let record = if self.error.is_none() && self.model.validate_change(&change).is_ok() {
+			match JournalEncoder::<CT>::encode(&mut self.encoder, &change) {
+				Ok(record) => Some(record),
+				Err(err) => {
+					self.error = Some(err);
+					None
+				},
+			}
+		} else {
+			None
+		};
+		
+		self.model.changeable_apply(change, watcher);
+		
+		if let Some(record) = record {
+			let mut bytes = (record.len() as u32).to_le_bytes().to_vec();
+			bytes.extend(record);
+			if let Err(err) = self.journal.write_all(&bytes) {
+				self.error = Some(err);
+			}
+		}
+	}
+	
+	fn reset_view_signals(&self) -> Vec<CT::SignalType> {
+		self.model.reset_view_signals()
+	}
+	
+	fn validate_change(&self, change: &CT) -> Result<(), ApplyError> {
+		self.model.validate_change(change)
+	}
+}
+
+impl<M, CT, E> Object<CT> for Journaled<M, CT, E> where
+	M: Object<CT>,
+	CT: Change,
+{
+	fn update(&self, cxt: &mut ApplyContext<CT>, signal: &CT::SignalType) {
+		self.model.update(cxt, signal);
+	}
+}
+
+/// The snapshot for the journal at `path` has the same name with ".snapshot" added.
+fn snapshot_path(path: &Path) -> PathBuf {
+	let mut snapshot_path = path.as_os_str().to_owned();
+	snapshot_path.push(".snapshot");
+	snapshot_path.into()
+}
+
+fn split_header(bytes: &[u8]) -> Option<(u64, &[u8])> {
+	if bytes.len() < HEADER_LEN {
+		return None;
+	}
+	let mut header = [0; HEADER_LEN];
+	header.copy_from_slice(&bytes[..HEADER_LEN]);
+	Some((u64::from_le_bytes(header), &bytes[HEADER_LEN..]))
+}
+
+/// Splits the first length-prefixed record from `bytes`, or returns None if it wasn't fully
+/// written.
+fn split_record(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+	if bytes.len() < 4 {
+		return None;
+	}
+	let mut len = [0; 4];
+	len.copy_from_slice(&bytes[..4]);
+	let len = u32::from_le_bytes(len) as usize;
+	if bytes.len() - 4 < len {
+		return None;
+	}
+	Some((&bytes[4..4 + len], &bytes[4 + len..]))
+}
+
+fn invalid_record(err: ApplyError) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("Journal has a change that can't be applied: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::history::{History, HistoryChange};
+	use crate::change_string::StringChange;
+	
+	#[cfg(feature = "serde")]
+	struct JsonEncoder;
+	
+	#[cfg(feature = "serde")]
+	impl<R: serde::Serialize + serde::de::DeserializeOwned> JournalEncoder<R> for JsonEncoder {
+		fn encode(&mut self, record: &R) -> io::Result<Vec<u8>> {
+			Ok(serde_json::to_vec(record)?)
+		}
+		
+		fn decode(&mut self, bytes: &[u8]) -> io::Result<R> {
+			Ok(serde_json::from_slice(bytes)?)
+		}
+	}
+	
+	#[test] fn records_are_split_until_a_partial_one() {
+		let bytes = [2, 0, 0, 0, 7, 8, 5, 0, 0, 0, 1];
+		let (record, rest) = split_record(&bytes).unwrap();
+		assert_eq!(record, &[7, 8]);
+		assert_eq!(split_record(rest), None);
+	}
+	
+	#[cfg(feature = "serde")]
+	#[test] fn journaled_history_recovers() {
+		type Model = History<String, StringChange>;
+		let path = std::env::temp_dir().join(format!("modelone_journal_test_{}", std::process::id()));
+		let open = || Journaled::<Model, HistoryChange<String, StringChange>, _>::open(&path, JsonEncoder, || History::new("".into())).unwrap();
+		
+		let mut journaled = open();
+		journaled.changeable_apply(HistoryChange::NewTransaction("Hello".into()), &mut NoWatcher);
+		journaled.changeable_apply(HistoryChange::Push(StringChange{index: 0, len: 0, new: "Hello".into()}), &mut NoWatcher);
+		journaled.snapshot().unwrap();
+		journaled.changeable_apply(HistoryChange::NewTransaction("World".into()), &mut NoWatcher);
+		journaled.changeable_apply(HistoryChange::Push(StringChange{index: 5, len: 0, new: " World".into()}), &mut NoWatcher);
+		journaled.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		drop(journaled);
+		
+		// Simulate a crash partway through writing a record
+		OpenOptions::new().append(true).open(&path).unwrap().write_all(&[100, 0, 0, 0, 1]).unwrap();
+		
+		let mut journaled = open();
+		assert_eq!(journaled.get().model, "Hello");
+		assert_eq!(journaled.get().redo_transactions()[0].name, "World");
+		journaled.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		drop(journaled);
+		
+		let mut journaled = open();
+		assert_eq!(journaled.get().model, "Hello World");
+		journaled.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		journaled.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		// History ignores this, so it isn't journaled
+		journaled.changeable_apply(HistoryChange::SelectBranch(1), &mut NoWatcher);
+		assert_eq!(journaled.get().model, "");
+		assert!(journaled.take_error().is_none());
+		drop(journaled);
+		
+		let journaled = open();
+		assert_eq!(journaled.get().model, "");
+		drop(journaled);
+		
+		// A record that doesn't fit the model is reported rather than applied
+		let record = serde_json::to_vec(&HistoryChange::<String, StringChange>::Push(StringChange{index: 50, len: 0, new: "!".into()})).unwrap();
+		let mut bytes = (record.len() as u32).to_le_bytes().to_vec();
+		bytes.extend(record);
+		OpenOptions::new().append(true).open(&path).unwrap().write_all(&bytes).unwrap();
+		let result = Journaled::<Model, HistoryChange<String, StringChange>, _>::open(&path, JsonEncoder, || History::new("".into()));
+		assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+		
+		fs::remove_file(&path).unwrap();
+		fs::remove_file(snapshot_path(&path)).unwrap();
+	}
+}
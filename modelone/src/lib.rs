@@ -1,12 +1,30 @@
+// This lets the code generated by the model macros and derives refer to `modelone` when they are
+// expanded inside this crate.
+extern crate self as modelone;
+
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub use serde;
+
 mod idalloc;
 #[macro_use] pub mod model;
 #[macro_use] pub mod object;
 
 pub mod change_box;
+pub mod change_map;
 pub mod change_option;
+pub mod change_rope;
+pub mod change_set;
 pub mod change_string;
 pub mod change_value;
 pub mod change_vec;
+pub mod diff;
+pub mod graph;
 pub mod history;
+pub mod journal;
+pub mod recorder;
+pub mod transform;
+pub mod undo_tree;
 
 pub use crate::idalloc::*;
+pub use modelone_derive::{Changeable, Diff, Revertable, Transform};
@@ -2,6 +2,7 @@ use std;
 use std::mem;
 use std::process;
 use std::any::Any;
+use crate::change_string::StringChangeError;
 
 /// A trait for things than can watch for signals sent when applying a Change.
 pub trait Watcher<ST> {
@@ -61,8 +62,16 @@ pub trait Change: Send + Sized + Any {
 	type SignalType: std::fmt::Debug + Clone + PartialEq;
 	// All changes come with a method of
 	//type ConstructorType: ChangeConstructor<Self>;
-
+	
 	//fn get_signals(&self) -> Vec<Self::SignalType> { vec![] }
+	
+	/// Tries to merge `next`, which is a change that would be applied straight after this one,
+	/// into this change, so that applying this change has the same effect as applying both. If the
+	/// changes can't be merged, `next` is returned back unchanged. The default implementation
+	/// never merges anything.
+	fn coalesce(&mut self, next: Self) -> Result<(), Self> {
+		Err(next)
+	}
 }
 
 /// Implement this on a type VT to allow VT to be updated with the data in CT.
@@ -84,6 +93,22 @@ pub trait Changeable<CT: Change>: Send {
 	/// that the length to remove is usize::MAX, so it will always replace
 	/// everything.
 	fn reset_view_signals(&self) -> Vec<CT::SignalType>;
+	
+	/// Checks whether `change` can be applied to self, and returns an error describing why if it
+	/// can't, such as when it refers to an index that is out of range. Implementations for types
+	/// that hold other changeable values should also check nested changes, adding to the error's
+	/// path. The default implementation accepts every change.
+	fn validate_change(&self, _change: &CT) -> Result<(), ApplyError> {
+		Ok(())
+	}
+	
+	/// The same as changeable_apply, except that it checks the change with validate_change first,
+	/// and returns the error without changing anything if the change can't be applied.
+	fn try_changeable_apply(&mut self, change: CT, watcher: &mut Watcher<CT::SignalType>) -> Result<(), ApplyError> {
+		self.validate_change(&change)?;
+		self.changeable_apply(change, watcher);
+		Ok(())
+	}
 }
 
 /// This extends on Changeable if you can take any instance of the given
@@ -96,6 +121,100 @@ pub trait Revertable<CT: Change>: Changeable<CT> {
 	/// This applies the given `change` to self, then returns a change that
 	/// can be used to revert it
 	fn revertable_apply(&mut self, change: CT, watcher: &mut Watcher<CT::SignalType>) -> CT;
+	
+	/// The same as revertable_apply, except that it checks the change with validate_change first,
+	/// and returns the error without changing anything if the change can't be applied.
+	fn try_revertable_apply(&mut self, change: CT, watcher: &mut Watcher<CT::SignalType>) -> Result<CT, ApplyError> {
+		self.validate_change(&change)?;
+		Ok(self.revertable_apply(change, watcher))
+	}
+}
+
+/// The error returned when a change can't be applied, along with the path to the nested change
+/// that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplyError {
+	/// The path from the outermost change to the nested change that failed, such as
+	/// `["editor", "Push", "[3]"]`. Struct fields and enum variants are given by name, and Vec and
+	/// map items are given as `[index]` or `[key]`.
+	pub path: Vec<String>,
+	pub kind: ApplyErrorKind,
+}
+
+impl ApplyError {
+	pub fn new(kind: ApplyErrorKind) -> ApplyError {
+		ApplyError { path: vec![], kind }
+	}
+	
+	/// Adds `segment` to the start of the path. This is used when passing up an error from a
+	/// nested change.
+	pub fn within<S: Into<String>>(mut self, segment: S) -> ApplyError {
+		self.path.insert(0, segment.into());
+		self
+	}
+}
+
+impl From<ApplyErrorKind> for ApplyError {
+	fn from(kind: ApplyErrorKind) -> ApplyError {
+		ApplyError::new(kind)
+	}
+}
+
+impl std::fmt::Display for ApplyError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		if self.path.is_empty() {
+			write!(f, "{}", self.kind)
+		} else {
+			write!(f, "{}: {}", self.path.join("/"), self.kind)
+		}
+	}
+}
+
+impl std::error::Error for ApplyError {}
+
+/// Why a change couldn't be applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyErrorKind {
+	/// A change referred to `index` in a collection that only has `len` items.
+	IndexOutOfBounds{index: usize, len: usize},
+	/// A change referred to a key that isn't in the map. The key is given in its Debug format.
+	MissingKey(String),
+	/// A nested change was applied to an Option that is None.
+	NoValue,
+	/// A nested change was applied to an enum holding a different variant.
+	EnumVariant(EnumVariantError),
+	/// A StringChange didn't fit the text it was applied to.
+	Text(StringChangeError),
+	/// A Manager's Validator rejected the batch of changes that this change was part of.
+	Validation(String),
+	/// Any other reason, for custom Changeable implementations.
+	Other(String),
+}
+
+impl std::fmt::Display for ApplyErrorKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			ApplyErrorKind::IndexOutOfBounds{index, len} => write!(f, "Index {} is out of bounds for length {}", index, len),
+			ApplyErrorKind::MissingKey(ref key) => write!(f, "Key {} is not in the map", key),
+			ApplyErrorKind::NoValue => write!(f, "Change applied to None"),
+			ApplyErrorKind::EnumVariant(ref err) => err.fmt(f),
+			ApplyErrorKind::Text(ref err) => err.fmt(f),
+			ApplyErrorKind::Validation(ref message) => write!(f, "Validation failed: {}", message),
+			ApplyErrorKind::Other(ref message) => write!(f, "{}", message),
+		}
+	}
+}
+
+impl From<EnumVariantError> for ApplyError {
+	fn from(err: EnumVariantError) -> ApplyError {
+		ApplyError::new(ApplyErrorKind::EnumVariant(err))
+	}
+}
+
+impl From<StringChangeError> for ApplyError {
+	fn from(err: StringChangeError) -> ApplyError {
+		ApplyError::new(ApplyErrorKind::Text(err))
+	}
 }
 
 /// Implement this on a type that describes how to build a change that is nested inside another
@@ -109,6 +228,13 @@ pub trait ChangeConstructor<C: Change> {
 	/// Updates the ChangeConstructor, returns true if the thing the change applied to still exists.
 	fn update<'s, 'c>(&'s mut self, change: &'c C) -> bool;
 	
+	/// Returns true if the change modifies the thing the change constructor builds changes for,
+	/// including replacing or removing it. Call this before `update` with the same change. The
+	/// default returns true for every change, which is always safe but means nothing is filtered.
+	fn affects(&self, _change: &C) -> bool {
+		true
+	}
+	
 	fn debug_string(&self) -> String;
 }
 
@@ -138,6 +264,10 @@ impl<C: 'static + Change> ChangeConstructor<C> for LeafChangeConstructor<C> {
 		true
 	}
 	
+	fn affects(&self, _change: &C) -> bool {
+		true
+	}
+	
 	fn debug_string(&self) -> String {
 		"?".into()
 	}
@@ -147,6 +277,7 @@ pub struct SubChangeConstructor<PC: Change, C: Change> {
 	sub_constructor: Box<ChangeConstructor<C>>,
 	create_fn: fn(&Box<ChangeConstructor<C>>, Box<std::any::Any>) -> PC,
 	update_fn: fn(&mut Box<ChangeConstructor<C>>, &PC) -> bool,
+	affects_fn: fn(&Box<ChangeConstructor<C>>, &PC) -> bool,
 	debug_string_fn: fn(&Box<ChangeConstructor<C>>) -> String,
 }
 
@@ -155,9 +286,10 @@ impl<PC: Change, C: Change> SubChangeConstructor<PC, C> {
 		sub_constructor: Box<ChangeConstructor<C>>,
 		create_fn: fn(&Box<ChangeConstructor<C>>, Box<std::any::Any>) -> PC,
 		update_fn: fn(&mut Box<ChangeConstructor<C>>, &PC) -> bool,
+		affects_fn: fn(&Box<ChangeConstructor<C>>, &PC) -> bool,
 		debug_string_fn: fn(&Box<ChangeConstructor<C>>) -> String,
 	) -> SubChangeConstructor<PC, C> {
-		SubChangeConstructor { sub_constructor, create_fn, update_fn, debug_string_fn }
+		SubChangeConstructor { sub_constructor, create_fn, update_fn, affects_fn, debug_string_fn }
 	}
 }
 
@@ -170,6 +302,10 @@ impl<PC: Change, C: Change> ChangeConstructor<PC> for SubChangeConstructor<PC, C
 		(self.update_fn)(&mut self.sub_constructor, change)
 	}
 	
+	fn affects(&self, change: &PC) -> bool {
+		(self.affects_fn)(&self.sub_constructor, change)
+	}
+	
 	fn debug_string(&self) -> String {
 		(self.debug_string_fn)(&self.sub_constructor)
 	}
@@ -214,6 +350,36 @@ macro_rules! macro_id{
 	(($tok:tt)*) => {$($tok)*}
 }*/
 
+/// Wraps the given item so that it derives serde's `Serialize` and `Deserialize` when the `serde`
+/// feature is enabled. This is used by the model macros so that the change and signal types they
+/// generate can be serialized. The item can be preceded by `bound("...", "...")` to give serde
+/// explicit serialize and deserialize bounds.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export] macro_rules! impl_serde_derive{
+	(bound($serialize_bound:tt, $deserialize_bound:tt) $($item:tt)*) => {
+		#[derive($crate::serde::Serialize, $crate::serde::Deserialize)]
+		#[serde(crate = "modelone::serde", bound(serialize = $serialize_bound, deserialize = $deserialize_bound))]
+		$($item)*
+	};
+	($($item:tt)*) => {
+		#[derive($crate::serde::Serialize, $crate::serde::Deserialize)]
+		#[serde(crate = "modelone::serde")]
+		$($item)*
+	};
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export] macro_rules! impl_serde_derive{
+	(bound($serialize_bound:tt, $deserialize_bound:tt) $($item:tt)*) => {
+		$($item)*
+	};
+	($($item:tt)*) => {
+		$($item)*
+	};
+}
+
 #[macro_export] macro_rules! impl_changeable_body{
 	(on_changed $model_name:ident (&mut $arg:ident) $body:expr) => {
 		fn on_changed($arg: &mut $model_name) {
@@ -222,9 +388,16 @@ macro_rules! macro_id{
 	};
 }
 
+/// Generates change and signal enums for a struct model, with one variant per field that applies a
+/// nested change to that field.
+///
+/// Transform for the change type and Diff for the model can also be implemented from the same
+/// field list by naming them after the model with `with`, such as
+/// `impl_changeable_struct!{DocChange[DocSignal] for Doc with Transform, Diff: ...}`. Each field
+/// must then support the trait too.
 //$(do $action_name => $action:expr,)* 
 #[macro_export] macro_rules! impl_changeable_struct{
-	($change_name:ident[$signal_name:ident] for $model_name:ident:
+	($change_name:ident[$signal_name:ident] for $model_name:ident $(with $($trait_name:ident),+)?:
 		$(on_reset => ($($reset_arg:tt)*) $reset_body:expr,)*
 		$($field_name:ident: $change_type:ty $(=> $mod:ident ($($arg:tt)*) $body:expr)*,)+
 	) => {
@@ -234,23 +407,39 @@ macro_rules! macro_id{
 			)*
 		}*/
 		
-		#[allow(non_camel_case_types)]
-		#[derive(Debug, Clone, PartialEq)]
-		pub enum $signal_name {
- 			$(
-				$field_name(<$change_type as $crate::model::Change>::SignalType),
-			)*
+		$crate::impl_serde_derive!{
+			#[allow(non_camel_case_types)]
+			#[derive(Debug, Clone, PartialEq)]
+			pub enum $signal_name {
+				$(
+					$field_name(<$change_type as $crate::model::Change>::SignalType),
+				)*
+			}
 		}
 		
-		#[allow(non_camel_case_types)]
-		#[derive(Debug, Clone, PartialEq)]
-		pub enum $change_name {
-			$($field_name($change_type),)*
+		$crate::impl_serde_derive!{
+			#[allow(non_camel_case_types)]
+			#[derive(Debug, Clone, PartialEq)]
+			pub enum $change_name {
+				$($field_name($change_type),)*
+			}
 		}
 		
 		impl $crate::model::Change for $change_name {
 			type SignalType = $signal_name;
 			
+			fn coalesce(&mut self, next: $change_name) -> Result<(), $change_name> {
+				#[allow(unreachable_patterns)]
+				match (self, next) {
+					$(
+						($change_name::$field_name(subchange), $change_name::$field_name(next_subchange)) => {
+							$crate::model::Change::coalesce(subchange, next_subchange).map_err($change_name::$field_name)
+						},
+					)*
+					(_, next) => Err(next),
+				}
+			}
+			
 			/*fn get_signals(&self) -> Vec<Self::SignalType> {
 				match *self {
 					$($change_name::$field_name(ref subchange) => {
@@ -290,23 +479,73 @@ macro_rules! macro_id{
 				)*
 				signals
 			}
+			
+			fn validate_change(&self, change: &$change_name) -> Result<(), $crate::model::ApplyError> {
+				match *change {
+					$(
+						$change_name::$field_name(ref subchange) => {
+							$crate::model::Changeable::<$change_type>::validate_change(&self.$field_name, subchange)
+								.map_err(|err| err.within(stringify!($field_name)))
+						},
+					)*
+				}
+			}
+		}
+		
+		$crate::impl_struct_traits!{[$($($trait_name)+)?] $change_name[$signal_name] for $model_name:
+			$($field_name: $change_type,)*
 		}
 	};
 	// This allows for not including a final trailing comma
-	($change_name:ident[$signal_name:ident] for $model_name:ident:
+	($change_name:ident[$signal_name:ident] for $model_name:ident $(with $($trait_name:ident),+)?:
 		$($field_name:ident: $change_type:ty),+
 	) => {
-		impl_changeable_struct!{$change_name[$signal_name] for $model_name:
+		impl_changeable_struct!{$change_name[$signal_name] for $model_name $(with $($trait_name),+)?:
+			$($field_name: $change_type,)*
+		}
+	};
+}
+
+/// Implements each of the listed traits for a struct's change type, or for the model in the case
+/// of Diff. This is used by
+/// `impl_changeable_struct!` for the traits named with `with`.
+#[doc(hidden)]
+#[macro_export] macro_rules! impl_struct_traits{
+	([] $change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)*
+	) => {};
+	([Transform $($rest:ident)*] $change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)*
+	) => {
+		$crate::impl_transform_struct!{$change_name[$signal_name] for $model_name:
+			$($field_name: $change_type,)*
+		}
+		$crate::impl_struct_traits!{[$($rest)*] $change_name[$signal_name] for $model_name:
+			$($field_name: $change_type,)*
+		}
+	};
+	([Diff $($rest:ident)*] $change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)*
+	) => {
+		$crate::impl_diff_struct!{$change_name[$signal_name] for $model_name:
+			$($field_name: $change_type,)*
+		}
+		$crate::impl_struct_traits!{[$($rest)*] $change_name[$signal_name] for $model_name:
 			$($field_name: $change_type,)*
 		}
 	};
+	([$trait_name:ident $($rest:ident)*] $change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)*
+	) => {
+		compile_error!(concat!("impl_changeable_struct! can't implement ", stringify!($trait_name)));
+	};
 }
 
 #[macro_export] macro_rules! impl_revertable_struct{
-	($change_name:ident[$signal_name:ident] for $model_name:ident:
+	($change_name:ident[$signal_name:ident] for $model_name:ident $(with $($trait_name:ident),+)?:
 		$($field_name:ident: $change_type:ty,)+
 	) => {
-		impl_changeable_struct!{$change_name[$signal_name] for $model_name:
+		impl_changeable_struct!{$change_name[$signal_name] for $model_name $(with $($trait_name),+)?:
 			$($field_name: $change_type,)*
 		}
 		
@@ -326,54 +565,100 @@ macro_rules! macro_id{
 		}
 	};
 	// This allows for not including a final trailing comma
-	($change_name:ident[$signal_name:ident] for $model_name:ident:
+	($change_name:ident[$signal_name:ident] for $model_name:ident $(with $($trait_name:ident),+)?:
 		$($field_name:ident: $change_type:ty),+
 	) => (
-		impl_revertable_struct!{$change_name[$signal_name] for $model_name:
+		impl_revertable_struct!{$change_name[$signal_name] for $model_name $(with $($trait_name),+)?:
 			$($field_name: $change_type,)*
 		}
 	)
 }
 
-/*macro_rules! impl_changeable_enum{
+/// The error returned when a change for one variant of an enum is applied to a value holding a
+/// different variant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariantError {
+	/// The name of the variant the change was made for.
+	pub expected: &'static str,
+}
+
+impl std::fmt::Display for EnumVariantError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "Change for enum variant {} applied to a different variant", self.expected)
+	}
+}
+
+impl std::error::Error for EnumVariantError {}
+
+/// Generates change and signal enums for an enum model whose variants each hold a single value.
+/// The change enum has a `SetEnumValue` variant, which replaces the whole value, plus one variant
+/// per enum variant that applies a nested change to the value held by that variant.
+///
+/// Applying a nested change to a value that holds a different variant panics. Use
+/// `validate_change`, `try_changeable_apply` or `try_revertable_apply` to get an ApplyError
+/// instead.
+///
+/// When the `serde` feature is enabled, the model type must also implement `Serialize` and
+/// `Deserialize`, because `SetEnumValue` holds a whole value.
+#[macro_export] macro_rules! impl_changeable_enum{
 	($change_name:ident[$signal_name:ident] for $model_name:ident:
 		$($variant_name:ident: $change_type:ty,)+
 	) => {
-		#[derive(Debug, Clone, PartialEq)]
-		pub enum $signal_name {
-			SetEnumValue,
- 			$(
-				$variant_name(<$change_type as Change>::SignalType),
-			)*
+		$crate::impl_serde_derive!{
+			#[derive(Debug, Clone, PartialEq)]
+			pub enum $signal_name {
+				SetEnumValue,
+				$(
+					$variant_name(<$change_type as $crate::model::Change>::SignalType),
+				)*
+			}
 		}
 		
-		#[derive(Debug, Clone, PartialEq)]
-		pub enum $change_name {
-			SetEnumValue($model_name),
-			$($variant_name($change_type),)*
+		$crate::impl_serde_derive!{
+			#[derive(Debug, Clone, PartialEq)]
+			pub enum $change_name {
+				SetEnumValue($model_name),
+				$($variant_name($change_type),)*
+			}
 		}
 		
-		impl Change for $change_name {
+		impl $crate::model::Change for $change_name {
 			type SignalType = $signal_name;
+			
+			fn coalesce(&mut self, next: $change_name) -> Result<(), $change_name> {
+				match (self, next) {
+					(this @ $change_name::SetEnumValue(_), next @ $change_name::SetEnumValue(_)) => {
+						*this = next;
+						Ok(())
+					},
+					$(
+						($change_name::$variant_name(subchange), $change_name::$variant_name(next_subchange)) => {
+							$crate::model::Change::coalesce(subchange, next_subchange).map_err($change_name::$variant_name)
+						},
+					)*
+					(_, next) => Err(next),
+				}
+			}
 		}
 		
-		impl Changeable<$change_name> for $model_name {
-			fn changeable_apply(&mut self, change: $change_name, watcher: &mut Watcher<$signal_name>) {
-				use $change_name::*;
+		impl $crate::model::Changeable<$change_name> for $model_name {
+			fn changeable_apply(&mut self, change: $change_name, watcher: &mut $crate::model::Watcher<$signal_name>) {
 				match change {
 					$change_name::SetEnumValue(value) => {
 						*self = value;
+						watcher.send_signal($signal_name::SetEnumValue);
 					},
 					$(
 						$change_name::$variant_name(subchange) => {
-							if let $model_name::$variant_name(ref mut subvalue) = *self {
-								let mut watcher_fn = |signal| {
-									watcher.send_signal($signal_name::$variant_name(signal));
-								};
-								
-								subvalue.changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
-							} else {
-								println!("Applied change to wrong enum variant");
+							#[allow(unreachable_patterns)]
+							match *self {
+								$model_name::$variant_name(ref mut subvalue) => {
+									let mut watcher_fn = |signal| {
+										watcher.send_signal($signal_name::$variant_name(signal));
+									};
+									$crate::model::Changeable::<$change_type>::changeable_apply(subvalue, subchange, &mut $crate::model::SubWatcher::new(&mut watcher_fn));
+								},
+								_ => panic!("{}", $crate::model::EnumVariantError { expected: stringify!($variant_name) }),
 							}
 						},
 					)*
@@ -381,8 +666,25 @@ macro_rules! macro_id{
 			}
 			
 			fn reset_view_signals(&self) -> Vec<$signal_name> {
-				let mut signals = vec![$signal_name::SetEnumValue];
-				signals
+				vec![$signal_name::SetEnumValue]
+			}
+			
+			fn validate_change(&self, change: &$change_name) -> Result<(), $crate::model::ApplyError> {
+				match *change {
+					$change_name::SetEnumValue(_) => Ok(()),
+					$(
+						$change_name::$variant_name(ref subchange) => {
+							#[allow(unreachable_patterns)]
+							match *self {
+								$model_name::$variant_name(ref subvalue) => {
+									$crate::model::Changeable::<$change_type>::validate_change(subvalue, subchange)
+										.map_err(|err| err.within(stringify!($variant_name)))
+								},
+								_ => Err($crate::model::EnumVariantError { expected: stringify!($variant_name) }.into()),
+							}
+						},
+					)*
+				}
 			}
 		}
 	};
@@ -396,7 +698,9 @@ macro_rules! macro_id{
 	};
 }
 
-macro_rules! impl_revertable_enum{
+/// The same as impl_changeable_enum, but also implements Revertable. Setting the whole value
+/// reverts to the previous value, and nested changes revert with the nested revert change.
+#[macro_export] macro_rules! impl_revertable_enum{
 	($change_name:ident[$signal_name:ident] for $model_name:ident:
 		$($variant_name:ident: $change_type:ty,)+
 	) => {
@@ -404,35 +708,26 @@ macro_rules! impl_revertable_enum{
 			$($variant_name: $change_type,)*
 		}
 		
-		impl Revertable<$change_name> for $model_name {
-			fn revertable_apply(&mut self, change: $change_name, watcher: &mut Watcher<$signal_name>) -> $change_name {
-				use $change_name::*;
+		impl $crate::model::Revertable<$change_name> for $model_name {
+			fn revertable_apply(&mut self, change: $change_name, watcher: &mut $crate::model::Watcher<$signal_name>) -> $change_name {
 				match change {
 					$change_name::SetEnumValue(mut value) => {
 						std::mem::swap(self, &mut value);
+						watcher.send_signal($signal_name::SetEnumValue);
 						$change_name::SetEnumValue(value)
 					},
 					$(
 						$change_name::$variant_name(subchange) => {
-							if let $model_name::$variant_name(ref mut subvalue) = *self {
-								let mut watcher_fn = |signal| {
-									watcher.send_signal($signal_name::$variant_name(signal));
-								};
-								
-								subvalue.changeable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn));
-							} else {
-								println!("Applied change to wrong enum variant");
-							}
-						},
-						
-						$change_name::$variant_name(subchange) => {
-							if let $model_name::$variant_name(ref mut subvalue) = *self {
-								let mut watcher_fn = |signal| {
-									watcher.send_signal($signal_name::$variant_name(signal));
-								};
-								$change_name::$variant_name(self.$variant_name.revertable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn)))
-							} else {
-								println!("Applied change to wrong enum variant");
+							#[allow(unreachable_patterns)]
+							match *self {
+								$model_name::$variant_name(ref mut subvalue) => {
+									let mut watcher_fn = |signal| {
+										watcher.send_signal($signal_name::$variant_name(signal));
+									};
+									let revertchange = $crate::model::Revertable::<$change_type>::revertable_apply(subvalue, subchange, &mut $crate::model::SubWatcher::new(&mut watcher_fn));
+									$change_name::$variant_name(revertchange)
+								},
+								_ => panic!("{}", $crate::model::EnumVariantError { expected: stringify!($variant_name) }),
 							}
 						},
 					)*
@@ -448,7 +743,7 @@ macro_rules! impl_revertable_enum{
 			$($variant_name: $change_type,)*
 		}
 	)
-}*/
+}
 
 
 /*#[derive(Debug, Clone)]
@@ -551,8 +846,9 @@ pub fn apply_pipe_to_mut_ref<T, FT>(func: FT, val_ref: &mut T)
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use change_value::ValueChange;
-	use change_string::StringChange;
+	use crate::change_value::{ValueChange, ValueSignal};
+	use crate::change_string::{StringChange, StringSignal};
+	use crate::{Changeable, Revertable};
 	
 	#[derive(Debug, Clone, PartialEq)]
 	struct TestModel {
@@ -560,18 +856,127 @@ mod tests {
 		last_name: String,
 		age: u64,
 	}
-
+	
 	impl_revertable_struct!{TestModelChange[TestModelSignal] for TestModel:
 		first_name: StringChange,
 		last_name: StringChange,
 		age: ValueChange<u64>,
 	}
 	
+	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+	#[derive(Debug, Clone, PartialEq)]
+	pub enum TestEnum {
+		Name(String),
+		Age(u64),
+	}
+	
+	impl_revertable_enum!{TestEnumChange[TestEnumSignal] for TestEnum:
+		Name: StringChange,
+		Age: ValueChange<u64>,
+	}
+	
+	#[test] fn enum_change() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut e = TestEnum::Name("Joe".into());
+		let revert1 = e.revertable_apply(TestEnumChange::Name(StringChange{index: 3, len: 0, new: "y".into()}), &mut spy);
+		assert_eq!(e, TestEnum::Name("Joey".into()));
+		let revert2 = e.revertable_apply(TestEnumChange::SetEnumValue(TestEnum::Age(41)), &mut spy);
+		assert_eq!(e, TestEnum::Age(41));
+		
+		let result = e.try_changeable_apply(TestEnumChange::Name(StringChange{index: 0, len: 0, new: "x".into()}), &mut spy);
+		assert_eq!(result, Err(ApplyError::new(ApplyErrorKind::EnumVariant(EnumVariantError { expected: "Name" }))));
+		assert_eq!(e, TestEnum::Age(41));
+		
+		e.changeable_apply(TestEnumChange::Age(ValueChange(42)), &mut spy);
+		e.revertable_apply(revert2, &mut spy);
+		e.revertable_apply(revert1, &mut spy);
+		assert_eq!(e, TestEnum::Name("Joe".into()));
+		
+		assert_eq!(spy.signals, vec![
+			TestEnumSignal::Name(StringSignal{index: 3, from_len: 0, to_len: 1}),
+			TestEnumSignal::SetEnumValue,
+			TestEnumSignal::Age(ValueSignal),
+			TestEnumSignal::SetEnumValue,
+			TestEnumSignal::Name(StringSignal{index: 3, from_len: 1, to_len: 0}),
+		]);
+	}
+	
+	#[test] #[should_panic(expected = "Change for enum variant Name applied to a different variant")]
+	fn enum_change_wrong_variant() {
+		let mut e = TestEnum::Age(41);
+		e.changeable_apply(TestEnumChange::Name(StringChange{index: 0, len: 0, new: "x".into()}), &mut NoWatcher);
+	}
+	
+	#[derive(Debug, Clone, PartialEq, Changeable, Revertable)]
+	struct DerivedModel {
+		/// The person's name.
+		#[change(StringChange)]
+		#[on_changed(DerivedModel::note_previous_name)]
+		name: String,
+		#[change(ValueChange<u64>)]
+		age: u64,
+		previous_names: Vec<String>,
+	}
+	
+	impl DerivedModel {
+		fn note_previous_name(&mut self) {
+			self.previous_names.push(self.name.clone());
+		}
+	}
+	
+	#[derive(Debug, Clone, PartialEq, Changeable, Revertable)]
+	#[change(PairChange[PairSignal])]
+	struct Pair<T: 'static + PartialEq + Send, U: Send>(#[change(ValueChange<T>)] T, U);
+	
+	#[test] fn derived_change() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut model = DerivedModel { name: "Joe".into(), age: 41, previous_names: vec![] };
+		let revert1 = model.revertable_apply(DerivedModelChange::name(StringChange{index: 3, len: 0, new: "y".into()}), &mut spy);
+		let revert2 = model.revertable_apply(DerivedModelChange::age(ValueChange(42)), &mut spy);
+		assert_eq!(model, DerivedModel { name: "Joey".into(), age: 42, previous_names: vec!["Joe".into()] });
+		model.revertable_apply(revert2, &mut spy);
+		model.revertable_apply(revert1, &mut spy);
+		assert_eq!(model, DerivedModel { name: "Joe".into(), age: 41, previous_names: vec!["Joe".into(), "Joey".into()] });
+		
+		assert_eq!(spy.signals, vec![
+			DerivedModelSignal::name(StringSignal{index: 3, from_len: 0, to_len: 1}),
+			DerivedModelSignal::age(ValueSignal),
+			DerivedModelSignal::age(ValueSignal),
+			DerivedModelSignal::name(StringSignal{index: 3, from_len: 1, to_len: 0}),
+		]);
+		assert_eq!(Changeable::<DerivedModelChange>::reset_view_signals(&model), vec![
+			DerivedModelSignal::name(StringSignal{index: 0, from_len: usize::MAX, to_len: 3}),
+			DerivedModelSignal::age(ValueSignal),
+		]);
+		
+		let mut change = DerivedModelChange::name(StringChange{index: 0, len: 0, new: "A".into()});
+		assert!(change.coalesce(DerivedModelChange::name(StringChange{index: 1, len: 0, new: "l".into()})).is_ok());
+		assert_eq!(change, DerivedModelChange::name(StringChange{index: 0, len: 0, new: "Al".into()}));
+		assert!(change.coalesce(DerivedModelChange::age(ValueChange(3))).is_err());
+		
+		let result = model.try_revertable_apply(DerivedModelChange::name(StringChange{index: 4, len: 0, new: "!".into()}), &mut spy);
+		assert_eq!(result.unwrap_err().path, vec!["name".to_string()]);
+		assert_eq!(model.name, "Joe");
+	}
+	
+	#[test] fn derived_generic_change() {
+		let mut spy = SpyWatcher::new();
+		
+		let mut pair = Pair(1.5f32, "unchanged");
+		let revert = pair.revertable_apply(PairChange::_0(ValueChange(2.5)), &mut spy);
+		assert_eq!(pair, Pair(2.5, "unchanged"));
+		pair.changeable_apply(revert, &mut spy);
+		assert_eq!(pair, Pair(1.5, "unchanged"));
+		assert_eq!(spy.signals, vec![PairSignal::_0(ValueSignal), PairSignal::_0(ValueSignal)]);
+	}
+	
 	#[test] fn change_constructor() {
 		{
 			let lcc = LeafChangeConstructor::<ValueChange<u32>>::new();
-			let change = lcc.create(Box::new(ValueChange::Set(123u32)));
-			assert_eq!(change, ValueChange::Set(123u32));
+			let change = lcc.create(Box::new(ValueChange(123u32)));
+			assert_eq!(change, ValueChange(123u32));
 		}
 		
 		{
@@ -581,13 +986,33 @@ mod tests {
 				|sub_constructor, any_change| {
 					TestModelChange::age(sub_constructor.create(any_change))
 				},
-				|sub_constructor, change| {
+				|_, _| {
+					true
+				},
+				|_, _| {
 					true
 				},
 				|_| "".into()
 			);
-			let change = scc.create(Box::new(ValueChange::Set(123u64)));
-			assert_eq!(change, TestModelChange::age(ValueChange::Set(123u64)));
+			let change = scc.create(Box::new(ValueChange(123u64)));
+			assert_eq!(change, TestModelChange::age(ValueChange(123u64)));
 		}
 	}
+	
+	#[cfg(feature = "serde")]
+	#[test] fn serde_round_trip() {
+		let change = TestModelChange::first_name(StringChange{index: 1, len: 2, new: "ab".into()});
+		let json = serde_json::to_string(&change).unwrap();
+		assert_eq!(serde_json::from_str::<TestModelChange>(&json).unwrap(), change);
+		
+		let mut model = TestModel {
+			first_name: "Joe".into(),
+			last_name: "Bloggs".into(),
+			age: 41,
+		};
+		let mut spy = SpyWatcher::new();
+		model.changeable_apply(change, &mut spy);
+		let json = serde_json::to_string(&spy.signals).unwrap();
+		assert_eq!(serde_json::from_str::<Vec<TestModelSignal>>(&json).unwrap(), spy.signals);
+	}
 }
@@ -1,18 +1,26 @@
 use crate::model::*;
-use crate::idalloc::IdAlloc;
+use crate::idalloc::{IdAlloc, AllocId};
+use crate::history::HistoryChange;
 
 use std;
 use std::mem;
+use std::ops::Range;
 use std::sync::{mpsc, Mutex, Condvar, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::any::Any;
+use std::time::Instant;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct Handle(usize);
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct JustSignal;
 
 /// The most basic of changes, which just swaps the value with another
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 pub struct JustSignalChange<ST>(pub ST);
 
@@ -56,26 +64,85 @@ impl_changeable_struct!{TaskChange<ST>[TaskSignal<ST>] for Task<ST>:
 #[derive(Debug)]
 pub enum ApplyHandleMessage {
 	/// Apply the given change (wrapped in Any) using the ChangeConstructor with the ID.
-	Apply(usize, Box<Any + Send>),
+	Apply(AllocId, Box<Any + Send>),
 	/// Apply the given changes (wrapped in Any) all at once using the ChangeConstructor with the ID.
-	ApplyAll(usize, Vec<Box<Any + Send>>),
+	ApplyAll(AllocId, Vec<Box<Any + Send>>),
+	/// Apply the given change (wrapped in Any) like Apply, then send back what happened to it.
+	ApplyWithAck(AllocId, Box<Any + Send>, mpsc::Sender<ApplyResult>),
 	/// Indicate that an apply handle was cloned for the ChangeConstructor with the given ID, so
 	/// increment its reference count.
-	Clone(usize),
+	Clone(AllocId),
 	/// Indicate that an apply handle was dropped for the ChangeConstructor with the given ID, so
 	/// decrement its reference count.
-	Drop(usize),
+	Drop(AllocId),
+}
+
+/// What happened to a change that was sent through an apply handle.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyResult {
+	/// The change was applied to the model.
+	Applied,
+	/// The change was dropped because the part of the model the apply handle refers to was
+	/// replaced or removed, or the manager was dropped.
+	Invalidated,
+	/// The change was rejected by the manager, either because it couldn't be applied or because
+	/// its batch failed validation.
+	Rejected(ApplyError),
+}
+
+/// Returned by ApplyHandle::invoke_with_ack to find out what happened to the change once the
+/// manager has processed it.
+pub struct ApplyAck {
+	result_recv: mpsc::Receiver<ApplyResult>,
+	result: Option<ApplyResult>,
+}
+
+impl ApplyAck {
+	fn new(result_recv: mpsc::Receiver<ApplyResult>) -> ApplyAck {
+		ApplyAck { result_recv, result: None }
+	}
+	
+	/// Blocks until the manager has processed the change.
+	pub fn wait(mut self) -> ApplyResult {
+		if let Some(result) = self.result.take() {
+			return result;
+		}
+		// The sender is only dropped without sending if the manager went away first
+		self.result_recv.recv().unwrap_or(ApplyResult::Invalidated)
+	}
+	
+	/// Returns the result if the manager has processed the change, without blocking.
+	pub fn try_result(&mut self) -> Option<ApplyResult> {
+		if self.result.is_none() {
+			self.result = match self.result_recv.try_recv() {
+				Ok(result) => Some(result),
+				Err(mpsc::TryRecvError::Disconnected) => Some(ApplyResult::Invalidated),
+				Err(mpsc::TryRecvError::Empty) => None,
+			};
+		}
+		self.result.clone()
+	}
 }
 
 pub struct ApplyHandleAny {
-	id: usize,
+	id: AllocId,
 	async_change_queue_send: mpsc::Sender<ApplyHandleMessage>,
 	async_change_notifier: AsyncChangeNotifier,
+	/// Cleared by the manager when the handle's ChangeConstructor becomes invalid.
+	valid: Arc<AtomicBool>,
 }
 
 impl ApplyHandleAny {
-	fn new(id: usize, async_change_queue_send: mpsc::Sender<ApplyHandleMessage>, async_change_notifier: AsyncChangeNotifier) -> ApplyHandleAny {
-		ApplyHandleAny { id, async_change_queue_send, async_change_notifier }
+	fn new(id: AllocId, async_change_queue_send: mpsc::Sender<ApplyHandleMessage>, async_change_notifier: AsyncChangeNotifier, valid: Arc<AtomicBool>) -> ApplyHandleAny {
+		ApplyHandleAny { id, async_change_queue_send, async_change_notifier, valid }
+	}
+	
+	/// Returns false once the part of the model that the handle applies changes to has been
+	/// replaced or removed, or the manager has been dropped, after which invoking it does nothing.
+	/// This is updated when the manager applies changes, so changes that are still queued up can
+	/// invalidate the handle after this returns true.
+	pub fn is_valid(&self) -> bool {
+		self.valid.load(Ordering::SeqCst)
 	}
 	
 	/// Returns false if the invocation fails (when the model has already been deleted).
@@ -92,6 +159,16 @@ impl ApplyHandleAny {
 		self.async_change_notifier.notify();
 		result.is_err()
 	}
+	
+	/// Like `invoke`, but returns an ApplyAck that reports whether the change was applied.
+	pub fn invoke_with_ack<C: 'static + Send>(&self, change: C) -> ApplyAck {
+		let (result_send, result_recv) = mpsc::channel();
+		// If the manager is gone, the sender is dropped along with the message, which the ack
+		// reports as invalidated
+		self.async_change_queue_send.send(ApplyHandleMessage::ApplyWithAck(self.id, Box::new(change) as Box<Any + Send>, result_send)).ok();
+		self.async_change_notifier.notify();
+		ApplyAck::new(result_recv)
+	}
 }
 
 impl Clone for ApplyHandleAny {
@@ -102,6 +179,7 @@ impl Clone for ApplyHandleAny {
 			id: self.id,
 			async_change_queue_send: self.async_change_queue_send.clone(),
 			async_change_notifier: self.async_change_notifier.clone(),
+			valid: self.valid.clone(),
 		}
 	}
 }
@@ -129,21 +207,38 @@ impl<C: 'static + Send> ApplyHandle<C> {
 	pub fn invoke(&self, change: C) {
 		self.apply_handle_any.invoke(change);
 	}
+	
+	/// Like `invoke`, but returns an ApplyAck that reports whether the change was applied.
+	pub fn invoke_with_ack(&self, change: C) -> ApplyAck {
+		self.apply_handle_any.invoke_with_ack(change)
+	}
+	
+	/// Returns false once invoking the handle does nothing. See ApplyHandleAny::is_valid.
+	pub fn is_valid(&self) -> bool {
+		self.apply_handle_any.is_valid()
+	}
 }
 
-/*struct TransactionScope<T: Changeable<C>, C: Change> {
-	apply_context: Option<&ApplyContext<T, C>>
-}*/
-
 /// An ApplyContext is something that can be used to apply changes to a model.
 pub trait ApplyContext<C: Change> {
 	/// Apply the given change to the model.
 	fn apply(&mut self, change: C);
 	
-	// TODO: This mechanism doesn't account for ignoring nested transactions!
-	/// Make a new transaction if this is part of an revertable model.
+	/// Make a new transaction if this is part of an revertable model. This is ignored while a
+	/// transaction scope is open.
 	fn new_transaction(&mut self, name: String);
 	
+	/// Opens a transaction scope if this is part of a revertable model. Scopes can be nested, and
+	/// only the outermost one starts a new transaction. It's usually easier to use
+	/// TransactionScope than to call this directly.
+	fn begin_transaction(&mut self, name: String);
+	
+	/// Closes the innermost transaction scope, keeping its changes.
+	fn commit_transaction(&mut self);
+	
+	/// Closes the innermost transaction scope, reverting the changes that were applied in it.
+	fn abort_transaction(&mut self);
+	
 	/// Create an ApplyHandleAny that should use the given constructor to build a change.
 	fn apply_handle_any(&mut self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny;
 	
@@ -212,6 +307,15 @@ pub trait Object<C: Change> {
 										_ => { true }
 									}
 								},
+								|sub_constructor, change| {
+									#[allow(unreachable_patterns)]
+									match *change {
+										$change_name::$field_name(ref sub_change) => {
+											sub_constructor.affects(sub_change)
+										}
+										_ => { false }
+									}
+								},
 								|sub_constructor| format!("{}/{}", stringify!($field_name), sub_constructor.debug_string())
 							))
 						),
@@ -248,52 +352,71 @@ pub trait Object<C: Change> {
 	}
 }*/
 
-pub trait Validator<C: Change> {
+/// Checks that a batch of changes applied by a Manager leaves the model in a valid state, which is
+/// useful for keeping invariants that span several parts of the model.
+pub trait Validator<T, C: Change> {
+	/// Called with the model after `changes` have been applied to it. Corrective changes can be
+	/// applied through `cxt`, and are applied once this returns as part of the same batch, without
+	/// being validated again. Returning an error rolls back the whole batch, including corrections.
 	#[must_use]
-	fn validate(&mut self, cxt: &mut ApplyContext<C>, changes: &Vec<C>) -> Result<(), String>;
+	fn validate(&mut self, model: &T, cxt: &mut ApplyContext<C>, changes: &Vec<C>) -> Result<(), String>;
 }
 
 pub struct NoValidator;
-impl<C: Change> Validator<C> for NoValidator {
-	fn validate(&mut self, _cxt: &mut ApplyContext<C>, _changes: &Vec<C>) -> Result<(), String> { Ok(()) }
+impl<T, C: Change> Validator<T, C> for NoValidator {
+	fn validate(&mut self, _model: &T, _cxt: &mut ApplyContext<C>, _changes: &Vec<C>) -> Result<(), String> { Ok(()) }
 }
 
-/*struct ValidatorApplier<'t, T: 't+Changeable<C>, C: 't+Change> {
-	manager_data: &'t mut ManagerData<T, C>,
+/// The ApplyContext given to a Validator, which collects corrective changes to apply once
+/// validation has finished.
+struct ValidatorApplier<'t, C: 'static + Change> {
+	changes: Vec<C>,
+	change_queue: &'t mut ChangeQueue<C>,
 }
 
-impl<'t, T: Changeable<C>, C: Change+std::fmt::Debug+std::clone::Clone> ApplyContext<T, C> for ValidatorApplier<'t, T, C> {
-	fn get(&self) -> &T {
-		&self.manager_data.model
-	}
-	
+impl<'t, C> ApplyContext<C> for ValidatorApplier<'t, C> where
+	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
+{
 	fn apply(&mut self, change: C) {
-		let signal_queue = &mut self.manager_data.signal_queue;
-		let mut watcher_fn = |signal| {
-			signal_queue.push(signal);
-		};
-		self.manager_data.model.changeable_apply(change.clone(), &mut SubWatcher::new(&mut watcher_fn));
-		//self.manager_data.change_queue.push(change);
+		self.changes.push(change);
 	}
 	
-	fn apply_handle_any(&self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny {
-		//ApplyHandleAny::new(0, self.)
+	fn apply_handle_any(&mut self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny {
+		self.change_queue.apply_handle_any(constructor)
 	}
 	
-	fn new_transaction(&mut self, name: String) {
+	fn new_transaction(&mut self, _name: String) {
 		// Ignore new transactions during validation
 	}
 	
-	/*fn get_handle() -> Handle {
-		Handle(0)
-	}*/
-}*/
+	fn begin_transaction(&mut self, _name: String) {}
+	
+	fn commit_transaction(&mut self) {}
+	
+	fn abort_transaction(&mut self) {}
+}
+
+struct AsyncChangeNotifierState {
+	/// The number of notifications since a wait last returned. Counting them under the mutex
+	/// means a notification that comes in before the wait starts isn't lost.
+	pending: usize,
+	shut_down: bool,
+}
 
 struct AsyncChangeNotifierInternal {
-	mutex: Mutex<()>,
+	mutex: Mutex<AsyncChangeNotifierState>,
 	condvar: Condvar,
 }
 
+/// Why AsyncChangeNotifier::wait_until returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+	Notified,
+	TimedOut,
+	ShutDown,
+}
+
+/// Wakes up the threads waiting for apply handles to send changes to a Manager.
 #[derive(Clone)]
 pub struct AsyncChangeNotifier {
 	internal: Arc<AsyncChangeNotifierInternal>,
@@ -303,22 +426,104 @@ impl AsyncChangeNotifier {
 	fn new() -> AsyncChangeNotifier {
 		AsyncChangeNotifier {
 			internal: Arc::new(AsyncChangeNotifierInternal {
-				mutex: Mutex::new(()),
+				mutex: Mutex::new(AsyncChangeNotifierState {
+					pending: 0,
+					shut_down: false,
+				}),
 				condvar: Condvar::new(),
 			})
 		}
 	}
-
+	
 	pub fn notify(&self) {
+		let mut state = self.internal.mutex.lock().unwrap();
+		state.pending = state.pending.saturating_add(1);
 		self.internal.condvar.notify_all();
 	}
 	
-	pub fn wait(&self) {
-		let _dummy_mutex_guard = self.internal.condvar.wait(self.internal.mutex.lock().unwrap()).unwrap();
+	/// Wakes up all the waiting threads, and makes every later wait return straight away.
+	pub fn shutdown(&self) {
+		self.internal.mutex.lock().unwrap().shut_down = true;
+		self.internal.condvar.notify_all();
+	}
+	
+	pub fn is_shut_down(&self) -> bool {
+		self.internal.mutex.lock().unwrap().shut_down
+	}
+	
+	/// Blocks until there has been a notification since a wait last returned, which it then
+	/// takes, so when several threads are waiting only one of them returns for it. Returns false
+	/// if the notifier has been shut down.
+	pub fn wait(&self) -> bool {
+		let mut state = self.internal.mutex.lock().unwrap();
+		while state.pending == 0 && !state.shut_down {
+			state = self.internal.condvar.wait(state).unwrap();
+		}
+		state.pending = 0;
+		!state.shut_down
+	}
+	
+	/// Like `wait`, but gives up at `deadline`.
+	pub fn wait_until(&self, deadline: Instant) -> WaitResult {
+		let mut state = self.internal.mutex.lock().unwrap();
+		loop {
+			if state.shut_down {
+				return WaitResult::ShutDown;
+			}
+			if state.pending > 0 {
+				state.pending = 0;
+				return WaitResult::Notified;
+			}
+			
+			let now = Instant::now();
+			if now >= deadline {
+				return WaitResult::TimedOut;
+			}
+			state = self.internal.condvar.wait_timeout(state, deadline - now).unwrap().0;
+		}
 	}
 }
 
-type ChangeConstructorSet<C> = IdAlloc<(Option<Box<ChangeConstructor<C>>>, usize)>;
+type ChangeConstructorSet<C> = IdAlloc<(Option<Box<ChangeConstructor<C>>>, usize, Arc<AtomicBool>)>;
+
+/// A change that a Manager didn't apply because the model couldn't accept it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedChange<C> {
+	pub change: C,
+	pub error: ApplyError,
+}
+
+/// Called with each change a Manager rejects, given to Manager::set_rejection_listener.
+pub type RejectionListener<C> = Box<FnMut(RejectedChange<C>)>;
+
+/// A listener for the signals a Manager sends, given to Manager::subscribe.
+pub type SignalListener<C> = Box<FnMut(&<C as Change>::SignalType)>;
+
+/// A listener registered with Manager::subscribe, and the path it is limited to.
+struct SubscriptionEntry<C: Change> {
+	listener: SignalListener<C>,
+	path: Option<Box<ChangeConstructor<C>>>,
+}
+
+/// Keeps a listener subscribed to a Manager's signals. The listener is unsubscribed when this is
+/// dropped, and is itself dropped the next time the manager applies a batch of changes.
+pub struct Subscription {
+	id: AllocId,
+	unsubscribe_send: mpsc::Sender<AllocId>,
+}
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		// The manager might already be gone, in which case there is nothing to unsubscribe from
+		let _ = self.unsubscribe_send.send(self.id);
+	}
+}
+
+/// Called with each batch of changes a Manager applies, along with the signals the batch sent.
+pub(crate) type ChangeObserver<C> = Box<FnMut(&[&C], &[<C as Change>::SignalType])>;
+
+/// Applies a change to a model, returning the change that reverts it.
+type RevertableApplyFn<T, C> = fn(&mut T, C, &mut Watcher<<C as Change>::SignalType>) -> Result<C, ApplyError>;
 
 /// Internal data for Manager.
 struct ManagerData<T: Changeable<C>, C: Change> {
@@ -326,6 +531,17 @@ struct ManagerData<T: Changeable<C>, C: Change> {
 	model: T,
 	/// Signals yet to be sent to the view.
 	signal_queue: Vec<C::SignalType>,
+	/// Called with the changes that failed validation.
+	rejection_listener: Option<RejectionListener<C>>,
+	/// Used instead of Changeable::try_changeable_apply when the model is Revertable, so that
+	/// batches that fail validation can be rolled back.
+	revertable_apply_fn: Option<RevertableApplyFn<T, C>>,
+	/// Listeners that are sent the signals from each batch of changes once it has been applied.
+	subscriptions: IdAlloc<SubscriptionEntry<C>>,
+	/// Receives the IDs of dropped Subscriptions.
+	unsubscribe_recv: mpsc::Receiver<AllocId>,
+	unsubscribe_send: mpsc::Sender<AllocId>,
+	change_observer: Option<ChangeObserver<C>>,
 	//handles: HashMap<Handle, C::SignalType>,
 }
 
@@ -333,113 +549,286 @@ impl<T, C> ManagerData<T, C> where
 	T: Changeable<C>,
 	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
 {
-	fn apply_change(&mut self, change: C, change_constructors: &mut ChangeConstructorSet<C>) {
-		let signal_queue = &mut self.signal_queue;
+	fn new(model: T, revertable_apply_fn: Option<RevertableApplyFn<T, C>>) -> ManagerData<T, C> {
+		let (unsubscribe_send, unsubscribe_recv) = mpsc::channel();
+		
+		ManagerData {
+			model,
+			signal_queue: vec![],
+			rejection_listener: None,
+			revertable_apply_fn,
+			subscriptions: IdAlloc::new(),
+			unsubscribe_recv,
+			unsubscribe_send,
+			change_observer: None,
+			//handles: HashMap::new(),
+		}
+	}
+	
+	/// Applies the change, adding its signals to `signals`. If the model is Revertable, the change
+	/// that reverts it is returned.
+	fn apply_change(&mut self, change: C, signals: &mut Vec<C::SignalType>) -> Result<Option<C>, ApplyError> {
 		let mut watcher_fn = |signal| {
-			signal_queue.push(signal);
+			signals.push(signal);
 		};
 		
-		self.model.changeable_apply(change.clone(), &mut SubWatcher::new(&mut watcher_fn));
-		
-		change_constructors.apply_to_all_mut(&mut |_, &mut (ref mut opt_change_constructor, _)| {
-			let still_valid = if let Some(ref mut change_constructor) = *opt_change_constructor {
-				change_constructor.update(&change)
-			} else {
-				true
+		if let Some(revertable_apply_fn) = self.revertable_apply_fn {
+			revertable_apply_fn(&mut self.model, change, &mut SubWatcher::new(&mut watcher_fn)).map(Some)
+		} else {
+			self.model.try_changeable_apply(change, &mut SubWatcher::new(&mut watcher_fn)).map(|()| None)
+		}
+	}
+	
+	/// Runs the validator over the applied changes, then applies the corrections it made. Returns
+	/// the corrections that were applied, adding the range of `signals` each one sent to
+	/// `signal_ranges`.
+	fn validate_changes<V: Validator<T, C>>(
+		&mut self,
+		changes: &Vec<C>,
+		validator: &mut V,
+		change_queue: &mut ChangeQueue<C>,
+		signals: &mut Vec<C::SignalType>,
+		signal_ranges: &mut Vec<Range<usize>>,
+		reverts: &mut Vec<C>,
+	) -> Result<Vec<C>, String> {
+		let corrections = {
+			let mut applier = ValidatorApplier {
+				changes: vec![],
+				change_queue,
 			};
-			
-			if !still_valid {
-				*opt_change_constructor = None;
+			validator.validate(&self.model, &mut applier, changes)?;
+			applier.changes
+		};
+		
+		for correction in &corrections {
+			let signals_start = signals.len();
+			match self.apply_change(correction.clone(), signals) {
+				Ok(revert) => {
+					reverts.extend(revert);
+					signal_ranges.push(signals_start..signals.len());
+				}
+				Err(error) => return Err(format!("Invalid corrective change: {}", error)),
 			}
-		});
+		}
+		
+		Ok(corrections)
 	}
 	
-	fn process_apply_handle_message(&mut self, message: ApplyHandleMessage, change_constructors: &mut ChangeConstructorSet<C>) {
-		match message {
-			ApplyHandleMessage::Apply(id, any_change) => {
-				let mut opt_change = None;
-				
-				if let (Some(ref change_constructor), _) = *change_constructors.get(id) {
-					opt_change = Some(change_constructor.create(any_change));
-				} else {
-					println!("Invalidated apply handle was called");
+	/// Applies all the queued changes as one batch and validates them. If validation fails, the
+	/// batch is rolled back using the revert changes, and its changes are rejected.
+	fn consume_all<V: Validator<T, C>>(&mut self, change_queue: &mut ChangeQueue<C>, validator: &mut V) {
+		let changes: Vec<C> = change_queue.changes.drain(..).collect();
+		let acks = mem::take(&mut change_queue.acks);
+		// What happened to each of the changes, for the ones that were invoked with an ack
+		let mut results = vec![];
+		let mut signals = vec![];
+		let mut signal_ranges = vec![];
+		let mut reverts = vec![];
+		let mut applied = vec![];
+		
+		for change in changes {
+			// A change that can't be applied, such as a stale one from an apply handle, is reported
+			// instead of taking down the whole manager.
+			let signals_start = signals.len();
+			match self.apply_change(change.clone(), &mut signals) {
+				Ok(revert) => {
+					reverts.extend(revert);
+					applied.push(change);
+					signal_ranges.push(signals_start..signals.len());
+					results.push(ApplyResult::Applied);
 				}
-				
-				if let Some(change) = opt_change {
-					//println!("Applying {:?}: {:?}", id, change);
-					self.apply_change(change, change_constructors);
+				Err(error) => {
+					results.push(ApplyResult::Rejected(error.clone()));
+					self.reject(change, error);
 				}
 			}
-			ApplyHandleMessage::ApplyAll(id, any_changes) => {
-				any_changes.into_iter().for_each(|any_change| {
-					let mut opt_change = None;
-					
-					if let (Some(ref change_constructor), _) = *change_constructors.get(id) {
-						opt_change = Some(change_constructor.create(any_change));
-					} else {
-						println!("Invalidated apply handle was called");
-					}
-					
-					if let Some(change) = opt_change {
-						//println!("Applying {:?}: {:?}", id, change);
-						self.apply_change(change, change_constructors);
+		}
+		
+		if applied.is_empty() {
+			send_acks(acks, &results);
+			return;
+		}
+		
+		match self.validate_changes(&applied, validator, change_queue, &mut signals, &mut signal_ranges, &mut reverts) {
+			Ok(corrections) => {
+				let batch: Vec<&C> = applied.iter().chain(corrections.iter()).collect();
+				if let Some(ref mut change_observer) = self.change_observer {
+					change_observer(&batch, &signals);
+				}
+				self.notify_subscribers(&batch, &signal_ranges, &signals);
+				self.signal_queue.append(&mut signals);
+				
+				for change in applied.iter().chain(corrections.iter()) {
+					for (_, &mut (ref mut opt_change_constructor, _, ref valid)) in &mut change_queue.change_constructors {
+						let still_valid = if let Some(ref mut change_constructor) = *opt_change_constructor {
+							change_constructor.update(change)
+						} else {
+							true
+						};
+						
+						if !still_valid {
+							*opt_change_constructor = None;
+							valid.store(false, Ordering::SeqCst);
+						}
 					}
-				});
-			}
-			ApplyHandleMessage::Clone(id) => {
-				println!("Clone {}", id);
-				let (_, ref mut ref_count) = *change_constructors.get_mut(id);
-				*ref_count += 1;
+				}
 			}
-			ApplyHandleMessage::Drop(id) => {
-				println!("Drop {:?}", id);
-				let mut should_dealloc = false;
-				{
-					let (_, ref mut ref_count) = *change_constructors.get_mut(id);
-					*ref_count -= 1;
-					
-					if *ref_count == 0 {
-						should_dealloc = true;
-					}
+			Err(message) => {
+				// The model ends up as it was before the batch, so the view doesn't need to hear
+				// about any of it.
+				let mut watcher_fn = |_| {};
+				for revert in reverts.into_iter().rev() {
+					self.model.changeable_apply(revert, &mut SubWatcher::new(&mut watcher_fn));
 				}
 				
-				if should_dealloc {
-					println!("Dealloc {:?}", id);
-					change_constructors.deallocate(id);
+				let error = ApplyError::new(ApplyErrorKind::Validation(message));
+				for result in &mut results {
+					if *result == ApplyResult::Applied {
+						*result = ApplyResult::Rejected(error.clone());
+					}
+				}
+				for change in applied {
+					self.reject(change, error.clone());
 				}
 			}
 		}
+		
+		send_acks(acks, &results);
 	}
 	
-	fn consume_all(&mut self, changes: &mut Vec<C>, change_constructors: &mut ChangeConstructorSet<C>) {
-		for change in changes.drain(..) {
-			self.apply_change(change, change_constructors);
+	/// Reports a change that wasn't applied to the rejection listener, if there is one.
+	fn reject(&mut self, change: C, error: ApplyError) {
+		if let Some(ref mut rejection_listener) = self.rejection_listener {
+			rejection_listener(RejectedChange { change, error });
 		}
 	}
 	
+	/// Sends the signals from an applied batch of changes to the subscribers, where `signal_ranges`
+	/// holds the range of `signals` that each change sent. A subscriber with a path is only sent
+	/// the signals from changes that affect it, and is removed once the path stops existing.
+	fn notify_subscribers(&mut self, changes: &[&C], signal_ranges: &[Range<usize>], signals: &[C::SignalType]) {
+		while let Ok(id) = self.unsubscribe_recv.try_recv() {
+			self.subscriptions.deallocate(id);
+		}
+		
+		let mut ended = vec![];
+		for (id, subscription) in &mut self.subscriptions {
+			let SubscriptionEntry { ref mut listener, ref mut path } = *subscription;
+			for (change, range) in changes.iter().zip(signal_ranges) {
+				let still_valid = if let Some(ref mut path) = *path {
+					if path.affects(change) {
+						for signal in &signals[range.clone()] {
+							listener(signal);
+						}
+					}
+					path.update(change)
+				} else {
+					for signal in &signals[range.clone()] {
+						listener(signal);
+					}
+					true
+				};
+				
+				if !still_valid {
+					ended.push(id);
+					break;
+				}
+			}
+		}
+		
+		for id in ended {
+			self.subscriptions.deallocate(id);
+		}
+	}
+}
+
+/// Sends each ack the result for the change at its index in the batch.
+fn send_acks(acks: Vec<(usize, mpsc::Sender<ApplyResult>)>, results: &[ApplyResult]) {
+	for (index, ack) in acks {
+		// The ApplyAck may have been dropped by whoever invoked the handle
+		ack.send(results[index].clone()).ok();
+	}
 }
 
 struct ChangeQueue<C: Change> {
 	changes: Vec<C>,
+	/// Whether consecutive queued changes should be merged with Change::coalesce.
+	coalesce_changes: bool,
 	/// Asynchronous change queue.
 	async_change_queue_recv: mpsc::Receiver<ApplyHandleMessage>,
 	async_change_queue_send: mpsc::Sender<ApplyHandleMessage>,
 	async_change_notifier: AsyncChangeNotifier,
 	/// ID allocator for ChangeConstructors. If the option is None, then the constructor became
-	/// invalid and was removed. The usize is the reference count.
+	/// invalid and was removed. The usize is the reference count, and the flag is shared with the
+	/// apply handles so they can tell whether they are still valid.
 	change_constructors: ChangeConstructorSet<C>,
+	/// The index in `changes` of each change that was invoked with an ack, and where to send what
+	/// happened to it.
+	acks: Vec<(usize, mpsc::Sender<ApplyResult>)>,
 }
 
 impl<C: Change> ChangeQueue<C> {
 	fn new() -> ChangeQueue<C> {
 		let (async_change_queue_send, async_change_queue_recv) = mpsc::channel();
-
+		
 		ChangeQueue {
 			changes: vec![],
+			coalesce_changes: false,
 			async_change_queue_send,
 			async_change_queue_recv,
 			async_change_notifier: AsyncChangeNotifier::new(),
 			change_constructors: ChangeConstructorSet::new(),
+			acks: vec![],
+		}
+	}
+	
+	/// Queues up the changes for the message, or updates the apply handle reference counts.
+	fn process_apply_handle_message(&mut self, message: ApplyHandleMessage) {
+		match message {
+			ApplyHandleMessage::Apply(id, any_change) => {
+				if let Some(&(Some(ref change_constructor), _, _)) = self.change_constructors.try_get(id) {
+					self.changes.push(change_constructor.create(any_change));
+				} else {
+					println!("Invalidated apply handle was called");
+				}
+			}
+			ApplyHandleMessage::ApplyAll(id, any_changes) => {
+				if let Some(&(Some(ref change_constructor), _, _)) = self.change_constructors.try_get(id) {
+					self.changes.extend(any_changes.into_iter().map(|any_change| change_constructor.create(any_change)));
+				} else {
+					println!("Invalidated apply handle was called");
+				}
+			}
+			ApplyHandleMessage::ApplyWithAck(id, any_change, ack) => {
+				if let Some(&(Some(ref change_constructor), _, _)) = self.change_constructors.try_get(id) {
+					self.acks.push((self.changes.len(), ack));
+					self.changes.push(change_constructor.create(any_change));
+				} else {
+					ack.send(ApplyResult::Invalidated).ok();
+				}
+			}
+			ApplyHandleMessage::Clone(id) => {
+				println!("Clone {}", id);
+				if let Some(&mut (_, ref mut ref_count, _)) = self.change_constructors.try_get_mut(id) {
+					*ref_count += 1;
+				}
+			}
+			ApplyHandleMessage::Drop(id) => {
+				println!("Drop {:?}", id);
+				let mut should_dealloc = false;
+				if let Some(&mut (_, ref mut ref_count, _)) = self.change_constructors.try_get_mut(id) {
+					*ref_count -= 1;
+					
+					if *ref_count == 0 {
+						should_dealloc = true;
+					}
+				}
+				
+				if should_dealloc {
+					println!("Dealloc {:?}", id);
+					self.change_constructors.deallocate(id);
+				}
+			}
 		}
 	}
 }
@@ -447,7 +836,18 @@ impl<C: Change> ChangeQueue<C> {
 impl<C> ApplyContext<C> for ChangeQueue<C> where
 	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
 {
-	fn apply(&mut self, change: C) {
+	fn apply(&mut self, mut change: C) {
+		// A change that was invoked with an ack is kept as it is, so the ack reports what happened
+		// to that change alone
+		let last_acked = self.acks.last().map(|&(index, _)| index + 1) == Some(self.changes.len());
+		if self.coalesce_changes && !last_acked {
+			if let Some(last) = self.changes.last_mut() {
+				match last.coalesce(change) {
+					Ok(()) => return,
+					Err(next) => change = next,
+				}
+			}
+		}
 		self.changes.push(change);
 	}
 	
@@ -455,9 +855,16 @@ impl<C> ApplyContext<C> for ChangeQueue<C> where
 		// There is no undoing in managers, so ignore new transaction requests
 	}
 	
+	fn begin_transaction(&mut self, _name: String) {}
+	
+	fn commit_transaction(&mut self) {}
+	
+	fn abort_transaction(&mut self) {}
+	
 	fn apply_handle_any(&mut self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny {
 		let debug_string = constructor.debug_string();
-		let (_, id) = self.change_constructors.allocate((Some(constructor), 1));
+		let valid = Arc::new(AtomicBool::new(true));
+		let (_, id) = self.change_constructors.allocate((Some(constructor), 1, valid.clone()));
 		println!("Creating apply handle({}): /{}", id, debug_string);
 		let new_send = self.async_change_queue_send.clone();
 		let async_change_notifier = self.async_change_notifier.clone();
@@ -465,7 +872,7 @@ impl<C> ApplyContext<C> for ChangeQueue<C> where
 			new_send.send();
 			async_change_notifier.notify();
 		})*/
-		ApplyHandleAny::new(id, new_send, async_change_notifier)
+		ApplyHandleAny::new(id, new_send, async_change_notifier, valid)
 	}
 	
 	/*fn apply_handle(&self) -> Box<Fn(C) + Send> {
@@ -483,46 +890,78 @@ impl<C> ApplyContext<C> for ChangeQueue<C> where
 	}*/
 }
 
+impl<C: Change> Drop for ChangeQueue<C> {
+	fn drop(&mut self) {
+		// Apply handles that outlive the manager can't do anything
+		for (_, (_, _, valid)) in &self.change_constructors {
+			valid.store(false, Ordering::SeqCst);
+		}
+	}
+}
+
 // To modify the model or listen to modifications of the model, you need access
 // to the model's manager.
-pub struct Manager<T: Changeable<C>, C: Change, V: Validator<C>> {
+pub struct Manager<T: Changeable<C>, C: Change, V: Validator<T, C>> {
 	// This data is separated so that it can be passed to the validator
 	data: ManagerData<T, C>,
 	change_queue: ChangeQueue<C>,
-	// While the validator is in use, the other stuff still needs to be
-	// appliable. Solution: make a ValidatedManager and a non-validated Manager
-	#[allow(dead_code)]
 	validator: V,
 }
 
-impl<T, C, V> Manager<T, C, V> where
+impl<T, C> Manager<T, C, NoValidator> where
 	T: Changeable<C> + Object<C>,
 	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
-	V: Validator<C>,
 {
-	pub fn new(model: T, validator: V) -> Manager<T, C, V> {
+	/// Creates a manager that doesn't validate changes, which works with any Changeable model.
+	pub fn new(model: T, validator: NoValidator) -> Manager<T, C, NoValidator> {
 		Manager {
-			data: ManagerData {
-				model,
-				signal_queue: vec![],
-				//handles: HashMap::new(),
-			},
+			data: ManagerData::new(model, None),
 			change_queue: ChangeQueue::new(),
 			validator,
 		}
 	}
-	
+}
+
+impl<T, C, V> Manager<T, C, V> where
+	T: Revertable<C> + Object<C>,
+	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
+	V: Validator<T, C>,
+{
+	/// Creates a manager that runs `validator` after each batch of changes is applied. Batches that
+	/// fail validation are rolled back using revert changes, so the model must be Revertable.
+	pub fn new_validated(model: T, validator: V) -> Manager<T, C, V> {
+		Manager {
+			data: ManagerData::new(model, Some(Revertable::try_revertable_apply)),
+			change_queue: ChangeQueue::new(),
+			validator,
+		}
+	}
+}
+
+impl<T, C, V> Manager<T, C, V> where
+	T: Changeable<C> + Object<C>,
+	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
+	V: Validator<T, C>,
+{
 	pub fn get(&self) -> &T {
 		&self.data.model
 	}
 	
+	/// When enabled, changes that are queued up before being applied (such as the ones made while
+	/// resolving signals) are merged together where possible, so fewer changes are applied and
+	/// fewer signals are sent. Disabled by default.
+	pub fn set_coalesce_changes(&mut self, coalesce_changes: bool) {
+		self.change_queue.coalesce_changes = coalesce_changes;
+	}
+	
 	pub fn apply(&mut self, change: C) {
 		self.change_queue.apply(change);
 		self.apply_all_queued();
 	}
 	
+	/// Applies all queued changes as one batch.
 	pub fn apply_all_queued(&mut self) {
-		self.data.consume_all(&mut self.change_queue.changes, &mut self.change_queue.change_constructors);
+		self.data.consume_all(&mut self.change_queue, &mut self.validator);
 	}
 	
 	/// This destroys the manager forever, returning the internal model as value
@@ -549,17 +988,45 @@ impl<T, C, V> Manager<T, C, V> where
 		}
 	}*/
 	
+	/// Applies the changes sent by apply handles as they come in, until the async change notifier
+	/// is shut down. The changes sent before it was shut down are still applied.
 	pub fn process_async_changes(&mut self) {
-		for message in self.change_queue.async_change_queue_recv.iter() {
-			self.data.process_apply_handle_message(message, &mut self.change_queue.change_constructors);
-			self.data.consume_all(&mut self.change_queue.changes, &mut self.change_queue.change_constructors);
+		let notifier = self.get_async_change_notifier();
+		while notifier.wait() {
+			self.try_process_async_changes();
+		}
+		self.try_process_async_changes();
+	}
+	
+	/// Like `process_async_changes`, but returns at `deadline` too. Returns false if it returned
+	/// because the async change notifier was shut down.
+	pub fn process_async_changes_until(&mut self, deadline: Instant) -> bool {
+		let notifier = self.get_async_change_notifier();
+		loop {
+			// Checked here too so that a steady stream of changes can't keep this going forever
+			if Instant::now() >= deadline {
+				self.try_process_async_changes();
+				return true;
+			}
+			
+			match notifier.wait_until(deadline) {
+				WaitResult::Notified => self.try_process_async_changes(),
+				WaitResult::TimedOut => {
+					self.try_process_async_changes();
+					return true;
+				}
+				WaitResult::ShutDown => {
+					self.try_process_async_changes();
+					return false;
+				}
+			}
 		}
 	}
 	
 	pub fn try_process_async_changes(&mut self) {
-		for message in self.change_queue.async_change_queue_recv.try_iter() {
-			self.data.process_apply_handle_message(message, &mut self.change_queue.change_constructors);
-			self.data.consume_all(&mut self.change_queue.changes, &mut self.change_queue.change_constructors);
+		while let Ok(message) = self.change_queue.async_change_queue_recv.try_recv() {
+			self.change_queue.process_apply_handle_message(message);
+			self.apply_all_queued();
 		}
 	}
 	
@@ -567,6 +1034,12 @@ impl<T, C, V> Manager<T, C, V> where
 		self.change_queue.async_change_notifier.clone()
 	}
 	
+	/// Shuts down the async change notifier, so that threads processing or waiting for async
+	/// changes stop. Use the notifier's `shutdown` to do this from another thread.
+	pub fn shutdown(&self) {
+		self.change_queue.async_change_notifier.shutdown();
+	}
+	
 	/// Updates the view with all queued signals.
 	pub fn resolve_signals(&mut self) {
 		loop {
@@ -580,7 +1053,35 @@ impl<T, C, V> Manager<T, C, V> where
 				self.data.model.update(&mut self.change_queue, &signal);
 				self.apply_all_queued();
 			}
-			
+		
+		}
+	}
+	
+	/// Registers a listener that is sent every signal, after the batch of changes that sent it has
+	/// been applied and validated. It is sent the signals for as long as the returned Subscription
+	/// is kept.
+	pub fn subscribe(&mut self, listener: SignalListener<C>) -> Subscription {
+		self.add_subscription(listener, None)
+	}
+	
+	/// Like `subscribe`, but the listener is only sent the signals from changes that affect the
+	/// part of the model that `path` builds changes for. The listener stops being sent signals
+	/// once that part of the model is replaced or removed.
+	pub fn subscribe_path(&mut self, path: Box<ChangeConstructor<C>>, listener: SignalListener<C>) -> Subscription {
+		self.add_subscription(listener, Some(path))
+	}
+	
+	/// Sets the function that is called with every batch of changes once it has been applied and
+	/// validated, including the corrections made by the validator.
+	pub(crate) fn set_change_observer(&mut self, change_observer: Option<ChangeObserver<C>>) {
+		self.data.change_observer = change_observer;
+	}
+	
+	fn add_subscription(&mut self, listener: SignalListener<C>, path: Option<Box<ChangeConstructor<C>>>) -> Subscription {
+		let (_, id) = self.data.subscriptions.allocate(SubscriptionEntry { listener, path });
+		Subscription {
+			id,
+			unsubscribe_send: self.data.unsubscribe_send.clone(),
 		}
 	}
 	
@@ -590,15 +1091,12 @@ impl<T, C, V> Manager<T, C, V> where
 		mem::replace(&mut self.data.signal_queue, vec![])
 	}
 	
-	//fn validate_changes(&mut self, _changes: &Vec<C>) {
-		// TODO: Make the validator function run after a transaction has
-		// completed, and allow the validator to fail, causing it to undo all
-		// changes in that transaction and discard them
-		/*let mut applier = ValidatorApplier {
-			manager_data: &mut self.data,
-		};
-		self.validator.validate(&mut applier, changes);*/
-	//}
+	/// Sets the function that is called with each change that is rejected because it couldn't be
+	/// applied to the model, along with why. Rejected changes have no effect on the model, and
+	/// without a listener nothing else hears about them.
+	pub fn set_rejection_listener(&mut self, rejection_listener: Option<RejectionListener<C>>) {
+		self.data.rejection_listener = rejection_listener;
+	}
 }
 
 /*struct Message<'t, 'c, T: Revertable<C>+'t, C: 'c> {
@@ -608,7 +1106,7 @@ impl<T, C, V> Manager<T, C, V> where
 
 trait View<T: Revertable<C>, C> {
 	fn dispatch(message: Message<T, C>) {
-		
+	
 	}
 }*/
 
@@ -653,15 +1151,28 @@ impl<'p, PC: 'static + Change, C: 'static + Change> ApplyContext<C> for SubApply
 	/*fn apply_handle(&self) -> Box<Fn(C) + Send> {
 		let parent_handle = self.parent_context.apply_handle();
 		let wrap_fn = (self.box_wrap_fn)();
-	
+		
 		Box::new(move |change| {
 			parent_handle(wrap_fn(change));
 		})
 	}*/
 	
-	fn new_transaction(&mut self, _name: String) {
-		// Because this SubApplyContext consumes changes, it can't undo,
-		// therefore new transactions are ignored
+	// This SubApplyContext can't undo by itself, so transactions are left to the parent context,
+	// which may be part of a revertable model.
+	fn new_transaction(&mut self, name: String) {
+		self.parent_context.new_transaction(name);
+	}
+	
+	fn begin_transaction(&mut self, name: String) {
+		self.parent_context.begin_transaction(name);
+	}
+	
+	fn commit_transaction(&mut self) {
+		self.parent_context.commit_transaction();
+	}
+	
+	fn abort_transaction(&mut self) {
+		self.parent_context.abort_transaction();
 	}
 }
 
@@ -695,41 +1206,47 @@ impl<'p, PC: 'static + Change, C: 'static + Change> ApplyContext<C> for SubApply
 						true
 					}
 				},
+				|sub_constructor, change| {
+					if let sub_apply!(@impl (ref sub_change) $change_type::$field_name $($sub_change_type::$sub_field_name)*) = *change {
+						sub_constructor.affects(sub_change)
+					} else {
+						false
+					}
+				},
 				|sub_constructor| format!("{}/{}", stringify!($field_name).to_string() $(+ "/" + stringify!($sub_field_name))*, sub_constructor.debug_string())
 			))
 		)
 	};
 }
 
-pub struct RevertableSubApplyContext<'p, 'c, PC: 'static + Change, C: 'static + Change> {
+/// An ApplyContext for the model inside a History. Changes are pushed onto the history, and
+/// transactions are mapped onto the history's transactions.
+pub struct RevertableSubApplyContext<'p, 'c, PC: 'static + Change, T: 'static + Revertable<C> + Send, C: 'static + Change> {
 	parent_context: &'p mut ApplyContext<PC>,
-	wrap_fn: &'c Fn(C) -> PC,
+	wrap_fn: &'c Fn(HistoryChange<T, C>) -> PC,
 	//box_wrap_fn: &'p Fn() -> Box<Fn(C) -> PC>,
 	wrap_constructor_fn: &'p Fn(Box<ChangeConstructor<C>>) -> Box<ChangeConstructor<PC>>,
-	new_transaction_fn: &'c mut Fn(String),
 }
 
-impl<'p, 'c, PC: 'static + Change, C: 'static + Change> RevertableSubApplyContext<'p, 'c, PC, C> {
+impl<'p, 'c, PC: 'static + Change, T: 'static + Revertable<C> + Send, C: 'static + Change> RevertableSubApplyContext<'p, 'c, PC, T, C> {
 	pub fn new(
 			parent_context: &'p mut ApplyContext<PC>,
-			wrap_fn: &'c Fn(C) -> PC,
+			wrap_fn: &'c Fn(HistoryChange<T, C>) -> PC,
 			//box_wrap_fn: &'p Fn() -> Box<Fn(C) -> PC>,
 			wrap_constructor_fn: &'p Fn(Box<ChangeConstructor<C>>) -> Box<ChangeConstructor<PC>>,
-			new_transaction_fn: &'c mut Fn(String)
-	) -> RevertableSubApplyContext<'p, 'c, PC, C> {
+	) -> RevertableSubApplyContext<'p, 'c, PC, T, C> {
 		RevertableSubApplyContext {
 			parent_context,
 			wrap_fn,
 			//box_wrap_fn,
 			wrap_constructor_fn,
-			new_transaction_fn,
 		}
 	}
 }
 
-impl<'p, 'c, PC: 'static + Change, C: 'static + Change> ApplyContext<C> for RevertableSubApplyContext<'p, 'c, PC, C> {
+impl<'p, 'c, PC: 'static + Change, T: 'static + Revertable<C> + Send, C: 'static + Change> ApplyContext<C> for RevertableSubApplyContext<'p, 'c, PC, T, C> {
 	fn apply(&mut self, change: C) {
-		self.parent_context.apply((self.wrap_fn)(change));
+		self.parent_context.apply((self.wrap_fn)(HistoryChange::Push(change)));
 	}
 	
 	fn apply_handle_any(&mut self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny {
@@ -739,17 +1256,411 @@ impl<'p, 'c, PC: 'static + Change, C: 'static + Change> ApplyContext<C> for Reve
 	
 	/*fn apply_handle(&self) -> Box<Fn(C) + Send> {
 		//let parent_handle = self.parent_context.apply_handle();
-	
+		
 		Box::new(move |change| {
 			//parent_handle((self.apply_handle_fn)(change));
 		})
 	}*/
 	
 	fn new_transaction(&mut self, name: String) {
-		(self.new_transaction_fn)(name);
+		self.parent_context.apply((self.wrap_fn)(HistoryChange::NewTransaction(name)));
+	}
+	
+	fn begin_transaction(&mut self, name: String) {
+		self.parent_context.apply((self.wrap_fn)(HistoryChange::BeginTransaction(name)));
+	}
+	
+	fn commit_transaction(&mut self) {
+		self.parent_context.apply((self.wrap_fn)(HistoryChange::CommitTransaction));
+	}
+	
+	fn abort_transaction(&mut self) {
+		self.parent_context.apply((self.wrap_fn)(HistoryChange::AbortTransaction));
+	}
+}
+
+/// Groups the changes applied through it into a transaction scope, which is committed when it is
+/// dropped unless it was aborted. Scopes can be nested by making a new scope from this one.
+pub struct TransactionScope<'p, C: 'static + Change> {
+	context: &'p mut ApplyContext<C>,
+	open: bool,
+}
+
+impl<'p, C: 'static + Change> TransactionScope<'p, C> {
+	pub fn new(context: &'p mut ApplyContext<C>, name: String) -> TransactionScope<'p, C> {
+		context.begin_transaction(name);
+		TransactionScope {
+			context,
+			open: true,
+		}
+	}
+	
+	/// Closes the scope, keeping its changes. This is the same as dropping it.
+	pub fn commit(mut self) {
+		self.open = false;
+		self.context.commit_transaction();
+	}
+	
+	/// Closes the scope, reverting the changes that were applied through it.
+	pub fn abort(mut self) {
+		self.open = false;
+		self.context.abort_transaction();
+	}
+}
+
+impl<'p, C: 'static + Change> Drop for TransactionScope<'p, C> {
+	fn drop(&mut self) {
+		if self.open {
+			self.context.commit_transaction();
+		}
+	}
+}
+
+impl<'p, C: 'static + Change> ApplyContext<C> for TransactionScope<'p, C> {
+	fn apply(&mut self, change: C) {
+		self.context.apply(change);
+	}
+	
+	fn apply_handle_any(&mut self, constructor: Box<ChangeConstructor<C>>) -> ApplyHandleAny {
+		self.context.apply_handle_any(constructor)
+	}
+	
+	fn new_transaction(&mut self, name: String) {
+		self.context.new_transaction(name);
+	}
+	
+	fn begin_transaction(&mut self, name: String) {
+		self.context.begin_transaction(name);
+	}
+	
+	fn commit_transaction(&mut self) {
+		self.context.commit_transaction();
+	}
+	
+	fn abort_transaction(&mut self) {
+		self.context.abort_transaction();
 	}
 }
 
 /*trait View {
 	fn dispatch(Revertable) -> 
 }*/
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::change_string::{StringChange, StringChangeError};
+	use crate::change_value::ValueChange;
+	use crate::change_vec::{VecChange, ValueVecChange, VecChangeConstructor};
+	use crate::history::History;
+	use std::rc::Rc;
+	use std::cell::RefCell;
+	
+	/// Collects the changes that `manager` rejects.
+	fn collect_rejected<T, C, V>(manager: &mut Manager<T, C, V>) -> Rc<RefCell<Vec<RejectedChange<C>>>> where
+		T: Changeable<C> + Object<C>,
+		C: 'static + Change + std::fmt::Debug + Clone,
+		V: Validator<T, C>,
+	{
+		let rejected = Rc::new(RefCell::new(vec![]));
+		let listener_rejected = rejected.clone();
+		manager.set_rejection_listener(Some(Box::new(move |rejected_change| listener_rejected.borrow_mut().push(rejected_change))));
+		rejected
+	}
+	
+	#[derive(Debug, Clone, PartialEq)]
+	struct TestModel {
+		names: Vec<String>,
+	}
+	
+	impl_changeable_struct!{TestModelChange[TestModelSignal] for TestModel:
+		names: VecChange<String, StringChange>,
+	}
+	
+	impl Object<TestModelChange> for TestModel {}
+	
+	#[test] fn manager_rejects_invalid_changes() {
+		let mut manager = Manager::new(TestModel { names: vec!["é".into()] }, NoValidator);
+		let rejected = collect_rejected(&mut manager);
+		
+		let out_of_bounds = TestModelChange::names(VecChange::Remove{index: 3});
+		let bad_text = TestModelChange::names(VecChange::At{index: 0, change: StringChange{index: 1, len: 0, new: "x".into()}});
+		manager.apply(out_of_bounds.clone());
+		manager.apply(bad_text.clone());
+		manager.apply(TestModelChange::names(VecChange::Insert{index: 1, item: "ok".into()}));
+		
+		assert_eq!(manager.get().names, vec!["é".to_string(), "ok".into()]);
+		assert_eq!(manager.take_signal_queue().len(), 1);
+		
+		let rejected = rejected.borrow();
+		assert_eq!(*rejected, vec![
+			RejectedChange {
+				change: out_of_bounds,
+				error: ApplyError {
+					path: vec!["names".into()],
+					kind: ApplyErrorKind::IndexOutOfBounds{index: 3, len: 1},
+				},
+			},
+			RejectedChange {
+				change: bad_text,
+				error: ApplyError {
+					path: vec!["names".into(), "[0]".into()],
+					kind: ApplyErrorKind::Text(StringChangeError::NotCharBoundary{index: 1}),
+				},
+			},
+		]);
+		assert_eq!(rejected[1].error.to_string(), "names/[0]: StringChange index 1 is not on a character boundary");
+	}
+	
+	/// Builds changes for the second name in a TestModel.
+	fn second_name_path() -> Box<ChangeConstructor<TestModelChange>> {
+		Box::new(SubChangeConstructor::new(
+			Box::new(VecChangeConstructor::new(1, Box::new(LeafChangeConstructor::new()))),
+			|sub_constructor, leaf_change| TestModelChange::names(sub_constructor.create(leaf_change)),
+			|sub_constructor, change| match *change {
+				TestModelChange::names(ref sub_change) => sub_constructor.update(sub_change),
+			},
+			|sub_constructor, change| match *change {
+				TestModelChange::names(ref sub_change) => sub_constructor.affects(sub_change),
+			},
+			|sub_constructor| format!("names/{}", sub_constructor.debug_string()),
+		))
+	}
+	
+	#[test] fn manager_subscriptions() {
+		use crate::change_vec::VecSignal;
+		use crate::change_string::StringSignal;
+		
+		let mut manager = Manager::new(TestModel { names: vec!["a".into(), "b".into()] }, NoValidator);
+		
+		let all = Rc::new(RefCell::new(vec![]));
+		let all_subscription = {
+			let all = all.clone();
+			manager.subscribe(Box::new(move |signal: &TestModelSignal| all.borrow_mut().push(signal.clone())))
+		};
+		
+		let second_name = Rc::new(RefCell::new(vec![]));
+		let _second_name_subscription = {
+			let second_name = second_name.clone();
+			manager.subscribe_path(second_name_path(), Box::new(move |signal: &TestModelSignal| second_name.borrow_mut().push(signal.clone())))
+		};
+		
+		let edit = |index| TestModelChange::names(VecChange::At{index, change: StringChange{index: 1, len: 0, new: "!".into()}});
+		manager.apply(edit(0));
+		manager.apply(TestModelChange::names(VecChange::Insert{index: 0, item: "z".into()}));
+		manager.apply(edit(2));
+		manager.apply(TestModelChange::names(VecChange::Remove{index: 2}));
+		manager.apply(TestModelChange::names(VecChange::Insert{index: 2, item: "c".into()}));
+		manager.apply(edit(2));
+		
+		let edit_signal = |index| TestModelSignal::names(VecSignal::At{index, signal: StringSignal{index: 1, from_len: 0, to_len: 1}});
+		assert_eq!(*second_name.borrow(), vec![
+			edit_signal(2),
+			TestModelSignal::names(VecSignal::Remove{index: 2}),
+		]);
+		assert_eq!(all.borrow().len(), 6);
+		assert_eq!(all.borrow()[0], edit_signal(0));
+		
+		// Signals are still queued up for the model itself
+		assert_eq!(manager.take_signal_queue().len(), 6);
+		
+		drop(all_subscription);
+		manager.apply(edit(0));
+		assert_eq!(all.borrow().len(), 6);
+	}
+	
+	#[test] fn apply_handle_acks() {
+		let mut manager = Manager::new(TestModel { names: vec!["a".into(), "b".into()] }, NoValidator);
+		let handle = ApplyHandle::<StringChange>::new(manager.change_queue.apply_handle_any(second_name_path()));
+		let root: ApplyHandle<TestModelChange> = manager.change_queue.apply_handle();
+		let rejected = collect_rejected(&mut manager);
+		
+		let mut ack = handle.invoke_with_ack(StringChange{index: 1, len: 0, new: "!".into()});
+		assert_eq!(ack.try_result(), None);
+		manager.try_process_async_changes();
+		assert_eq!(ack.try_result(), Some(ApplyResult::Applied));
+		assert_eq!(manager.get().names[1], "b!");
+		
+		let ack = handle.invoke_with_ack(StringChange{index: 10, len: 0, new: "!".into()});
+		manager.try_process_async_changes();
+		assert_eq!(ack.wait(), ApplyResult::Rejected(rejected.borrow()[0].error.clone()));
+		
+		manager.apply(TestModelChange::names(VecChange::Remove{index: 1}));
+		assert!(!handle.is_valid());
+		let ack = handle.invoke_with_ack(StringChange{index: 0, len: 0, new: "!".into()});
+		manager.try_process_async_changes();
+		assert_eq!(ack.wait(), ApplyResult::Invalidated);
+		assert_eq!(manager.get().names, vec!["a".to_string()]);
+		
+		let ack = root.invoke_with_ack(TestModelChange::names(VecChange::Remove{index: 0}));
+		assert!(root.is_valid());
+		drop(manager);
+		assert!(!root.is_valid());
+		assert_eq!(ack.wait(), ApplyResult::Invalidated);
+	}
+	
+	#[test] fn async_changes_until_shutdown() {
+		use std::thread;
+		use std::time::Duration;
+		
+		let mut manager = Manager::new(TestModel { names: vec![] }, NoValidator);
+		let handle: ApplyHandle<TestModelChange> = manager.change_queue.apply_handle();
+		let notifier = manager.get_async_change_notifier();
+		
+		assert!(manager.process_async_changes_until(Instant::now() + Duration::from_millis(10)));
+		
+		// A notification that comes in before the wait starts isn't lost
+		notifier.notify();
+		assert_eq!(notifier.wait_until(Instant::now() + Duration::from_secs(10)), WaitResult::Notified);
+		
+		let worker = thread::spawn(move || {
+			for name in &["a", "b", "c"] {
+				handle.invoke(TestModelChange::names(VecChange::Insert{index: 0, item: name.to_string()}));
+			}
+			notifier.shutdown();
+		});
+		manager.process_async_changes();
+		worker.join().unwrap();
+		
+		assert_eq!(manager.get().names, vec!["c".to_string(), "b".into(), "a".into()]);
+		assert!(!manager.process_async_changes_until(Instant::now() + Duration::from_secs(10)));
+	}
+	
+	#[test] fn acked_changes_are_not_coalesced() {
+		let mut manager = Manager::new(TestModel { names: vec!["a".into(), "b".into()] }, NoValidator);
+		manager.set_coalesce_changes(true);
+		let handle = ApplyHandle::<StringChange>::new(manager.change_queue.apply_handle_any(second_name_path()));
+		
+		let ack = handle.invoke_with_ack(StringChange{index: 1, len: 0, new: "!".into()});
+		let message = manager.change_queue.async_change_queue_recv.try_recv().unwrap();
+		manager.change_queue.process_apply_handle_message(message);
+		// This would merge with the acked change into one that can't be applied
+		manager.change_queue.apply(TestModelChange::names(VecChange::At{index: 1, change: StringChange{index: 2, len: 5, new: "".into()}}));
+		manager.apply_all_queued();
+		
+		assert_eq!(ack.wait(), ApplyResult::Applied);
+		assert_eq!(manager.get().names[1], "b!");
+	}
+	
+	#[derive(Debug, Clone, PartialEq)]
+	struct Editor {
+		names: Vec<String>,
+		name_ref: Option<usize>,
+	}
+	
+	impl_revertable_struct!{EditorChange[EditorSignal] for Editor:
+		names: ValueVecChange<String>,
+		name_ref: ValueChange<Option<usize>>,
+	}
+	
+	impl Object<EditorChange> for Editor {}
+	
+	/// Keeps name_ref pointing at the same name as the names change, and requires there to be at
+	/// least one name.
+	struct EditorValidator;
+	
+	impl Validator<Editor, EditorChange> for EditorValidator {
+		fn validate(&mut self, model: &Editor, cxt: &mut ApplyContext<EditorChange>, changes: &Vec<EditorChange>) -> Result<(), String> {
+			if model.names.is_empty() {
+				return Err("There must be at least one name".into());
+			}
+			
+			let mut name_ref = model.name_ref;
+			for change in changes {
+				match *change {
+					EditorChange::names(ref names_change) => name_ref = names_change.updated_reference(name_ref),
+					EditorChange::name_ref(ValueChange(new_ref)) => name_ref = new_ref,
+				}
+			}
+			
+			if let Some(index) = name_ref {
+				if index >= model.names.len() {
+					return Err(format!("name_ref {} is out of range", index));
+				}
+			}
+			if name_ref != model.name_ref {
+				cxt.apply(EditorChange::name_ref(ValueChange(name_ref)));
+			}
+			Ok(())
+		}
+	}
+	
+	#[test] fn manager_validates_batches() {
+		let editor = Editor { names: vec!["a".into(), "b".into()], name_ref: Some(1) };
+		let mut manager = Manager::new_validated(editor.clone(), EditorValidator);
+		let rejected = collect_rejected(&mut manager);
+		
+		manager.apply(EditorChange::names(VecChange::Insert{index: 0, item: "z".into()}));
+		assert_eq!(manager.get().names, vec!["z".to_string(), "a".into(), "b".into()]);
+		assert_eq!(manager.get().name_ref, Some(2));
+		assert_eq!(manager.take_signal_queue().len(), 2);
+		
+		manager.apply(EditorChange::names(VecChange::Remove{index: 2}));
+		assert_eq!(manager.get().name_ref, None);
+		manager.take_signal_queue();
+		
+		let bad_ref = EditorChange::name_ref(ValueChange(Some(5)));
+		manager.apply(bad_ref.clone());
+		let remove_all = EditorChange::names(VecChange::RemoveRange{range: 0..2});
+		manager.apply(remove_all.clone());
+		
+		assert_eq!(*manager.get(), Editor { names: vec!["z".into(), "a".into()], name_ref: None });
+		assert!(manager.take_signal_queue().is_empty());
+		assert_eq!(*rejected.borrow(), vec![
+			RejectedChange {
+				change: bad_ref,
+				error: ApplyErrorKind::Validation("name_ref 5 is out of range".into()).into(),
+			},
+			RejectedChange {
+				change: remove_all,
+				error: ApplyErrorKind::Validation("There must be at least one name".into()).into(),
+			},
+		]);
+	}
+	
+	#[derive(Debug, PartialEq)]
+	struct Document {
+		text: History<String, StringChange>,
+	}
+	
+	impl_changeable_struct!{DocumentChange[DocumentSignal] for Document:
+		text: HistoryChange<String, StringChange>,
+	}
+	
+	impl Object<DocumentChange> for Document {}
+	
+	#[test] fn nested_transaction_scopes() {
+		let mut manager = Manager::new(Document { text: History::new("".into()) }, NoValidator);
+		
+		{
+			let cxt = &mut manager.change_queue;
+			let mut text_cxt = SubApplyContext::new(
+				cxt,
+				&|change| change,
+				&|constructor| constructor,
+			);
+			let mut history_cxt = RevertableSubApplyContext::new(
+				&mut text_cxt,
+				&DocumentChange::text,
+				&|_| unreachable!(),
+			);
+			
+			let mut typing = TransactionScope::new(&mut history_cxt, "Typing".into());
+			typing.apply(StringChange{index: 0, len: 0, new: "Hello".into()});
+			{
+				let mut suffix = TransactionScope::new(&mut typing, "Suffix".into());
+				suffix.apply(StringChange{index: 5, len: 0, new: " World".into()});
+				suffix.abort();
+			}
+			typing.apply(StringChange{index: 5, len: 0, new: "!".into()});
+		}
+		manager.apply_all_queued();
+		
+		assert_eq!(manager.get().text.model, "Hello!");
+		assert_eq!(manager.get().text.transaction_depth(), 0);
+		
+		manager.apply(DocumentChange::text(HistoryChange::Undo));
+		assert_eq!(manager.get().text.model, "");
+		assert!(!manager.get().text.can_undo());
+	}
+}
@@ -0,0 +1,293 @@
+use crate::model::{Change, Changeable, Watcher, SpyWatcher};
+use crate::object::{Object, Manager, Validator, NoValidator, RejectedChange, Subscription};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::vec;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// A batch of changes that a Manager applied while it was being recorded.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+	serialize = "C: Serialize, C::SignalType: Serialize",
+	deserialize = "C: Deserialize<'de>, C::SignalType: Deserialize<'de>",
+)))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedBatch<C: Change> {
+	/// The position of the batch in the recording, starting from 0.
+	pub sequence: u64,
+	/// How long after the recording started the batch was applied.
+	pub time: Duration,
+	/// The changes in the order they were applied, including any corrections from the validator.
+	pub changes: Vec<C>,
+	/// The signals the batch sent, in the order they were sent.
+	pub signals: Vec<C::SignalType>,
+}
+
+/// Everything a Manager did while a ChangeRecorder was wrapping it, which a Replayer can repeat.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+	serialize = "T: Serialize, C: Serialize, C::SignalType: Serialize",
+	deserialize = "T: Deserialize<'de>, C: Deserialize<'de>, C::SignalType: Deserialize<'de>",
+)))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording<T, C: Change> {
+	/// The model when the recording started.
+	pub initial_model: T,
+	pub batches: Vec<RecordedBatch<C>>,
+	/// The model when the recording was taken.
+	pub final_model: T,
+}
+
+/// Wraps a Manager to record every batch of changes it applies, whether they were applied
+/// directly, queued up while resolving signals or sent through an apply handle.
+pub struct ChangeRecorder<T: Changeable<C>, C: Change, V: Validator<T, C>> {
+	manager: Manager<T, C, V>,
+	initial_model: T,
+	batches: Rc<RefCell<Vec<RecordedBatch<C>>>>,
+}
+
+impl<T, C, V> ChangeRecorder<T, C, V> where
+	T: Changeable<C> + Object<C> + Clone,
+	C: 'static + Change + fmt::Debug + Clone,
+	V: Validator<T, C>,
+{
+	/// Starts recording the changes that `manager` applies, from the model it has now.
+	pub fn new(mut manager: Manager<T, C, V>) -> ChangeRecorder<T, C, V> {
+		let batches = Rc::new(RefCell::new(vec![]));
+		let start = Instant::now();
+		
+		let observer_batches = batches.clone();
+		manager.set_change_observer(Some(Box::new(move |changes: &[&C], signals: &[C::SignalType]| {
+			let mut batches = observer_batches.borrow_mut();
+			let sequence = batches.len() as u64;
+			batches.push(RecordedBatch {
+				sequence,
+				time: start.elapsed(),
+				changes: changes.iter().map(|&change| change.clone()).collect(),
+				signals: signals.to_vec(),
+			});
+		})));
+		
+		ChangeRecorder {
+			initial_model: manager.get().clone(),
+			manager,
+			batches,
+		}
+	}
+	
+	pub fn manager(&self) -> &Manager<T, C, V> {
+		&self.manager
+	}
+	
+	/// Use this to apply changes and process signals as if the recorder wasn't there.
+	pub fn manager_mut(&mut self) -> &mut Manager<T, C, V> {
+		&mut self.manager
+	}
+	
+	/// Copies out everything recorded so far.
+	pub fn recording(&self) -> Recording<T, C> {
+		Recording {
+			initial_model: self.initial_model.clone(),
+			batches: self.batches.borrow().clone(),
+			final_model: self.manager.get().clone(),
+		}
+	}
+	
+	/// Stops recording, returning the manager along with the recording.
+	pub fn finish(mut self) -> (Manager<T, C, V>, Recording<T, C>) {
+		let recording = self.recording();
+		self.manager.set_change_observer(None);
+		(self.manager, recording)
+	}
+}
+
+/// Why a Replayer couldn't reproduce a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError<C: Change> {
+	/// A change in the batch with the given sequence couldn't be applied.
+	Rejected{sequence: u64, rejected: RejectedChange<C>},
+	/// The batch with the given sequence sent different signals to when it was recorded.
+	SignalMismatch{sequence: u64, expected: Vec<C::SignalType>, actual: Vec<C::SignalType>},
+	/// Every batch was replayed, but the model ended up different to when it was recorded.
+	ModelMismatch,
+}
+
+impl<C: Change> fmt::Display for ReplayError<C> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ReplayError::Rejected{sequence, ref rejected} => write!(f, "Batch {} has a change that was rejected: {}", sequence, rejected.error),
+			ReplayError::SignalMismatch{sequence, ref expected, ref actual} => write!(f, "Batch {} sent {:?} instead of {:?}", sequence, actual, expected),
+			ReplayError::ModelMismatch => write!(f, "Replayed model doesn't match the recorded one"),
+		}
+	}
+}
+
+/// Applies the batches from a Recording to a new Manager one at a time, checking that each one
+/// sends the same signals as when it was recorded.
+///
+/// The manager doesn't validate changes or resolve signals, since the recording already has the
+/// changes that the validator and the model's Object::update made.
+pub struct Replayer<T: Changeable<C>, C: Change> {
+	manager: Manager<T, C, NoValidator>,
+	spy: Rc<RefCell<SpyWatcher<C::SignalType>>>,
+	_subscription: Subscription,
+	/// The changes the manager rejected from the batch being replayed.
+	rejected: Rc<RefCell<Vec<RejectedChange<C>>>>,
+	batches: vec::IntoIter<RecordedBatch<C>>,
+	final_model: T,
+}
+
+impl<T, C> Replayer<T, C> where
+	T: Changeable<C> + Object<C> + PartialEq,
+	C: 'static + Change + fmt::Debug + Clone,
+{
+	pub fn new(recording: Recording<T, C>) -> Replayer<T, C> {
+		let mut manager = Manager::new(recording.initial_model, NoValidator);
+		let spy = Rc::new(RefCell::new(SpyWatcher::new()));
+		
+		let listener_spy = spy.clone();
+		let subscription = manager.subscribe(Box::new(move |signal: &C::SignalType| {
+			listener_spy.borrow_mut().send_signal(signal.clone());
+		}));
+		
+		let rejected = Rc::new(RefCell::new(vec![]));
+		let listener_rejected = rejected.clone();
+		manager.set_rejection_listener(Some(Box::new(move |rejected_change| {
+			listener_rejected.borrow_mut().push(rejected_change);
+		})));
+		
+		Replayer {
+			manager,
+			spy,
+			_subscription: subscription,
+			rejected,
+			batches: recording.batches.into_iter(),
+			final_model: recording.final_model,
+		}
+	}
+	
+	/// The manager that the recording is replayed into, to inspect the model between batches.
+	pub fn manager(&self) -> &Manager<T, C, NoValidator> {
+		&self.manager
+	}
+	
+	/// Replays the next batch. Returns false if there are no batches left.
+	pub fn replay_next(&mut self) -> Result<bool, ReplayError<C>> {
+		let batch = match self.batches.next() {
+			Some(batch) => batch,
+			None => return Ok(false),
+		};
+		
+		for change in batch.changes {
+			self.manager.apply(change);
+		}
+		self.manager.take_signal_queue();
+		
+		if let Some(rejected) = mem::take(&mut *self.rejected.borrow_mut()).into_iter().next() {
+			return Err(ReplayError::Rejected{sequence: batch.sequence, rejected});
+		}
+		
+		let signals = mem::take(&mut self.spy.borrow_mut().signals);
+		if signals != batch.signals {
+			return Err(ReplayError::SignalMismatch{sequence: batch.sequence, expected: batch.signals, actual: signals});
+		}
+		
+		Ok(true)
+	}
+	
+	/// Replays the rest of the batches, then checks that the model matches the recording. Returns
+	/// the replayed model.
+	pub fn finish(mut self) -> Result<T, ReplayError<C>> {
+		while self.replay_next()? {}
+		
+		let Replayer { manager, final_model, .. } = self;
+		let model = manager.take_model();
+		if model == final_model {
+			Ok(model)
+		} else {
+			Err(ReplayError::ModelMismatch)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::ApplyContext;
+	use crate::change_value::{ValueChange, ValueSignal};
+	use crate::change_vec::{VecChange, VecSignal, ValueVecChange};
+	
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+	#[derive(Debug, Clone, PartialEq)]
+	struct Roster {
+		names: Vec<String>,
+		count: usize,
+	}
+	
+	impl_changeable_struct!{RosterChange[RosterSignal] for Roster:
+		names: ValueVecChange<String>,
+		count: ValueChange<usize>,
+	}
+	
+	impl Object<RosterChange> for Roster {
+		fn update(&self, cxt: &mut ApplyContext<RosterChange>, signal: &RosterSignal) {
+			if let RosterSignal::names(_) = *signal {
+				cxt.apply(RosterChange::count(ValueChange(self.names.len())));
+			}
+		}
+	}
+	
+	fn record() -> Recording<Roster, RosterChange> {
+		let manager = Manager::new(Roster { names: vec!["a".into()], count: 0 }, NoValidator);
+		let mut recorder = ChangeRecorder::new(manager);
+		
+		recorder.manager_mut().apply(RosterChange::names(VecChange::Insert{index: 1, item: "b".into()}));
+		recorder.manager_mut().apply(RosterChange::names(VecChange::Remove{index: 0}));
+		// Rejected changes aren't recorded
+		recorder.manager_mut().apply(RosterChange::names(VecChange::Remove{index: 5}));
+		recorder.manager_mut().resolve_signals();
+		
+		let (manager, recording) = recorder.finish();
+		assert_eq!(*manager.get(), Roster { names: vec!["b".into()], count: 1 });
+		recording
+	}
+	
+	#[test] fn recording_replays() {
+		let recording = record();
+		assert_eq!(recording.batches.iter().map(|batch| batch.sequence).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+		assert_eq!(recording.batches[1].signals, vec![RosterSignal::names(VecSignal::Remove{index: 0})]);
+		assert_eq!(recording.batches[2].changes, vec![RosterChange::count(ValueChange(1))]);
+		assert!(recording.batches.windows(2).all(|pair| pair[0].time <= pair[1].time));
+		
+		let mut replayer = Replayer::new(recording.clone());
+		assert_eq!(replayer.replay_next(), Ok(true));
+		assert_eq!(replayer.manager().get().names, vec!["a".to_string(), "b".into()]);
+		assert_eq!(replayer.finish(), Ok(recording.final_model.clone()));
+		
+		let mut wrong_signals = recording.clone();
+		wrong_signals.batches[2].signals.push(RosterSignal::names(VecSignal::ReplaceAll));
+		assert_eq!(Replayer::new(wrong_signals).finish(), Err(ReplayError::SignalMismatch{
+			sequence: 2,
+			expected: vec![RosterSignal::count(ValueSignal), RosterSignal::names(VecSignal::ReplaceAll)],
+			actual: vec![RosterSignal::count(ValueSignal)],
+		}));
+		
+		let mut wrong_model = recording;
+		wrong_model.final_model.count = 2;
+		assert_eq!(Replayer::new(wrong_model).finish(), Err(ReplayError::ModelMismatch));
+	}
+	
+	#[cfg(feature = "serde")]
+	#[test] fn recording_serializes() {
+		let recording = record();
+		let json = serde_json::to_string(&recording).unwrap();
+		let loaded: Recording<Roster, RosterChange> = serde_json::from_str(&json).unwrap();
+		assert_eq!(loaded, recording);
+		assert!(Replayer::new(loaded).finish().is_ok());
+	}
+}
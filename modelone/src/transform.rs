@@ -0,0 +1,137 @@
+use crate::model::Change;
+
+/// Decides which of two concurrent changes wins when they conflict, such as when both set the
+/// same value or both insert at the same position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+	/// The change being transformed wins. It overrides the other change, and text or items it
+	/// inserts at the same position go first.
+	Mine,
+	/// The other change wins.
+	Theirs,
+}
+
+impl Priority {
+	/// The priority to use when transforming the other change the other way around.
+	pub fn opposite(self) -> Priority {
+		match self {
+			Priority::Mine => Priority::Theirs,
+			Priority::Theirs => Priority::Mine,
+		}
+	}
+}
+
+/// Operational transformation for changes that were made concurrently against the same model
+/// state.
+///
+/// Given changes `a` and `b`, `a.transform(&b, priority)` rebases `a` so that it can be applied
+/// after `b`. As long as `b` is transformed over `a` with the opposite priority, applying `a` then
+/// the transformed `b` ends up with the same model as applying `b` then the transformed `a`.
+pub trait Transform: Change {
+	/// Rebases this change so that it can be applied after `other`. Returns None if `other` makes
+	/// this change meaningless, such as when it removed the item this change modifies, or when
+	/// both changes set the same value and `other` has priority.
+	fn transform(self, other: &Self, priority: Priority) -> Option<Self>;
+}
+
+/// Rebases `change` over each of the `others` in turn, which is useful for a change that was made
+/// against an older model state that has since had `others` applied to it.
+pub fn rebase<C: Transform>(change: C, others: &[C], priority: Priority) -> Option<C> {
+	others.iter().try_fold(change, |change, other| change.transform(other, priority))
+}
+
+/// Implements Transform for the change type generated by `impl_changeable_struct!`, when it's
+/// given `with Transform`. Changes to different fields don't affect each other, and changes to the
+/// same field are transformed by the field's change type, which must implement Transform.
+#[doc(hidden)]
+#[macro_export] macro_rules! impl_transform_struct{
+	($change_name:ident[$signal_name:ident] for $model_name:ident:
+		$($field_name:ident: $change_type:ty,)+
+	) => {
+		impl $crate::transform::Transform for $change_name {
+			fn transform(self, other: &$change_name, priority: $crate::transform::Priority) -> Option<$change_name> {
+				#[allow(unreachable_patterns)]
+				match (self, other) {
+					$(
+						($change_name::$field_name(subchange), $change_name::$field_name(other_subchange)) => {
+							$crate::transform::Transform::transform(subchange, other_subchange, priority).map($change_name::$field_name)
+						},
+					)*
+					(change, _) => Some(change),
+				}
+			}
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{Changeable, NoWatcher};
+	use crate::change_value::ValueChange;
+	use crate::change_string::StringChange;
+	use crate::change_vec::VecChange;
+	use crate::{Changeable, Transform};
+	
+	#[derive(Debug, Clone, PartialEq)]
+	struct Document {
+		title: String,
+		tags: Vec<String>,
+	}
+	
+	impl_changeable_struct!{DocumentChange[DocumentSignal] for Document with Transform:
+		title: StringChange,
+		tags: VecChange<String, StringChange>,
+	}
+	
+	#[derive(Debug, Clone, PartialEq, Changeable, Transform)]
+	struct Pair<T: 'static + PartialEq + Send> {
+		#[change(ValueChange<T>)]
+		first: T,
+		#[change(ValueChange<T>)]
+		second: T,
+	}
+	
+	#[test] fn transform_struct_change() {
+		let title_change = DocumentChange::title(StringChange{index: 0, len: 0, new: "My ".into()});
+		let tags_change = DocumentChange::tags(VecChange::Remove{index: 0});
+		let tag_change = DocumentChange::tags(VecChange::At{index: 1, change: StringChange{index: 0, len: 1, new: "B".into()}});
+		
+		assert_eq!(title_change.clone().transform(&tags_change, Priority::Theirs), Some(title_change.clone()));
+		assert_eq!(
+			rebase(tag_change, &[title_change, tags_change], Priority::Mine),
+			Some(DocumentChange::tags(VecChange::At{index: 0, change: StringChange{index: 0, len: 1, new: "B".into()}}))
+		);
+		
+		// Applying either change first ends up with the same document
+		let document = Document { title: "Notes".into(), tags: vec!["a".into(), "b".into()] };
+		let mine = DocumentChange::tags(VecChange::Insert{index: 1, item: "x".into()});
+		let theirs = DocumentChange::tags(VecChange::Insert{index: 1, item: "y".into()});
+		
+		let mut mine_first = document.clone();
+		mine_first.changeable_apply(mine.clone(), &mut NoWatcher);
+		mine_first.changeable_apply(theirs.clone().transform(&mine, Priority::Theirs).unwrap(), &mut NoWatcher);
+		let mut theirs_first = document;
+		theirs_first.changeable_apply(theirs.clone(), &mut NoWatcher);
+		theirs_first.changeable_apply(mine.transform(&theirs, Priority::Mine).unwrap(), &mut NoWatcher);
+		assert_eq!(mine_first, theirs_first);
+		assert_eq!(mine_first.tags, vec!["a".to_string(), "x".into(), "y".into(), "b".into()]);
+	}
+	
+	#[test] fn derived_transform() {
+		let first = PairChange::first(ValueChange(1));
+		let other_first = PairChange::first(ValueChange(2));
+		let second = PairChange::second(ValueChange(3));
+		
+		assert_eq!(first.clone().transform(&second, Priority::Theirs), Some(first.clone()));
+		assert_eq!(first.clone().transform(&other_first, Priority::Mine), Some(first.clone()));
+		assert_eq!(first.clone().transform(&other_first, Priority::Theirs), None);
+		
+		let mut pair = Pair { first: 0, second: 0 };
+		pair.changeable_apply(other_first.clone(), &mut NoWatcher);
+		for change in rebase(first, &[other_first], Priority::Mine).into_iter().chain(Some(second)) {
+			pair.changeable_apply(change, &mut NoWatcher);
+		}
+		assert_eq!(pair, Pair { first: 1, second: 3 });
+	}
+}
@@ -0,0 +1,575 @@
+use crate::model::{Change, Changeable, Revertable, Watcher, SubWatcher, ApplyError, ApplyErrorKind, apply_pipe_to_mut_ref};
+use crate::history::{ChangeSet, HistoryChange, HistorySignal, TransactionInfo};
+
+use std::fmt;
+use std::time::SystemTime;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// A transaction in an UndoTree.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, PartialEq)]
+struct UndoNode<C> {
+	/// The root node is its own parent.
+	parent: usize,
+	children: Vec<usize>,
+	/// The child that Redo moves to, which is the one that was most recently left or created.
+	redo_child: Option<usize>,
+	/// While this node is on the path from the root to the current node, these are the changes
+	/// that revert its transaction. Otherwise they are the changes that reapply it.
+	changeset: ChangeSet<C>,
+}
+
+impl<C: Change> UndoNode<C> {
+	fn new(parent: usize, name: String) -> UndoNode<C> {
+		UndoNode {
+			parent,
+			children: vec![],
+			redo_child: None,
+			changeset: ChangeSet::new(name),
+		}
+	}
+}
+
+/// Like [`History`](../history/struct.History.html), except that making a change after undoing
+/// starts a new branch instead of throwing away what could have been redone, so the model can be
+/// returned to any state it has been in.
+///
+/// Each transaction is a node in the tree, identified by the order it was created in. The root
+/// node 0 is the state before any transactions. This is changed with the same HistoryChange as
+/// History, which also has changes for moving around the tree.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, PartialEq)]
+pub struct UndoTree<T: Revertable<C>, C: Change> {
+	pub model: T,
+	nodes: Vec<UndoNode<C>>,
+	current: usize,
+	/// For each open transaction scope, the number of changes the current node had when it began.
+	/// The first one is the outermost scope.
+	open_transactions: Vec<usize>,
+	/// The node that was current when the clean state was marked, or None if it can't be
+	/// returned to.
+	clean_node: Option<usize>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	coalesce_changes: bool,
+}
+
+impl<T: Revertable<C> + fmt::Debug, C: Change> fmt::Debug for UndoTree<T, C> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "UndoTree({}/{}): ", self.current, self.nodes.len())?;
+		self.model.fmt(f)
+	}
+}
+
+impl<T: Revertable<C>, C: Change> UndoTree<T, C> {
+	pub fn new(model: T) -> UndoTree<T, C> {
+		UndoTree {
+			model,
+			nodes: vec![UndoNode::new(0, "".into())],
+			current: 0,
+			open_transactions: vec![],
+			clean_node: Some(0),
+			coalesce_changes: false,
+		}
+	}
+	
+	pub fn can_undo(&self) -> bool {
+		self.current != 0
+	}
+	
+	pub fn can_redo(&self) -> bool {
+		self.nodes[self.current].redo_child.is_some()
+	}
+	
+	/// The number of transaction scopes that have begun and haven't been committed or aborted.
+	pub fn transaction_depth(&self) -> usize {
+		self.open_transactions.len()
+	}
+	
+	pub fn coalesce_changes(&self) -> bool {
+		self.coalesce_changes
+	}
+	
+	/// When enabled, each pushed change is merged with the one pushed before it in the same
+	/// transaction where possible. Disabled by default.
+	pub fn set_coalesce_changes(&mut self, coalesce_changes: bool) {
+		self.coalesce_changes = coalesce_changes;
+	}
+	
+	/// The ID of the node for the model's current state.
+	pub fn current(&self) -> usize {
+		self.current
+	}
+	
+	/// The number of transactions between the root and the current node, which is how many can be
+	/// undone.
+	pub fn depth(&self) -> usize {
+		let mut depth = 0;
+		let mut node = self.current;
+		while node != 0 {
+			depth += 1;
+			node = self.nodes[node].parent;
+		}
+		depth
+	}
+	
+	/// Whether the model is in the state that was marked by `HistoryChange::MarkClean`. A new or
+	/// reset tree is clean. Nodes without any changes don't count, so starting a transaction
+	/// doesn't make the tree modified.
+	pub fn is_clean(&self) -> bool {
+		match self.clean_node {
+			Some(clean) => self.is_empty_path(self.current, clean) || self.is_empty_path(clean, self.current),
+			None => false,
+		}
+	}
+	
+	/// Whether `ancestor` can be reached from `node` by going up through nodes without changes.
+	fn is_empty_path(&self, node: usize, ancestor: usize) -> bool {
+		let mut node = node;
+		while node != ancestor && node != 0 && self.nodes[node].changeset.changes.is_empty() {
+			node = self.nodes[node].parent;
+		}
+		node == ancestor
+	}
+	
+	/// The number of nodes in the tree, including the root.
+	pub fn node_count(&self) -> usize {
+		self.nodes.len()
+	}
+	
+	/// Returns the parent of `node`, or None for the root.
+	pub fn parent(&self, node: usize) -> Option<usize> {
+		if node == 0 { None } else { Some(self.nodes[node].parent) }
+	}
+	
+	/// Returns the branches from `node`, oldest first.
+	pub fn children(&self, node: usize) -> &[usize] {
+		&self.nodes[node].children
+	}
+	
+	/// Returns the branch from `node` that Redo follows.
+	pub fn redo_child(&self, node: usize) -> Option<usize> {
+		self.nodes[node].redo_child
+	}
+	
+	/// The name the transaction for `node` was given.
+	pub fn name(&self, node: usize) -> &str {
+		&self.nodes[node].changeset.name
+	}
+	
+	/// When `node` was created.
+	pub fn time(&self, node: usize) -> SystemTime {
+		self.nodes[node].changeset.time
+	}
+	
+	/// A read-only view of the transaction for `node`.
+	pub fn transaction(&self, node: usize) -> TransactionInfo<'_> {
+		self.nodes[node].changeset.info()
+	}
+	
+	/// Adds a new node as a child of the current one and moves to it.
+	fn add_node(&mut self, name: String) {
+		let id = self.nodes.len();
+		self.nodes.push(UndoNode::new(self.current, name));
+		let current = &mut self.nodes[self.current];
+		current.children.push(id);
+		current.redo_child = Some(id);
+		self.current = id;
+	}
+	
+	/// Moves to the parent of the current node by reverting its transaction.
+	fn undo_node(&mut self, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let model = &mut self.model;
+		let node = &mut self.nodes[self.current];
+		let mut watcher_fn = |signal| {
+			watcher.send_signal(HistorySignal::Change(signal));
+		};
+		
+		for revertchange in node.changeset.changes.iter_mut().rev() {
+			apply_pipe_to_mut_ref(|change| model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), revertchange);
+		}
+		
+		let child = self.current;
+		self.current = node.parent;
+		self.nodes[self.current].redo_child = Some(child);
+	}
+	
+	/// Moves to `child` of the current node by reapplying its transaction.
+	fn redo_node(&mut self, child: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let model = &mut self.model;
+		let node = &mut self.nodes[child];
+		let mut watcher_fn = |signal| {
+			watcher.send_signal(HistorySignal::Change(signal));
+		};
+		
+		for reapplychange in node.changeset.changes.iter_mut() {
+			apply_pipe_to_mut_ref(|change| model.revertable_apply(change, &mut SubWatcher::new(&mut watcher_fn)), reapplychange);
+		}
+		
+		self.nodes[self.current].redo_child = Some(child);
+		self.current = child;
+	}
+	
+	/// Moves up to `count` nodes towards the root, sending one Undo signal at the end if it moved.
+	fn undo_steps(&mut self, count: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let count = std::cmp::min(count, self.depth());
+		for _ in 0..count {
+			self.undo_node(watcher);
+		}
+		
+		if count > 0 {
+			watcher.send_signal(HistorySignal::Undo);
+			watcher.send_signal(HistorySignal::CurrentTransaction);
+		}
+	}
+	
+	/// Moves up to `count` nodes along the redo branches, sending one Redo signal at the end if it
+	/// moved.
+	fn redo_steps(&mut self, count: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let mut redone = false;
+		for _ in 0..count {
+			if let Some(child) = self.nodes[self.current].redo_child {
+				self.redo_node(child, watcher);
+				redone = true;
+			}
+		}
+		
+		if redone {
+			watcher.send_signal(HistorySignal::Redo);
+			watcher.send_signal(HistorySignal::CurrentTransaction);
+		}
+	}
+	
+	/// Moves to `target` by undoing up to the closest node it shares with the current node, then
+	/// redoing down to it.
+	fn jump_to(&mut self, target: usize, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		let mut on_current_path = vec![false; self.nodes.len()];
+		let mut node = self.current;
+		on_current_path[0] = true;
+		while node != 0 {
+			on_current_path[node] = true;
+			node = self.nodes[node].parent;
+		}
+		
+		let mut redo_path = vec![];
+		let mut common = target;
+		while !on_current_path[common] {
+			redo_path.push(common);
+			common = self.nodes[common].parent;
+		}
+		
+		while self.current != common {
+			self.undo_node(watcher);
+		}
+		for child in redo_path.into_iter().rev() {
+			self.redo_node(child, watcher);
+		}
+	}
+}
+
+impl<T: 'static + Revertable<C> + Send, C: Change> Changeable<HistoryChange<T, C>> for UndoTree<T, C> {
+	fn changeable_apply(&mut self, change: HistoryChange<T, C>, watcher: &mut Watcher<HistorySignal<C::SignalType>>) {
+		use crate::history::HistoryChange::*;
+		let was_clean = self.is_clean();
+		let jump_target = match change {
+			Reset(newmodel) => {
+				self.model = *newmodel;
+				self.nodes = vec![UndoNode::new(0, "".into())];
+				self.current = 0;
+				self.open_transactions.clear();
+				self.clean_node = Some(0);
+				watcher.send_signal(HistorySignal::Reset);
+				None
+			},
+			NewTransaction(name) => {
+				// Nested transactions are grouped into the open one
+				if self.open_transactions.is_empty() {
+					self.add_node(name);
+					watcher.send_signal(HistorySignal::CurrentTransaction);
+				}
+				None
+			},
+			BeginTransaction(name) => {
+				if self.open_transactions.is_empty() {
+					self.add_node(name);
+					self.open_transactions.push(0);
+					watcher.send_signal(HistorySignal::CurrentTransaction);
+				} else {
+					self.open_transactions.push(self.nodes[self.current].changeset.changes.len());
+				}
+				None
+			},
+			CommitTransaction => {
+				self.open_transactions.pop();
+				None
+			},
+			AbortTransaction => {
+				if let Some(start) = self.open_transactions.pop() {
+					if self.clean_node == Some(self.current) && self.nodes[self.current].changeset.changes.len() > start {
+						self.clean_node = None;
+					}
+					{
+						let model = &mut self.model;
+						let node = &mut self.nodes[self.current];
+						let mut watcher_fn = |signal| {
+							watcher.send_signal(HistorySignal::Change(signal));
+						};
+						
+						for revertchange in node.changeset.changes.drain(start..).rev() {
+							model.changeable_apply(revertchange, &mut SubWatcher::new(&mut watcher_fn));
+						}
+					}
+					
+					// The outermost scope added the current node, and nothing can have been added
+					// after it, so it is the last node
+					if self.open_transactions.is_empty() {
+						if self.clean_node == Some(self.current) {
+							self.clean_node = Some(self.nodes[self.current].parent);
+						}
+						self.current = self.nodes[self.current].parent;
+						self.nodes.pop();
+						let parent = &mut self.nodes[self.current];
+						parent.children.pop();
+						parent.redo_child = parent.children.last().cloned();
+						watcher.send_signal(HistorySignal::CurrentTransaction);
+					}
+				}
+				None
+			},
+			Push(subchange) => {
+				let revertchange = {
+					let mut watcher_fn = |signal| {
+						watcher.send_signal(HistorySignal::Change(signal));
+					};
+					self.model.revertable_apply(subchange, &mut SubWatcher::new(&mut watcher_fn))
+				};
+				
+				// Other nodes were made from the current node's state, so its transaction can't
+				// be added to without breaking them, and a new branch is started instead
+				if self.current == 0 || !self.nodes[self.current].children.is_empty() {
+					self.add_node("".into());
+				} else if self.clean_node == Some(self.current) {
+					// The marked state is being added to, so it can't be returned to
+					self.clean_node = None;
+				}
+				let floor = self.open_transactions.last().cloned().unwrap_or(0);
+				self.nodes[self.current].changeset.push(revertchange, floor, self.coalesce_changes, None);
+				watcher.send_signal(HistorySignal::Push);
+				None
+			},
+			Undo => {
+				self.open_transactions.clear();
+				self.undo_steps(1, watcher);
+				None
+			},
+			Redo => {
+				self.open_transactions.clear();
+				self.redo_steps(1, watcher);
+				None
+			},
+			UndoTo(remaining) => {
+				self.open_transactions.clear();
+				let count = self.depth().saturating_sub(remaining);
+				self.undo_steps(count, watcher);
+				None
+			},
+			RedoTo(remaining) => {
+				self.open_transactions.clear();
+				let mut redo_count: usize = 0;
+				let mut node = self.current;
+				while let Some(child) = self.nodes[node].redo_child {
+					redo_count += 1;
+					node = child;
+				}
+				self.redo_steps(redo_count.saturating_sub(remaining), watcher);
+				None
+			},
+			ClearHistory => {
+				self.clean_node = if was_clean { Some(0) } else { None };
+				self.nodes = vec![UndoNode::new(0, "".into())];
+				self.current = 0;
+				self.open_transactions.clear();
+				watcher.send_signal(HistorySignal::ClearHistory);
+				watcher.send_signal(HistorySignal::CurrentTransaction);
+				None
+			},
+			MarkClean => {
+				self.clean_node = Some(self.current);
+				None
+			},
+			JumpTo(node) => Some(node),
+			Earlier(count) => Some(self.current.saturating_sub(count)),
+			Later(count) => Some(std::cmp::min(self.current.saturating_add(count), self.nodes.len() - 1)),
+			JumpToTime(time) => Some(self.nodes.iter().rposition(|node| node.changeset.time <= time).unwrap_or(0)),
+			SelectBranch(index) => {
+				let current = &mut self.nodes[self.current];
+				if let Some(&child) = current.children.get(index) {
+					current.redo_child = Some(child);
+					watcher.send_signal(HistorySignal::SelectBranch);
+				}
+				None
+			},
+		};
+		
+		if let Some(target) = jump_target {
+			self.open_transactions.clear();
+			if target < self.nodes.len() && target != self.current {
+				self.jump_to(target, watcher);
+				watcher.send_signal(HistorySignal::Jump);
+				watcher.send_signal(HistorySignal::CurrentTransaction);
+			}
+		}
+		
+		if self.is_clean() != was_clean {
+			watcher.send_signal(HistorySignal::CleanStateChanged);
+		}
+	}
+	
+	fn reset_view_signals(&self) -> Vec<HistorySignal<C::SignalType>> {
+		vec![HistorySignal::Reset, HistorySignal::CurrentTransaction]
+	}
+	
+	fn validate_change(&self, change: &HistoryChange<T, C>) -> Result<(), ApplyError> {
+		match *change {
+			HistoryChange::Push(ref subchange) => {
+				self.model.validate_change(subchange).map_err(|err| err.within("Push"))
+			}
+			HistoryChange::JumpTo(node) if node >= self.nodes.len() => {
+				Err(ApplyErrorKind::IndexOutOfBounds{index: node, len: self.nodes.len()}.into())
+			}
+			HistoryChange::SelectBranch(index) if index >= self.nodes[self.current].children.len() => {
+				Err(ApplyErrorKind::IndexOutOfBounds{index, len: self.nodes[self.current].children.len()}.into())
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::model::{NoWatcher, SpyWatcher};
+	use crate::change_string::StringChange;
+	use std::time::Duration;
+	
+	fn type_text(tree: &mut UndoTree<String, StringChange>, text: &str) {
+		let index = tree.model.len();
+		tree.changeable_apply(HistoryChange::NewTransaction(text.into()), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Push(StringChange{index, len: 0, new: text.into()}), &mut NoWatcher);
+	}
+	
+	#[test] fn undo_tree_keeps_branches() {
+		let mut tree: UndoTree<String, StringChange> = UndoTree::new("".into());
+		type_text(&mut tree, "a");
+		type_text(&mut tree, "b");
+		tree.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		type_text(&mut tree, "c");
+		assert_eq!(tree.model, "ac");
+		assert_eq!(tree.children(1), &[2, 3]);
+		assert_eq!(tree.parent(3), Some(1));
+		assert_eq!(tree.name(2), "b");
+		
+		let mut spy = SpyWatcher::new();
+		tree.changeable_apply(HistoryChange::JumpTo(2), &mut spy);
+		assert_eq!(tree.model, "ab");
+		assert_eq!(tree.current(), 2);
+		assert!(spy.signals.contains(&HistorySignal::Jump));
+		
+		tree.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert_eq!(tree.redo_child(1), Some(2));
+		tree.changeable_apply(HistoryChange::SelectBranch(1), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		assert_eq!(tree.model, "ac");
+		
+		tree.changeable_apply(HistoryChange::JumpTo(0), &mut NoWatcher);
+		assert_eq!(tree.model, "");
+		assert!(!tree.can_undo());
+		tree.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Redo, &mut NoWatcher);
+		assert_eq!(tree.model, "ac");
+		
+		assert!(tree.validate_change(&HistoryChange::JumpTo(4)).is_err());
+		
+		tree.changeable_apply(HistoryChange::UndoTo(0), &mut NoWatcher);
+		assert_eq!(tree.model, "");
+		tree.changeable_apply(HistoryChange::RedoTo(1), &mut NoWatcher);
+		assert_eq!(tree.model, "a");
+		assert_eq!(tree.depth(), 1);
+		assert_eq!(tree.transaction(3).name, "c");
+	}
+	
+	#[test] fn undo_tree_time_navigation() {
+		let mut tree: UndoTree<String, StringChange> = UndoTree::new("".into());
+		type_text(&mut tree, "a");
+		type_text(&mut tree, "b");
+		tree.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		type_text(&mut tree, "c");
+		
+		tree.changeable_apply(HistoryChange::Earlier(1), &mut NoWatcher);
+		assert_eq!(tree.model, "ab");
+		tree.changeable_apply(HistoryChange::Earlier(5), &mut NoWatcher);
+		assert_eq!(tree.model, "");
+		tree.changeable_apply(HistoryChange::Later(2), &mut NoWatcher);
+		assert_eq!(tree.model, "ab");
+		tree.changeable_apply(HistoryChange::Later(5), &mut NoWatcher);
+		assert_eq!(tree.model, "ac");
+		
+		// Nodes made in quick succession can have the same time, so they are spread out
+		let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+		for (id, node) in tree.nodes.iter_mut().enumerate() {
+			node.changeset.time = start + Duration::from_secs(10 * id as u64);
+		}
+		tree.changeable_apply(HistoryChange::JumpToTime(start + Duration::from_secs(15)), &mut NoWatcher);
+		assert_eq!(tree.current(), 1);
+		assert_eq!(tree.model, "a");
+		tree.changeable_apply(HistoryChange::JumpToTime(start + Duration::from_secs(20)), &mut NoWatcher);
+		assert_eq!(tree.current(), 2);
+		assert_eq!(tree.model, "ab");
+		tree.changeable_apply(HistoryChange::JumpToTime(start + Duration::from_secs(100)), &mut NoWatcher);
+		assert_eq!(tree.current(), 3);
+		assert_eq!(tree.model, "ac");
+		tree.changeable_apply(HistoryChange::JumpToTime(SystemTime::UNIX_EPOCH), &mut NoWatcher);
+		assert_eq!(tree.current(), 0);
+		assert_eq!(tree.model, "");
+	}
+	
+	#[test] fn undo_tree_abort_transaction() {
+		let mut tree: UndoTree<String, StringChange> = UndoTree::new("".into());
+		type_text(&mut tree, "a");
+		tree.changeable_apply(HistoryChange::BeginTransaction("b".into()), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Push(StringChange{index: 1, len: 0, new: "b".into()}), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::AbortTransaction, &mut NoWatcher);
+		assert_eq!(tree.model, "a");
+		assert_eq!(tree.node_count(), 2);
+		assert_eq!(tree.current(), 1);
+		assert!(!tree.can_redo());
+	}
+	
+	#[test] fn undo_tree_clean_state() {
+		let mut tree: UndoTree<String, StringChange> = UndoTree::new("".into());
+		type_text(&mut tree, "a");
+		tree.changeable_apply(HistoryChange::MarkClean, &mut NoWatcher);
+		type_text(&mut tree, "b");
+		assert!(!tree.is_clean());
+		
+		// Branching doesn't lose the clean state
+		tree.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert!(tree.is_clean());
+		type_text(&mut tree, "c");
+		let mut spy = SpyWatcher::new();
+		tree.changeable_apply(HistoryChange::JumpTo(1), &mut spy);
+		assert!(tree.is_clean());
+		assert!(spy.signals.contains(&HistorySignal::CleanStateChanged));
+		
+		tree.changeable_apply(HistoryChange::BeginTransaction("Empty".into()), &mut NoWatcher);
+		assert!(tree.is_clean());
+		tree.changeable_apply(HistoryChange::AbortTransaction, &mut NoWatcher);
+		assert!(tree.is_clean());
+		
+		tree.changeable_apply(HistoryChange::JumpTo(3), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::MarkClean, &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Push(StringChange{index: 2, len: 0, new: "d".into()}), &mut NoWatcher);
+		tree.changeable_apply(HistoryChange::Undo, &mut NoWatcher);
+		assert!(!tree.is_clean());
+	}
+}
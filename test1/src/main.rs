@@ -40,19 +40,33 @@ impl_revertable_struct!{EditorChange[EditorSignal] for Editor:
 	user_name: NameRecordChange,
 }
 
-/*struct EditorValidator;
+impl Object<EditorChange> for Editor {}
+
+/// Keeps name_ref pointing at the same name when names changes.
+struct EditorValidator;
 
 impl Validator<Editor, EditorChange> for EditorValidator {
-	fn validate(&mut self, cxt: &mut ApplyContext<Editor, EditorChange>, changes: &Vec<EditorChange>) -> Result<(), String> {
+	fn validate(&mut self, model: &Editor, cxt: &mut ApplyContext<EditorChange>, changes: &Vec<EditorChange>) -> Result<(), String> {
+		let mut name_ref = model.name_ref;
 		for change in changes {
-			if let EditorChange::names(ref names_change) = *change {
-				let new_ref = names_change.updated_reference(self.name_ref);
-				cxt.apply(EditorChange::name_ref(ValueChange::Set(new_ref)));
+			match *change {
+				EditorChange::names(ref names_change) => name_ref = names_change.updated_reference(name_ref),
+				EditorChange::name_ref(ValueChange(new_ref)) => name_ref = new_ref,
+				_ => {}
+			}
+		}
+		
+		if let Some(index) = name_ref {
+			if index >= model.names.len() {
+				return Err(format!("name_ref {} is out of range", index));
 			}
 		}
+		if name_ref != model.name_ref {
+			cxt.apply(EditorChange::name_ref(ValueChange(name_ref)));
+		}
 		Ok(())
 	}
-}*/
+}
 
 #[derive(Debug, Clone, PartialEq)]
 struct AppModel {
@@ -245,6 +259,9 @@ fn main() {
 	
 	println!("{:#?}", manager.get());
 	
+	let mut editor_manager = Manager::new_validated(manager.get().app_model.editor.model.clone(), EditorValidator);
+	editor_manager.apply(EditorChange::names(VecChange::Insert{index: 0, item: "First".into()}));
+	
 	//let scenegraph = SceneGraph::new();
 	
 	println!("Change size: {}", std::mem::size_of::<AppModelChange>());
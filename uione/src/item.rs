@@ -7,6 +7,7 @@ use modelone::model::{Change, Changeable, Watcher};
 use modelone::object::{ApplyContext, ApplyHandle, JustSignal, JustSignalChange, Object};
 use modelone::change_value::ValueChange;
 use modelone::change_option::OptionChange;
+use modelone::AllocId;
 
 use std::fmt;
 use std::cell::RefCell;
@@ -28,12 +29,12 @@ use bitflags::bitflags;
 }*/
 
 pub enum ItemUpdateMessage {
-	MarkDamaged{id: usize},
-	UpdateApplyHandle{id: usize, apply_handle: ApplyHandle<ItemDataChange>},
-	ChildrenAdded{id: usize, indices: Vec<usize>},
-	ChildrenRemoved{id: usize, indices: Vec<usize>},
-	AnimationStarted{id: usize},
-	AnimationStopped{id: usize},
+	MarkDamaged{id: AllocId},
+	UpdateApplyHandle{id: AllocId, apply_handle: ApplyHandle<ItemDataChange>},
+	ChildrenAdded{id: AllocId, indices: Vec<usize>},
+	ChildrenRemoved{id: AllocId, indices: Vec<usize>},
+	AnimationStarted{id: AllocId},
+	AnimationStopped{id: AllocId},
 }
 
 /// This must be implemented for all UI objects. It represents a rectangle on
@@ -102,7 +103,7 @@ pub struct ItemDataInternal {
 	/// This would start off as None, but then be set to a unique identifier by
 	/// the scene graph so it can be referred to in subsequent communications
 	/// with the scene graph.
-	pub id: usize,
+	pub id: AllocId,
 	pub message_sender: mpsc::Sender<ItemUpdateMessage>,
 }
 
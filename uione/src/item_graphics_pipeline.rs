@@ -24,7 +24,7 @@ use cgmath;
 use gl;
 use gl::types::{GLuint, GLint, GLenum, GLsizei, GLboolean};
 
-use modelone::IdAlloc;
+use modelone::{IdAlloc, AllocId};
 use modelone::object::{ApplyHandle, JustSignalChange};
 use modelone::change_value::ValueChange;
 use modelone::change_option::OptionChange;
@@ -39,7 +39,7 @@ uione_uniform_struct! {
 }
 
 struct ItemCacheEntry {
-	parent: Option<usize>,
+	parent: Option<AllocId>,
 	//children: Vec<usize>,
 	apply_handle: Option<ApplyHandle<item::ItemDataChange>>,
 	pos: Vec2f,
@@ -177,7 +177,7 @@ impl ItemCacheEntry {
 
 struct ItemCache {
 	alloc: IdAlloc<RefCell<ItemCacheEntry>>,
-	main_item: Option<usize>,
+	main_item: Option<AllocId>,
 }
 
 impl ItemCache {
@@ -192,20 +192,19 @@ impl ItemCache {
 		self.main_item = id;
 	}*/
 	
-	fn draw_worker(&self, transform: &cgmath::Matrix4<f32>, id: usize, resource_manager: &mut GraphicResourceManager, shader_cache: &mut ShaderCache) {
-		{
-			let entry = self.alloc.get(id);
+	fn draw_worker(&self, transform: &cgmath::Matrix4<f32>, id: AllocId, resource_manager: &mut GraphicResourceManager, shader_cache: &mut ShaderCache) {
+		if let Some(entry) = self.alloc.try_get(id) {
 			entry.borrow_mut().draw(transform, resource_manager, shader_cache);
 		}
 		
-		self.alloc.apply_to_all(&mut |entry_id, entry: &RefCell<ItemCacheEntry>| {
+		for (entry_id, entry) in &self.alloc {
 			let parent = entry.borrow_mut().parent;
 			if let Some(parent_id) = parent {
 				if parent_id == id {
 					self.draw_worker(transform, entry_id, resource_manager, shader_cache);
 				}
 			}
-		});
+		}
 	}
 	
 	fn draw(&self, transform: &cgmath::Matrix4<f32>, resource_manager: &mut GraphicResourceManager, shader_cache: &mut ShaderCache) {
@@ -214,10 +213,10 @@ impl ItemCache {
 		}
 	}
 	
-	fn hover_item_helper(&self, pos: Vec2f, parent_id: Option<usize>) -> Option<usize> {
+	fn hover_item_helper(&self, pos: Vec2f, parent_id: Option<AllocId>) -> Option<AllocId> {
 		let mut result_id = None;
 		
-		self.alloc.apply_to_all(&mut |id, item_cache_entry_cell: &RefCell<ItemCacheEntry>| {
+		for (id, item_cache_entry_cell) in &self.alloc {
 			let item_cache_entry = item_cache_entry_cell.borrow();
 			if item_cache_entry.parent == parent_id {
 				if let Some(found_id) = self.hover_item_helper(pos, Some(id)) {
@@ -229,12 +228,12 @@ impl ItemCache {
 					}
 				}
 			}
-		});
+		}
 		
 		result_id
 	}
 	
-	pub fn hover_item(&self, pos: Vec2f) -> Option<usize> {
+	pub fn hover_item(&self, pos: Vec2f) -> Option<AllocId> {
 		self.hover_item_helper(pos, None)
 	}
 }
@@ -596,10 +595,10 @@ pub struct DisplayCache {
 	shader_cache: ShaderCache,
 	message_sender: mpsc::Sender<item::ItemUpdateMessage>,
 	message_receiver: mpsc::Receiver<item::ItemUpdateMessage>,
-	mouse_hover_id: Option<usize>,
-	mouse_press_id: Option<usize>,
+	mouse_hover_id: Option<AllocId>,
+	mouse_press_id: Option<AllocId>,
 	pressed_mouse_buttons: MouseButtons,
-	animating_items: HashSet<usize>,
+	animating_items: HashSet<AllocId>,
 	resource_manager: GraphicResourceManager,
 }
 
@@ -634,21 +633,25 @@ impl DisplayCache {
 	pub fn process_item(&mut self,
 		item: &Item,
 		global_top_left: Vec2f,
-		parent_cache_entry: Option<usize>,
+		parent_cache_entry: Option<AllocId>,
 	) {
 		let global_item_pos = global_top_left + item.get_item().pos;
 		
-		let item_id: usize;
+		let item_id: AllocId;
 		
 		{
 			{
 				let mut item_cache_entry: RefMut<ItemCacheEntry>;
 				
 				let mut opt_internal = item.get_item().internal.borrow_mut();
-				if opt_internal.is_some() {
-					let internal = opt_internal.as_ref().unwrap();
-					item_id = internal.id;
-					item_cache_entry = self.item_cache.alloc.get_mut(internal.id).borrow_mut();
+				// An ID for an entry that has since been deallocated is treated as a cache miss.
+				let cached_entry = match *opt_internal {
+					Some(ref internal) => self.item_cache.alloc.try_get_mut(internal.id).map(|entry| (internal.id, entry)),
+					None => None,
+				};
+				if let Some((id, entry)) = cached_entry {
+					item_id = id;
+					item_cache_entry = entry.borrow_mut();
 					
 					item_cache_entry.parent = parent_cache_entry;
 					item_cache_entry.pos = item.get_item().pos;
@@ -750,8 +753,9 @@ impl DisplayCache {
 				
 				}
 				UpdateApplyHandle{id, apply_handle} => {
-					let mut item_cache_entry = self.item_cache.alloc.get_mut(id).borrow_mut();
-					item_cache_entry.apply_handle = Some(apply_handle);
+					if let Some(item_cache_entry) = self.item_cache.alloc.try_get_mut(id) {
+						item_cache_entry.borrow_mut().apply_handle = Some(apply_handle);
+					}
 				}
 				ChildrenAdded{id: _, indices: _} => {
 				
@@ -775,9 +779,10 @@ impl DisplayCache {
 	
 	pub fn send_animation_signals(&mut self, time_step: f64) {
 		for &item_id in &self.animating_items {
-			let item_cache_entry = self.item_cache.alloc.get_mut(item_id);
-			if let Some(ref apply_handle) = item_cache_entry.borrow().apply_handle {
-				apply_handle.invoke(ItemDataChange::animation_frame(JustSignalChange(time_step)));
+			if let Some(item_cache_entry) = self.item_cache.alloc.try_get(item_id) {
+				if let Some(ref apply_handle) = item_cache_entry.borrow().apply_handle {
+					apply_handle.invoke(ItemDataChange::animation_frame(JustSignalChange(time_step)));
+				}
 			}
 		}
 	}
@@ -785,15 +790,13 @@ impl DisplayCache {
 	pub fn process_mouse_pos(&mut self, pos: Vec2f) {
 		let opt_hover_id = self.item_cache.hover_item(pos);
 		if self.mouse_hover_id != opt_hover_id {
-			if let Some(mouse_hover_id) = self.mouse_hover_id {
-				let old_hover_item_cache_entry = self.item_cache.alloc.get(mouse_hover_id);
+			if let Some(old_hover_item_cache_entry) = self.mouse_hover_id.and_then(|id| self.item_cache.alloc.try_get(id)) {
 				if let Some(ref apply_handle) = old_hover_item_cache_entry.borrow().apply_handle {
 					apply_handle.invoke(ItemDataChange::mouse_data(OptionChange::Change(MouseDataChange::contains_mouse(ValueChange(false)))));
 				}
 			}
 			
-			if let Some(hover_id) = opt_hover_id {
-				let hover_item_cache_entry = self.item_cache.alloc.get(hover_id);
+			if let Some(hover_item_cache_entry) = opt_hover_id.and_then(|id| self.item_cache.alloc.try_get(id)) {
 				if let Some(ref apply_handle) = hover_item_cache_entry.borrow().apply_handle {
 					apply_handle.invoke(ItemDataChange::mouse_data(OptionChange::Change(MouseDataChange::contains_mouse(ValueChange(true)))));
 				}
@@ -810,8 +813,7 @@ impl DisplayCache {
 		
 		self.pressed_mouse_buttons.set_button(button, true);
 		
-		if let Some(mouse_press_id) = self.mouse_press_id {
-			let pressed_item_cache_entry = self.item_cache.alloc.get(mouse_press_id);
+		if let Some(pressed_item_cache_entry) = self.mouse_press_id.and_then(|id| self.item_cache.alloc.try_get(id)) {
 			if let Some(ref apply_handle) = pressed_item_cache_entry.borrow().apply_handle {
 				apply_handle.invoke(ItemDataChange::mouse_data(OptionChange::Change(MouseDataChange::pressed(ValueChange(self.pressed_mouse_buttons)))));
 			}
@@ -821,8 +823,7 @@ impl DisplayCache {
 	pub fn process_mouse_up(&mut self, button: MouseButton) {
 		self.pressed_mouse_buttons.set_button(button, false);
 		
-		if let Some(mouse_press_id) = self.mouse_press_id {
-			let pressed_item_cache_entry = self.item_cache.alloc.get(mouse_press_id);
+		if let Some(pressed_item_cache_entry) = self.mouse_press_id.and_then(|id| self.item_cache.alloc.try_get(id)) {
 			if let Some(ref apply_handle) = pressed_item_cache_entry.borrow().apply_handle {
 				apply_handle.invoke(ItemDataChange::mouse_data(OptionChange::Change(MouseDataChange::pressed(ValueChange(self.pressed_mouse_buttons)))));
 			}
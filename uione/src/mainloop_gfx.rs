@@ -76,7 +76,7 @@ pub fn exec<T, C, V>(
 where
 	T: Changeable<C> + Item + object::Object<C>,
 	C: 'static + Change + std::fmt::Debug + std::clone::Clone,
-	V: object::Validator<C>
+	V: object::Validator<T, C>
 {
 	println!("Starting event loop...");
 	let running = Arc::new(atomic::AtomicBool::new(true));
@@ -101,12 +101,9 @@ where
 	
 	let events_proxy = events_loop.create_proxy();
 	
-	let thread_running = running.clone();
 	let check_event_thread = thread::spawn(move || {
-		while thread_running.load(atomic::Ordering::Relaxed) {
-			async_change_notifier.wait();
+		while async_change_notifier.wait() {
 			events_proxy.wakeup().unwrap();
-			
 		}
 	});
 	
@@ -231,6 +228,6 @@ where
 	}
 	
 	running.store(false, atomic::Ordering::Relaxed);
-	manager.get_async_change_notifier().notify();
+	manager.shutdown();
 	check_event_thread.join().unwrap();
 }